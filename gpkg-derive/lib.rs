@@ -5,8 +5,8 @@ use quote::quote;
 use std::collections::HashMap;
 use std::ops::Deref;
 use syn::{
-    parse2, Attribute, DeriveInput, Field, GenericArgument, GenericParam, Generics, Ident, Lit,
-    LitInt, Meta, MetaNameValue, Type, TypePath, TypeReference,
+    parse2, parse_quote, Attribute, DeriveInput, Field, GenericArgument, Generics, Ident, Lit,
+    Meta, MetaNameValue, Type, TypePath, TypeReference,
 };
 
 lazy_static! {
@@ -72,10 +72,42 @@ lazy_static! {
 /// the used as the geomtry type for the layer. If the letters Z and/or M are present in the geometry type,
 /// the corresponding flags will be set within the GeoPackage indicating that the geometry has M or Z values.
 ///
+/// A field holding another struct that implements GPKGModel can be marked with `#[gpkg(flatten)]`, which stores
+/// the columns of the nested struct as columns of this layer. Adding `prefix = "..."` to the attribute
+/// prepends the prefix to the name of each nested column, so the same struct can be flattened more than once.
+/// The geometry of a flattened struct becomes the geometry of the layer, and a struct that ends up with more
+/// than one geometry column, its own or flattened, fails to compile.
+///
+/// Constraints can be added to a column with `#[gpkg(unique)]`, `#[gpkg(default = "...")]` and `#[gpkg(check = "...")]`,
+/// where the default is a SQL literal or parenthesized expression and the check is a SQL expression.
+/// A field marked with `#[gpkg(index)]` gets its own index, and indexes on several columns are declared on
+/// the struct with `#[gpkg(index = "column_a, column_b")]` or `#[gpkg(unique_index = "...")]`.
+/// Indexes are created in the same transaction as the layer. Check expressions of flattened fields are not
+/// rewritten when a prefix is used.
+///
+/// The identifier and description of the layer in gpkg_contents can be set with `#[gpkg(identifier = "...")]` and
+/// `#[gpkg(description = "...")]` on the struct. The identifier defaults to the layer name, and the description
+/// defaults to the doc comment on the struct.
+///
+/// Adding `#[gpkg(spatial_index)]` to the struct creates an RTree spatial index on the geometry column
+/// along with the layer.
+///
 /// When this macro is used, an "fid" primary key column will be created in order to comply with the specifcation,
 /// but will be transparent to you as a user of this crate
 ///
+/// Fields can be any of the supported SQLite types, a geometry type marked with the geom_field attribute,
+/// or an enum that derives GPKGEnum.
+///
+/// Structs can have lifetime and type parameters. A geometry field whose type is a type parameter is marked with
+/// `#[geom_field]` without a geometry type, and the type and Z/M flags come from the `GPKGGeometryType` impl
+/// of the parameter, so one struct can be used for layers of different geometry types.
+/// Fields of type `Cow<str>` or `Cow<[u8]>` are written without copying and read as owned values.
+/// `&str` and `&[u8]` fields can be written, but reading a struct with one returns an error since the
+/// value can't outlive the row it comes from.
+///
 /// When using this macro for reading an existing GeoPackage layer, any unspecified columns will not be read.
+/// Layers created by other tools can be read with the `*_checked` methods on `GeoPackage`, which
+/// check the layer schema against the struct first and then map columns by name instead of position.
 /// # Usage
 /// ```ignore
 /// # // would be great to get this test working, but I'm not sure how to do it without curculare dependency issues
@@ -99,12 +131,131 @@ lazy_static! {
 ///     #[geom_field("PointZ")]
 ///     shape: GPKGPointZ,
 /// }
-#[proc_macro_derive(GPKGModel, attributes(layer_name, geom_field))]
+#[proc_macro_derive(GPKGModel, attributes(layer_name, geom_field, gpkg))]
 pub fn derive_gpkg(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     let inner_input = proc_macro2::TokenStream::from(input);
     proc_macro::TokenStream::from(derive_gpkg_inner(inner_input))
 }
 
+/// A macro for deriving an implementation of GPKGEnum for a unit-only enum, allowing it to be used as a field in a GPKGModel
+///
+/// By default each variant is stored as its name in a TEXT column. Adding `#[gpkg(storage = "integer")]` to the enum
+/// stores the discriminant of each variant in an INTEGER column instead.
+///
+/// The constraint name used when registering the enum as a data column constraint defaults to the name of the enum in
+/// snake case, and can be set with `#[gpkg(constraint_name = "...")]`.
+/// # Usage
+/// ```ignore
+/// # use gpkg::{GPKGEnum, GPKGModel};
+/// #[derive(GPKGEnum)]
+/// enum Surface {
+///     Paved,
+///     Gravel,
+/// }
+///
+/// #[derive(GPKGEnum)]
+/// #[gpkg(storage = "integer")]
+/// enum RoadClass {
+///     Highway = 1,
+///     Arterial = 2,
+///     Local = 3,
+/// }
+///
+/// #[derive(GPKGModel)]
+/// struct Road {
+///     surface: Surface,
+///     class: Option<RoadClass>,
+/// }
+/// ```
+#[proc_macro_derive(GPKGEnum, attributes(gpkg))]
+pub fn derive_gpkg_enum(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let inner_input = proc_macro2::TokenStream::from(input);
+    proc_macro::TokenStream::from(derive_gpkg_enum_inner(inner_input))
+}
+
+fn derive_gpkg_enum_inner(input: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+    let ast = parse2::<DeriveInput>(input).unwrap();
+    let name = &ast.ident;
+
+    let variants: Vec<&Ident> = match &ast.data {
+        syn::Data::Enum(data) => data
+            .variants
+            .iter()
+            .map(|v| match v.fields {
+                syn::Fields::Unit => &v.ident,
+                _ => panic!("GPKGEnum derive expected an enum with only unit variants"),
+            })
+            .collect(),
+        _ => panic!("GPKGEnum derive expected an enum"),
+    };
+    let variant_names: Vec<String> = variants.iter().map(|v| v.to_string()).collect();
+
+    let constraint_name = get_gpkg_str_arg(&ast.attrs, "constraint_name")
+        .unwrap_or_else(|| to_snake_case(&name.to_string()));
+
+    let storage = get_gpkg_str_arg(&ast.attrs, "storage").unwrap_or_else(|| String::from("text"));
+    let (column_type, values, to_sql, from_sql) = match storage.to_lowercase().as_str() {
+        "text" => (
+            quote!(gpkg::ColumnType::Text),
+            quote!(vec![#((String::from(#variant_names), #variant_names)),*]),
+            quote!(match self {
+                #(#name::#variants => #variant_names,)*
+            }),
+            quote!(match value.as_str()? {
+                #(#variant_names => Ok(#name::#variants),)*
+                other => Err(rusqlite::types::FromSqlError::Other(Box::new(
+                    gpkg::Error::InvalidEnumValue(other.to_owned()),
+                ))),
+            }),
+        ),
+        "integer" => (
+            quote!(gpkg::ColumnType::Integer),
+            quote!(vec![#(((#name::#variants as i64).to_string(), #variant_names)),*]),
+            quote!(match self {
+                #(#name::#variants => #name::#variants as i64,)*
+            }),
+            quote!({
+                let int_value = value.as_i64()?;
+                #(
+                    if int_value == #name::#variants as i64 {
+                        return Ok(#name::#variants);
+                    }
+                )*
+                Err(rusqlite::types::FromSqlError::OutOfRange(int_value))
+            }),
+        ),
+        _ => panic!("GPKGEnum storage must be either \"text\" or \"integer\""),
+    };
+
+    quote!(
+        impl gpkg::GPKGEnum for #name {
+            const COLUMN_TYPE: gpkg::ColumnType = #column_type;
+
+            const CONSTRAINT_NAME: &'static str = #constraint_name;
+
+            fn variants() -> Vec<(String, &'static str)> {
+                #values
+            }
+        }
+
+        impl rusqlite::ToSql for #name {
+            #[inline]
+            fn to_sql(&self) -> rusqlite::Result<rusqlite::types::ToSqlOutput<'_>> {
+                Ok(rusqlite::types::ToSqlOutput::from(#to_sql))
+            }
+        }
+
+        impl rusqlite::types::FromSql for #name {
+            #[inline]
+            fn column_result(
+                value: rusqlite::types::ValueRef<'_>,
+            ) -> rusqlite::types::FromSqlResult<Self> {
+                #from_sql
+            }
+        }
+    )
+}
+
 fn derive_gpkg_inner(input: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
     let ast = parse2::<DeriveInput>(input).unwrap();
 
@@ -128,21 +279,109 @@ fn derive_gpkg_inner(input: proc_macro2::TokenStream) -> proc_macro2::TokenStrea
     }
     .collect();
 
-    impl_model(&name.clone(), &fields, tbl_name, &ast.generics)
+    impl_model(&name.clone(), &fields, tbl_name, &ast.generics, &ast.attrs)
 }
 
-fn get_meta_attr<'a>(attrs: &Vec<Attribute>, name: &'a str) -> Option<Meta> {
+fn get_meta_attr(attrs: &[Attribute], name: &str) -> Option<Meta> {
     let mut temp = attrs
         .iter()
         .filter_map(|attr| attr.parse_meta().ok())
         .filter(|i| match i.path().get_ident() {
-            Some(i) => i.to_string() == name.to_owned(),
+            Some(i) => *i == name,
             None => false,
         })
         .collect::<Vec<Meta>>();
     temp.pop()
 }
 
+// the string value of a `key = "value"` argument in a #[gpkg(...)] attribute
+fn get_gpkg_str_arg(attrs: &[Attribute], key: &str) -> Option<String> {
+    get_gpkg_str_args(attrs, key).into_iter().next()
+}
+
+// the string values of every `key = "value"` argument with the given key
+fn get_gpkg_str_args(attrs: &[Attribute], key: &str) -> Vec<String> {
+    get_gpkg_args(attrs)
+        .into_iter()
+        .filter_map(|nested| match nested {
+            syn::NestedMeta::Meta(Meta::NameValue(MetaNameValue {
+                path,
+                lit: Lit::Str(ls),
+                ..
+            })) if path.is_ident(key) => Some(ls.value()),
+            _ => None,
+        })
+        .collect()
+}
+
+fn option_str_tokens(value: &Option<String>) -> TokenStream {
+    match value {
+        Some(v) => quote!(Some(#v)),
+        None => quote!(None),
+    }
+}
+
+// the lines of any /// comments, joined together
+fn get_doc_comment(attrs: &[Attribute]) -> Option<String> {
+    let lines: Vec<String> = attrs
+        .iter()
+        .filter(|attr| attr.path.is_ident("doc"))
+        .filter_map(|attr| match attr.parse_meta() {
+            Ok(Meta::NameValue(MetaNameValue {
+                lit: Lit::Str(ls), ..
+            })) => Some(ls.value().trim().to_owned()),
+            _ => None,
+        })
+        .collect();
+    let doc = lines.join("\n").trim().to_owned();
+    if doc.is_empty() {
+        None
+    } else {
+        Some(doc)
+    }
+}
+
+fn option_string_tokens(value: &Option<String>) -> TokenStream {
+    match value {
+        Some(v) => quote!(Some(String::from(#v))),
+        None => quote!(None),
+    }
+}
+
+// whether a bare flag like #[gpkg(flatten)] is present
+fn has_gpkg_flag(attrs: &[Attribute], flag: &str) -> bool {
+    get_gpkg_args(attrs).into_iter().any(
+        |nested| matches!(nested, syn::NestedMeta::Meta(Meta::Path(path)) if path.is_ident(flag)),
+    )
+}
+
+// all of the arguments given in #[gpkg(...)] attributes
+fn get_gpkg_args(attrs: &[Attribute]) -> Vec<syn::NestedMeta> {
+    attrs
+        .iter()
+        .filter(|attr| attr.path.is_ident("gpkg"))
+        .flat_map(|attr| match attr.parse_meta() {
+            Ok(Meta::List(l)) => l.nested.into_iter(),
+            _ => panic!("Expected the gpkg attribute to be a list, i.e. #[gpkg(...)]"),
+        })
+        .collect()
+}
+
+fn to_snake_case(name: &str) -> String {
+    let mut out = String::new();
+    for (i, c) in name.chars().enumerate() {
+        if c.is_uppercase() {
+            if i > 0 {
+                out.push('_');
+            }
+            out.extend(c.to_lowercase());
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
 #[derive(Debug, Clone, Copy)]
 enum MZOptions {
     Prohibited = 0,
@@ -152,46 +391,86 @@ enum MZOptions {
 
 #[derive(Debug, Clone)]
 struct GeomInfo {
-    geom_type: String,
+    // None when the type and flags come from the GPKGGeometryType impl of the field's type
+    declared: Option<DeclaredGeomType>,
     // this is mostly for future proofing, we'll default to wgs84 for now
     srs_id: i64,
+}
+
+#[derive(Debug, Clone)]
+struct DeclaredGeomType {
+    geom_type: String,
     m: MZOptions,
     z: MZOptions,
 }
 
+// how the value of a field is read from a row
+#[derive(Debug)]
+enum FieldRead {
+    // the field type implements FromSql
+    Value,
+    // a Cow, which is read as the given owned type
+    Cow(TokenStream),
+    // a reference, which can't outlive the row it would be read from
+    Borrowed,
+}
+
+enum FieldKind {
+    Column(FieldInfo),
+    Flatten(FlattenInfo),
+}
+
+// a field holding another GPKGModel, whose columns are inlined into this one
+struct FlattenInfo {
+    name: String,
+    ty: Type,
+    prefix: String,
+}
+
 #[derive(Debug)]
 struct FieldInfo {
     name: String,
+    ty: Type,
+    read: FieldRead,
     geom_info: Option<GeomInfo>,
     optional: bool,
-    type_for_sql: String,
+    // an expression evaluating to the gpkg::ColumnType of the field
+    column_type: TokenStream,
+    unique: bool,
+    default: Option<String>,
+    check: Option<String>,
+    index: bool,
 }
 
 // only going to support &str and &[u8] for now
 fn get_reference_type_name(t: &TypeReference) -> String {
-    match t.elem.deref() {
-        syn::Type::Path(p) => {
-            assert!(p.path.segments.len() == 1);
+    get_borrowed_type_name(&t.elem).expect("The only reference types supported are &str and &[u8]")
+}
+
+// the type name of str or [u8], the types that can be borrowed by a reference or a Cow
+fn get_borrowed_type_name(t: &Type) -> Option<String> {
+    match t {
+        syn::Type::Path(p) if p.path.segments.len() == 1 => {
             match get_path_type_name(p).0.as_str() {
-                "str" => return String::from("str"),
-                _ => panic!("The only reference types supported are &str and &[u8]"),
+                "str" => Some(String::from("str")),
+                _ => None,
             }
         }
         syn::Type::Slice(s) => match s.elem.deref() {
             Type::Path(p) => match get_path_type_name(p).0.as_str() {
-                "u8" => return String::from("buf"),
-                _ => panic!("The only reference types supported are &str and &[u8]"),
+                "u8" => Some(String::from("buf")),
+                _ => None,
             },
-            _ => panic!("The only reference types supported are &str and &[u8]"),
+            _ => None,
         },
-        _ => panic!("The only reference types supported are &str and &[u8]"),
-    };
+        _ => None,
+    }
 }
 
 // return the field name and whether or not it's optional
 fn get_path_type_name(p: &TypePath) -> (String, bool) {
     let mut optional = false;
-    assert!(p.path.segments.len() > 0);
+    assert!(!p.path.segments.is_empty());
     let final_segment = p.path.segments.last().unwrap();
     let id_string = final_segment.ident.to_string();
     match id_string.as_str() {
@@ -215,6 +494,18 @@ fn get_path_type_name(p: &TypePath) -> (String, bool) {
                 panic!("Unsupported use of the option type");
             }
         }
+        "Cow" => {
+            if let syn::PathArguments::AngleBracketed(a) = &final_segment.arguments {
+                for arg in &a.args {
+                    if let GenericArgument::Type(t) = arg {
+                        let type_name = get_borrowed_type_name(t)
+                            .expect("The only Cow types supported are Cow<str> and Cow<[u8]>");
+                        return (type_name, optional);
+                    }
+                }
+            }
+            panic!("The only Cow types supported are Cow<str> and Cow<[u8]>");
+        }
         "Vec" => {
             if let syn::PathArguments::AngleBracketed(a) = &final_segment.arguments {
                 assert!(a.args.len() == 1, "Only one argument allowed in a Vec");
@@ -245,37 +536,36 @@ fn impl_model(
     fields: &Vec<&Field>,
     tbl_name: Option<String>,
     generics: &Generics,
+    attrs: &[Attribute],
 ) -> TokenStream {
-    // overwrite the struct name with a provided table name if one is given
-    // TODO: add some level of validation here based on sqlite's rules
-    let layer_name_final = match tbl_name {
-        Some(n) => Ident::new(&n, name.span()),
-        None => name.to_owned(),
-    };
+    // overwrite the struct name with a provided table name if one is given,
+    // the name is quoted whenever it's used in sql so it doesn't need to be a valid identifier
+    let layer_name_final = tbl_name.unwrap_or_else(|| name.to_string());
 
-    let geom_field_name: String;
-
-    // need to get this in order to make liftimes on the Impl work correctly
-    let mut final_generics = generics.clone();
-    if let Some(g) = final_generics.params.first_mut() {
-        match g {
-            GenericParam::Lifetime(l) => match l.lifetime.ident.to_string().as_str() {
-                "static" | "_" => {}
-                _ => l.lifetime.ident = Ident::new("_", Span::call_site()),
-            },
-            _ => {}
-        }
-    }
+    // fields whose types use a type parameter need bounds so the generated impl can read and write them
+    let type_params: Vec<&Ident> = generics.type_params().map(|t| &t.ident).collect();
+    let mut bounds: Vec<syn::WherePredicate> = Vec::new();
 
     // the goal is to support everything here (https://www.geopackage.org/spec130/index.html#table_column_data_types)
     // as well as allow the user change whether a field can have nulls or not with the option type
-    let field_infos: Vec<FieldInfo> = fields
+    let field_kinds: Vec<FieldKind> = fields
         .iter()
         .map(|f| {
-            let mut optional = false;
             let field_name = f.ident.as_ref().expect("Expected named field").to_string();
+            if has_gpkg_flag(&f.attrs, "flatten") {
+                let ty = &f.ty;
+                if mentions_type_param(ty, &type_params) {
+                    bounds.push(parse_quote!(#ty: for<'gpkg> gpkg::GPKGModel<'gpkg>));
+                }
+                return FieldKind::Flatten(FlattenInfo {
+                    name: field_name,
+                    ty: f.ty.clone(),
+                    prefix: get_gpkg_str_arg(&f.attrs, "prefix").unwrap_or_default(),
+                });
+            }
+            let mut optional = false;
             let type_name: String;
-            let geom_info = get_geom_field_info(&f);
+            let geom_info = get_geom_field_info(f);
             match &f.ty {
                 syn::Type::Reference(r) => {
                     type_name = get_reference_type_name(r);
@@ -285,185 +575,457 @@ fn impl_model(
                 }
                 _ => panic!("Don't know how to map to GPKG type {:?}", f.ty),
             }
-            let sql_type = match type_name.as_str() {
-                "bool" => quote!(INTEGER),
-                "String" | "str" => quote!(TEXT),
-                "i64" | "i32" | "i16" | "i8" => quote!(INTEGER),
-                "f64" | "f32" => quote!(REAL),
-                "buf" => quote!(BLOB),
+            let ty = &f.ty;
+            let inner = get_inner_type(ty);
+            let generic = mentions_type_param(ty, &type_params);
+            let read = match inner {
+                Type::Reference(_) => FieldRead::Borrowed,
+                Type::Path(tp) if tp.path.segments.last().is_some_and(|s| s.ident == "Cow") => {
+                    match type_name.as_str() {
+                        "str" => FieldRead::Cow(quote!(String)),
+                        _ => FieldRead::Cow(quote!(Vec<u8>)),
+                    }
+                }
+                _ => FieldRead::Value,
+            };
+            if generic {
+                match read {
+                    FieldRead::Value => {
+                        bounds.push(parse_quote!(#ty: rusqlite::ToSql + rusqlite::types::FromSql))
+                    }
+                    _ => bounds.push(parse_quote!(#ty: rusqlite::ToSql)),
+                }
+            }
+            let column_type = match type_name.as_str() {
+                "bool" => quote!(gpkg::ColumnType::Integer),
+                "String" | "str" => quote!(gpkg::ColumnType::Text),
+                "i64" | "i32" | "i16" | "i8" => quote!(gpkg::ColumnType::Integer),
+                "f64" | "f32" => quote!(gpkg::ColumnType::Real),
+                "buf" => quote!(gpkg::ColumnType::Blob),
                 "u128" | "u64" | "u32" | "u16" | "u8" => {
                     panic!("SQLite doesn't support unsigned integers, use a signed integer value")
                 }
                 // all geometry types are a blob inside sqlite
-                _ if geom_info.is_some() => quote!(BLOB),
-                _ => panic!("Don't know how to map to SQL type {}", type_name),
+                _ if geom_info.is_some() => {
+                    let from_type = geom_info.as_ref().is_some_and(|g| g.declared.is_none());
+                    if from_type && generic {
+                        bounds.push(parse_quote!(#inner: gpkg::GPKGGeometryType));
+                    }
+                    quote!(gpkg::ColumnType::Blob)
+                }
+                // anything else has to be an enum deriving GPKGEnum, which knows how it's stored
+                _ => {
+                    if generic {
+                        bounds.push(parse_quote!(#inner: gpkg::GPKGEnum));
+                    }
+                    quote!(<#inner as gpkg::GPKGEnum>::COLUMN_TYPE)
+                }
             };
-            FieldInfo {
+            FieldKind::Column(FieldInfo {
                 name: field_name,
+                ty: ty.clone(),
+                read,
                 optional,
                 geom_info,
-                type_for_sql: sql_type.to_string(),
-            }
+                column_type,
+                unique: has_gpkg_flag(&f.attrs, "unique"),
+                default: get_gpkg_str_arg(&f.attrs, "default"),
+                check: get_gpkg_str_arg(&f.attrs, "check"),
+                index: has_gpkg_flag(&f.attrs, "index"),
+            })
         })
         .collect();
-    let geom_fields: Vec<&FieldInfo> = field_infos
+    let geom_fields: Vec<&FieldInfo> = field_kinds
         .iter()
-        .filter(|f| f.geom_info.is_some())
+        .filter_map(|f| match f {
+            FieldKind::Column(info) if info.geom_info.is_some() => Some(info),
+            _ => None,
+        })
         .collect();
     assert!(
         geom_fields.len() <= 1,
         "Found {} geometry fields, 1 is the maximum allowed amount",
         geom_fields.len()
     );
-    let mut geom_column_sql: Option<String> = None;
-    let mut contents_sql = format!(
-        r#"INSERT INTO gpkg_contents (table_name, data_type) VALUES ("{}", "{}");"#,
-        layer_name_final, "attributes"
-    );
 
-    if geom_fields.len() > 0 {
-        let geom_field = geom_fields[0];
-        let geom_info = geom_field.geom_info.clone().unwrap();
-        let geom_type_sql = geom_info.geom_type.clone();
-        geom_field_name = geom_field.name.clone();
-        geom_column_sql = Some(format!(
-            r#"INSERT INTO gpkg_geometry_columns VALUES("{}", "{}", "{}", {}, {}, {});"#,
-            layer_name_final,
-            geom_field_name,
-            geom_type_sql.to_uppercase(),
-            geom_info.srs_id,
-            geom_info.m as i32,
-            geom_info.z as i32
-        ));
-        contents_sql = format!(
-            r#"INSERT INTO gpkg_contents (table_name, data_type, srs_id) VALUES ("{}", "{}", {});"#,
-            layer_name_final, "features", geom_info.srs_id
-        );
-    };
-    let contents_ts: TokenStream = contents_sql
-        .parse()
-        .expect("Unable to convert contents table insert statement into token stream");
-    let geom_column_ts: TokenStream = match geom_column_sql {
-        Some(s) => s
-            .parse()
-            .expect("Unable to convert contents table insert statement into token stream"),
-        None => TokenStream::new(),
-    };
+    let mut column_definitions = Vec::new();
+    let mut column_counts = Vec::new();
+    let mut row_offset_gets = Vec::new();
+    let mut row_prefixed_gets = Vec::new();
+    let mut param_pushes = Vec::new();
+    let mut flattened_geometries = Vec::new();
+    let mut flattened_geometry_counts = Vec::new();
+    let mut index_definitions = Vec::new();
+    for kind in &field_kinds {
+        match kind {
+            FieldKind::Column(f) => {
+                let field_ident = Ident::new(f.name.as_str(), Span::call_site());
+                let col_name = f.name.as_str();
+                let col_type = &f.column_type;
+                let not_null = !f.optional;
+                let unique = f.unique;
+                let default = option_string_tokens(&f.default);
+                let check = option_string_tokens(&f.check);
+                column_definitions.push(quote!(
+                    columns.push(gpkg::ColumnDefinition {
+                        name: String::from(#col_name),
+                        column_type: #col_type,
+                        not_null: #not_null,
+                        unique: #unique,
+                        default: #default,
+                        check: #check,
+                    });
+                ));
+                if f.index {
+                    index_definitions.push(quote!(
+                        indexes.push(gpkg::IndexDefinition {
+                            columns: vec![String::from(#col_name)],
+                            unique: false,
+                        });
+                    ));
+                }
+                column_counts.push(quote!(1));
+                let column_name = quote!(&*if prefix.is_empty() {
+                    std::borrow::Cow::Borrowed(#col_name)
+                } else {
+                    std::borrow::Cow::Owned(format!("{}{}", prefix, #col_name))
+                });
+                match &f.read {
+                    FieldRead::Value => {
+                        row_offset_gets.push(quote!(
+                            #field_ident: {
+                                let value = row.get(index)?;
+                                index += 1;
+                                value
+                            }
+                        ));
+                        row_prefixed_gets.push(quote!(
+                            #field_ident: row.get(#column_name)?
+                        ));
+                    }
+                    // the value is read as the owned type, so the struct doesn't borrow from the row
+                    FieldRead::Cow(owned) => {
+                        let convert = match f.optional {
+                            true => quote!(.map(std::borrow::Cow::Owned)),
+                            false => quote!(.into()),
+                        };
+                        let owned_ty = match f.optional {
+                            true => quote!(Option<#owned>),
+                            false => quote!(#owned),
+                        };
+                        row_offset_gets.push(quote!(
+                            #field_ident: {
+                                let value = row.get::<_, #owned_ty>(index)?#convert;
+                                index += 1;
+                                value
+                            }
+                        ));
+                        row_prefixed_gets.push(quote!(
+                            #field_ident: row.get::<_, #owned_ty>(#column_name)?#convert
+                        ));
+                    }
+                    FieldRead::Borrowed => {
+                        let error = quote!(rusqlite::Error::FromSqlConversionFailure(
+                            0,
+                            rusqlite::types::Type::Null,
+                            Box::new(gpkg::Error::BorrowedField(String::from(#col_name))),
+                        ));
+                        row_offset_gets.push(quote!(#field_ident: return Err(#error)));
+                        row_prefixed_gets.push(quote!(#field_ident: return Err(#error)));
+                    }
+                }
+                param_pushes.push(quote!(
+                    params.push(&self.#field_ident as &dyn rusqlite::ToSql);
+                ));
+            }
+            FieldKind::Flatten(f) => {
+                let field_ident = Ident::new(f.name.as_str(), Span::call_site());
+                let ty = &f.ty;
+                let prefix = f.prefix.as_str();
+                column_definitions.push(quote!(
+                    columns.extend(
+                        <#ty as gpkg::GPKGModel>::get_columns()
+                            .into_iter()
+                            .map(|mut c| {
+                                c.name = format!("{}{}", #prefix, c.name);
+                                c
+                            }),
+                    );
+                ));
+                column_counts.push(quote!(<#ty as gpkg::GPKGModel>::get_column_count()));
+                row_offset_gets.push(quote!(
+                    #field_ident: {
+                        let value = <#ty as gpkg::GPKGModel>::from_row_offset(row, index)?;
+                        index += <#ty as gpkg::GPKGModel>::get_column_count();
+                        value
+                    }
+                ));
+                row_prefixed_gets.push(quote!(
+                    #field_ident: <#ty as gpkg::GPKGModel>::from_row_prefixed(
+                        row,
+                        &format!("{}{}", prefix, #prefix),
+                    )?
+                ));
+                param_pushes.push(quote!(
+                    params.extend(self.#field_ident.as_params());
+                ));
+                index_definitions.push(quote!(
+                    indexes.extend(
+                        <#ty as gpkg::GPKGModel>::get_indexes()
+                            .into_iter()
+                            .map(|mut i| {
+                                i.columns = i
+                                    .columns
+                                    .into_iter()
+                                    .map(|c| format!("{}{}", #prefix, c))
+                                    .collect();
+                                i
+                            }),
+                    );
+                ));
+                flattened_geometry_counts.push(quote!(<#ty as gpkg::GPKGModel>::GEOMETRY_COLUMNS));
+                flattened_geometries.push(quote!(
+                    if let Some(mut g) = <#ty as gpkg::GPKGModel>::get_geometry_column() {
+                        g.name = format!("{}{}", #prefix, g.name);
+                        return Some(g);
+                    }
+                ));
+            }
+        }
+    }
 
-    let column_defs = field_infos
-        .iter()
-        .map(|f| {
-            let null_str = if f.optional { "" } else { " NOT NULL" };
-            format!("{} {}{}", f.name, f.type_for_sql, null_str)
-                .parse()
-                .unwrap()
-        })
-        .collect::<Vec<TokenStream>>();
+    // multi column indexes are declared on the struct, i.e. #[gpkg(index = "a, b")]
+    for (key, unique) in [("index", false), ("unique_index", true)] {
+        for columns in get_gpkg_str_args(attrs, key) {
+            let column_strs: Vec<String> = columns
+                .split(',')
+                .map(|c| c.trim().to_owned())
+                .filter(|c| !c.is_empty())
+                .collect();
+            assert!(
+                !column_strs.is_empty(),
+                "The {} attribute needs at least one column",
+                key
+            );
+            index_definitions.push(quote!(
+                indexes.push(gpkg::IndexDefinition {
+                    columns: vec![#(String::from(#column_strs)),*],
+                    unique: #unique,
+                });
+            ));
+        }
+    }
 
-    let column_names: Vec<Ident> = field_infos
-        .iter()
-        .map(|i| Ident::new(i.name.as_str(), Span::call_site()))
-        .collect();
+    // the description falls back to the doc comment on the struct
+    let identifier = option_str_tokens(&get_gpkg_str_arg(attrs, "identifier"));
+    let description = option_str_tokens(
+        &get_gpkg_str_arg(attrs, "description").or_else(|| get_doc_comment(attrs)),
+    );
+    let spatial_index = has_gpkg_flag(attrs, "spatial_index");
+
+    let geometry_column_definition = match geom_fields.first() {
+        Some(f) => {
+            let geom_info = f.geom_info.as_ref().unwrap();
+            let col_name = f.name.as_str();
+            let srs_id = geom_info.srs_id;
+            let (geom_type, z, m) = match &geom_info.declared {
+                Some(declared) => {
+                    let geom_type = declared.geom_type.as_str();
+                    let z = Ident::new(&format!("{:?}", declared.z), Span::call_site());
+                    let m = Ident::new(&format!("{:?}", declared.m), Span::call_site());
+                    (
+                        quote!(#geom_type),
+                        quote!(gpkg::MZOption::#z),
+                        quote!(gpkg::MZOption::#m),
+                    )
+                }
+                None => {
+                    let inner = get_inner_type(&f.ty);
+                    (
+                        quote!(<#inner as gpkg::GPKGGeometryType>::GEOMETRY_TYPE),
+                        quote!(<#inner as gpkg::GPKGGeometryType>::Z),
+                        quote!(<#inner as gpkg::GPKGGeometryType>::M),
+                    )
+                }
+            };
+            quote!(Some(gpkg::GeometryColumnDefinition {
+                name: String::from(#col_name),
+                geometry_type: String::from(#geom_type),
+                srs_id: #srs_id,
+                z: #z,
+                m: #m,
+            }))
+        }
+        // a geometry column can come from a flattened struct instead
+        None => quote!(
+            #(#flattened_geometries)*
+            None
+        ),
+    };
 
-    let params = vec![quote!(?); column_names.len()];
+    // a layer has at most one geometry column, which can only be checked once the flattened types are known,
+    // so the count is a constant that fails to evaluate when there are more
+    let own_geometry_count = geom_fields.len();
+    let too_many_geometries = format!(
+        "{} has more than one geometry column, including the ones of flattened fields, but a layer can only have one",
+        name
+    );
+    // the constant of a struct without type parameters is evaluated where it's declared,
+    // otherwise it's evaluated when the model is used
+    let check_geometry_count =
+        if generics.type_params().next().is_none() && generics.const_params().next().is_none() {
+            let lifetimes = generics.lifetimes().map(|_| quote!('static));
+            quote!(
+                const _: usize = <#name<#(#lifetimes),*> as gpkg::GPKGModel>::GEOMETRY_COLUMNS;
+            )
+        } else {
+            quote!()
+        };
 
-    let column_nums = (0..column_defs.len())
-        .map(|i| LitInt::new(i.to_string().as_str(), Span::call_site()))
-        .collect::<Vec<LitInt>>();
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    let mut where_clause = where_clause.cloned().unwrap_or_else(|| parse_quote!(where));
+    where_clause.predicates.extend(bounds);
 
-    // need to add some generic support like in here: https://github.com/diesel-rs/diesel/blob/master/diesel_derives/src/insertable.rs#L88
-    // this is so that lifetimes will work
     let new = quote!(
-        impl GPKGModel <'_> for #name #final_generics {
+        impl #impl_generics gpkg::GPKGModel<'_> for #name #ty_generics #where_clause {
             #[inline]
             fn get_gpkg_layer_name() -> &'static str {
-                std::stringify!(#layer_name_final)
+                #layer_name_final
             }
 
             #[inline]
-            fn get_create_sql() -> &'static str {
-                std::stringify!(
-                    BEGIN;
-                    CREATE TABLE #layer_name_final (
-                        fid INTEGER PRIMARY KEY,
-                        #(#column_defs ),*
-                    );
-                    #geom_column_ts
-                    #contents_ts
-                    COMMIT;
-                )
+            fn get_identifier() -> Option<&'static str> {
+                #identifier
             }
 
             #[inline]
-            fn get_insert_sql() -> &'static str {
-                std::stringify!(
-                    INSERT INTO #layer_name_final (
-                        #(#column_names),*
-                    ) VALUES (
-                        #(#params),*
-                    )
-                )
+            fn get_description() -> Option<&'static str> {
+                #description
             }
 
-            #[inline]
-            fn get_select_sql() -> &'static str {
-                std::stringify!(
-                    SELECT #(#column_names),* FROM #layer_name_final;
-                )
+            #[allow(unused_assignments, unreachable_code)]
+            fn from_row_offset(row: &rusqlite::Row, offset: usize) -> rusqlite::Result<Self> {
+                let mut index = offset;
+                Ok(Self {
+                    #(#row_offset_gets,)*
+                })
+            }
+
+            #[allow(unreachable_code)]
+            fn from_row_prefixed(row: &rusqlite::Row, prefix: &str) -> rusqlite::Result<Self> {
+                Ok(Self {
+                    #(#row_prefixed_gets,)*
+                })
+            }
+
+            fn get_columns() -> Vec<gpkg::ColumnDefinition> {
+                let mut columns = Vec::new();
+                #(#column_definitions)*
+                columns
             }
 
             #[inline]
-            fn get_select_where(predicate: &str) -> String {
-                (std::stringify!(
-                    SELECT #(#column_names),* FROM #layer_name_final WHERE
-                ).to_owned() + " " + predicate + ";")
+            fn get_column_count() -> usize {
+                0 #(+ #column_counts)*
             }
 
-            fn from_row(row: &rusqlite::Row) -> rusqlite::Result<Self> {
-                Ok(Self {
-                    #(#column_names: row.get((#column_nums))?,)*
-                })
+            const GEOMETRY_COLUMNS: usize = {
+                let count = #own_geometry_count #(+ #flattened_geometry_counts)*;
+                assert!(count <= 1, #too_many_geometries);
+                count
+            };
+
+            fn get_geometry_column() -> Option<gpkg::GeometryColumnDefinition> {
+                let _ = Self::GEOMETRY_COLUMNS;
+                #geometry_column_definition
+            }
+
+            fn get_indexes() -> Vec<gpkg::IndexDefinition> {
+                let mut indexes = Vec::new();
+                #(#index_definitions)*
+                indexes
+            }
+
+            #[inline]
+            fn has_spatial_index() -> bool {
+                #spatial_index
             }
 
             fn as_params(&self) -> Vec<&(dyn rusqlite::ToSql + '_)> {
-                vec![
-                    #(&self.#column_names as &dyn rusqlite::ToSql),*
-                ]
+                let mut params: Vec<&(dyn rusqlite::ToSql + '_)> = Vec::new();
+                #(#param_pushes)*
+                params
+            }
+        }
+
+        impl #impl_generics gpkg::GPKGRecord for #name #ty_generics #where_clause {
+            type Model = Self;
+
+            #[inline]
+            fn as_model(&self) -> &Self {
+                self
             }
         }
+
+        #check_geometry_count
     );
     new
 }
 
-fn get_geom_field_info(field: &Field) -> Option<GeomInfo> {
-    for attr in &field.attrs {
-        if let Some(ident) = attr.path.get_ident() {
-            if ident.to_string() == "geom_field" {
-                let geom_type_name =
-                    get_meta_attr(&field.attrs, "geom_field").and_then(|meta| match meta {
-                        Meta::List(l) => l.nested.first().and_then(|n| match n {
-                            syn::NestedMeta::Lit(Lit::Str(ls)) => Some(ls.value()),
-                            _ => panic!("You must specify a geometry type when using the geom_field attribute"),
-                        }),
-                        _ => panic!("You must specify a geometry type when using the geom_field attribute"),
-                    });
-                if let Some(name) = geom_type_name {
-                    let upper_name = name.to_uppercase();
-                    if let Some((m, z)) = GEO_TYPES.get((&upper_name).as_str()) {
-                        return Some(GeomInfo {
-                            geom_type: upper_name,
-                            srs_id: 4326,
-                            m: *m,
-                            z: *z,
-                        });
-                    } else {
-                        panic!("{} is not a supported geometry type", name);
+// the type inside of an Option, or the type itself if it isn't one
+fn get_inner_type(ty: &Type) -> &Type {
+    if let Type::Path(tp) = ty {
+        if let Some(segment) = tp.path.segments.last() {
+            if segment.ident == "Option" {
+                if let syn::PathArguments::AngleBracketed(a) = &segment.arguments {
+                    if let Some(GenericArgument::Type(t)) = a.args.first() {
+                        return t;
                     }
                 }
             }
         }
     }
-    None
+    ty
+}
+
+fn get_geom_field_info(field: &Field) -> Option<GeomInfo> {
+    let meta = get_meta_attr(&field.attrs, "geom_field")?;
+    let geom_type_name = match meta {
+        // without a type name, it comes from the type of the field, i.e. a generic parameter
+        Meta::Path(_) => None,
+        Meta::List(l) => l.nested.first().map(|n| match n {
+            syn::NestedMeta::Lit(Lit::Str(ls)) => ls.value(),
+            _ => panic!("The geometry type in the geom_field attribute must be a string"),
+        }),
+        _ => panic!("Expected the geom_field attribute to be either #[geom_field] or #[geom_field(\"Type\")]"),
+    };
+    let declared = geom_type_name.map(|name| {
+        let upper_name = name.to_uppercase();
+        match GEO_TYPES.get(upper_name.as_str()) {
+            Some((m, z)) => DeclaredGeomType {
+                geom_type: upper_name,
+                m: *m,
+                z: *z,
+            },
+            None => panic!("{} is not a supported geometry type", name),
+        }
+    });
+    Some(GeomInfo {
+        declared,
+        srs_id: 4326,
+    })
+}
+
+// whether a type refers to any of the given type parameters
+fn mentions_type_param(ty: &Type, params: &[&Ident]) -> bool {
+    fn walk(tokens: TokenStream, params: &[&Ident]) -> bool {
+        tokens.into_iter().any(|t| match t {
+            proc_macro2::TokenTree::Ident(i) => params.iter().any(|p| **p == i),
+            proc_macro2::TokenTree::Group(g) => walk(g.stream(), params),
+            _ => false,
+        })
+    }
+    walk(quote!(#ty), params)
 }
 
 #[cfg(test)]
@@ -484,6 +1046,56 @@ mod test {
                 geom: GPKGLineStringZ,
             }
         );
-        println!("{}", derive_gpkg_inner(tstream.into()));
+        let output = derive_gpkg_inner(tstream).to_string();
+        let expected = [
+            quote!(
+                fn get_gpkg_layer_name() -> &'static str {
+                    "streetlights"
+                }
+            ),
+            quote!(name: String::from("height"), column_type: gpkg::ColumnType::Real, not_null: true),
+            quote!(name: String::from("string_ref"), column_type: gpkg::ColumnType::Text, not_null: false),
+            quote!(name: String::from("buf_ref"), column_type: gpkg::ColumnType::Blob, not_null: true),
+            quote!(
+                geometry_type: String::from("LINESTRINGZ"),
+                srs_id: 4326i64,
+                z: gpkg::MZOption::Mandatory,
+                m: gpkg::MZOption::Prohibited,
+            ),
+        ];
+        for tokens in expected {
+            assert!(output.contains(&tokens.to_string()), "missing {}", tokens);
+        }
+    }
+
+    #[test]
+    fn enum_test() {
+        let tstream = quote!(
+            #[gpkg(storage = "integer")]
+            enum RoadClass {
+                Highway = 1,
+                Arterial = 2,
+            }
+        );
+        let output = derive_gpkg_enum_inner(tstream).to_string();
+        let expected = [
+            quote!(
+                const COLUMN_TYPE: gpkg::ColumnType = gpkg::ColumnType::Integer;
+            ),
+            quote!(
+                const CONSTRAINT_NAME: &'static str = "road_class";
+            ),
+            quote!(
+                ((RoadClass::Highway as i64).to_string(), "Highway"),
+                ((RoadClass::Arterial as i64).to_string(), "Arterial")
+            ),
+            quote!(RoadClass::Highway => RoadClass::Highway as i64),
+            quote!(if int_value == RoadClass::Arterial as i64 {
+                return Ok(RoadClass::Arterial);
+            }),
+        ];
+        for tokens in expected {
+            assert!(output.contains(&tokens.to_string()), "missing {}", tokens);
+        }
     }
 }
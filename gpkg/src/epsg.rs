@@ -0,0 +1,389 @@
+//! A small offline catalog of commonly used EPSG spatial reference systems, with their OGC WKT definitions
+use crate::srs::SpatialRefSys;
+
+const PRIMEM_GREENWICH: &str = "PRIMEM[\"Greenwich\",0,AUTHORITY[\"EPSG\",\"8901\"]]";
+const UNIT_DEGREE: &str = "UNIT[\"degree\",0.0174532925199433,AUTHORITY[\"EPSG\",\"9122\"]]";
+const UNIT_METRE: &str = "UNIT[\"metre\",1,AUTHORITY[\"EPSG\",\"9001\"]]";
+const AXES_EN: &str = "AXIS[\"Easting\",EAST],AXIS[\"Northing\",NORTH]";
+const AXES_NE: &str = "AXIS[\"Northing\",NORTH],AXIS[\"Easting\",EAST]";
+
+// a geographic system, described by its name, datum, spheroid and the EPSG codes of each
+struct Geographic {
+    code: i64,
+    name: &'static str,
+    datum: &'static str,
+    datum_code: i64,
+    spheroid: &'static str,
+    spheroid_code: i64,
+    semi_major_axis: &'static str,
+    inverse_flattening: &'static str,
+    to_wgs84: Option<&'static str>,
+    description: &'static str,
+}
+
+const WGS84: Geographic = Geographic {
+    code: 4326,
+    name: "WGS 84",
+    datum: "WGS_1984",
+    datum_code: 6326,
+    spheroid: "WGS 84",
+    spheroid_code: 7030,
+    semi_major_axis: "6378137",
+    inverse_flattening: "298.257223563",
+    to_wgs84: None,
+    description: "longitude/latitude coordinates in decimal degrees on the WGS 84 spheroid",
+};
+
+const ETRS89: Geographic = Geographic {
+    code: 4258,
+    name: "ETRS89",
+    datum: "European_Terrestrial_Reference_System_1989",
+    datum_code: 6258,
+    spheroid: "GRS 1980",
+    spheroid_code: 7019,
+    semi_major_axis: "6378137",
+    inverse_flattening: "298.257222101",
+    to_wgs84: Some("0,0,0,0,0,0,0"),
+    description:
+        "longitude/latitude coordinates in decimal degrees on the ETRS89 datum, for Europe",
+};
+
+const NAD83: Geographic = Geographic {
+    code: 4269,
+    name: "NAD83",
+    datum: "North_American_Datum_1983",
+    datum_code: 6269,
+    spheroid: "GRS 1980",
+    spheroid_code: 7019,
+    semi_major_axis: "6378137",
+    inverse_flattening: "298.257222101",
+    to_wgs84: Some("0,0,0,0,0,0,0"),
+    description:
+        "longitude/latitude coordinates in decimal degrees on the NAD83 datum, for North America",
+};
+
+const OSGB36: Geographic = Geographic {
+    code: 4277,
+    name: "OSGB 1936",
+    datum: "OSGB_1936",
+    datum_code: 6277,
+    spheroid: "Airy 1830",
+    spheroid_code: 7001,
+    semi_major_axis: "6377563.396",
+    inverse_flattening: "299.3249646",
+    to_wgs84: Some("446.448,-125.157,542.06,0.15,0.247,0.842,-20.489"),
+    description: "longitude/latitude coordinates in decimal degrees on the OSGB 1936 datum, for Great Britain",
+};
+
+const RGF93: Geographic = Geographic {
+    code: 4171,
+    name: "RGF93",
+    datum: "Reseau_Geodesique_Francais_1993",
+    datum_code: 6171,
+    spheroid: "GRS 1980",
+    spheroid_code: 7019,
+    semi_major_axis: "6378137",
+    inverse_flattening: "298.257222101",
+    to_wgs84: Some("0,0,0,0,0,0,0"),
+    description: "longitude/latitude coordinates in decimal degrees on the RGF93 datum, for France",
+};
+
+const AMERSFOORT: Geographic = Geographic {
+    code: 4289,
+    name: "Amersfoort",
+    datum: "Amersfoort",
+    datum_code: 6289,
+    spheroid: "Bessel 1841",
+    spheroid_code: 7004,
+    semi_major_axis: "6377397.155",
+    inverse_flattening: "299.1528128",
+    to_wgs84: Some("565.2369,50.0087,465.658,-0.406857,0.350733,-1.87035,4.0812"),
+    description: "longitude/latitude coordinates in decimal degrees on the Amersfoort datum, for the Netherlands",
+};
+
+const NZGD2000: Geographic = Geographic {
+    code: 4167,
+    name: "NZGD2000",
+    datum: "New_Zealand_Geodetic_Datum_2000",
+    datum_code: 6167,
+    spheroid: "GRS 1980",
+    spheroid_code: 7019,
+    semi_major_axis: "6378137",
+    inverse_flattening: "298.257222101",
+    to_wgs84: Some("0,0,0,0,0,0,0"),
+    description:
+        "longitude/latitude coordinates in decimal degrees on the NZGD2000 datum, for New Zealand",
+};
+
+const GEOGRAPHIC: [&Geographic; 7] = [
+    &WGS84,
+    &ETRS89,
+    &NAD83,
+    &OSGB36,
+    &RGF93,
+    &AMERSFOORT,
+    &NZGD2000,
+];
+
+impl Geographic {
+    fn wkt(&self) -> String {
+        let to_wgs84 = match self.to_wgs84 {
+            Some(params) => format!(",TOWGS84[{}]", params),
+            None => String::new(),
+        };
+        format!(
+            "GEOGCS[\"{}\",DATUM[\"{}\",SPHEROID[\"{}\",{},{},AUTHORITY[\"EPSG\",\"{}\"]]{},AUTHORITY[\"EPSG\",\"{}\"]],{},{},AUTHORITY[\"EPSG\",\"{}\"]]",
+            self.name,
+            self.datum,
+            self.spheroid,
+            self.semi_major_axis,
+            self.inverse_flattening,
+            self.spheroid_code,
+            to_wgs84,
+            self.datum_code,
+            PRIMEM_GREENWICH,
+            UNIT_DEGREE,
+            self.code
+        )
+    }
+
+    fn srs(&self) -> SpatialRefSys {
+        epsg_srs(self.code, self.name, self.wkt(), self.description)
+    }
+}
+
+// a projected system with metre units
+struct Projected {
+    code: i64,
+    name: &'static str,
+    base: &'static Geographic,
+    projection: &'static str,
+    parameters: &'static [(&'static str, &'static str)],
+    axes: &'static str,
+    extension: Option<&'static str>,
+    description: &'static str,
+}
+
+const PROJECTED: [Projected; 6] = [
+    Projected {
+        code: 3857,
+        name: "WGS 84 / Pseudo-Mercator",
+        base: &WGS84,
+        projection: "Mercator_1SP",
+        parameters: &[
+            ("central_meridian", "0"),
+            ("scale_factor", "1"),
+            ("false_easting", "0"),
+            ("false_northing", "0"),
+        ],
+        axes: AXES_EN,
+        extension: Some("EXTENSION[\"PROJ4\",\"+proj=merc +a=6378137 +b=6378137 +lat_ts=0 +lon_0=0 +x_0=0 +y_0=0 +k=1 +units=m +nadgrids=@null +wktext +no_defs\"]"),
+        description: "Web Mercator, as used by web map tiles",
+    },
+    Projected {
+        code: 3035,
+        name: "ETRS89-extended / LAEA Europe",
+        base: &ETRS89,
+        projection: "Lambert_Azimuthal_Equal_Area",
+        parameters: &[
+            ("latitude_of_center", "52"),
+            ("longitude_of_center", "10"),
+            ("false_easting", "4321000"),
+            ("false_northing", "3210000"),
+        ],
+        axes: AXES_NE,
+        extension: None,
+        description: "Equal area projection for statistical mapping of Europe",
+    },
+    Projected {
+        code: 27700,
+        name: "OSGB 1936 / British National Grid",
+        base: &OSGB36,
+        projection: "Transverse_Mercator",
+        parameters: &[
+            ("latitude_of_origin", "49"),
+            ("central_meridian", "-2"),
+            ("scale_factor", "0.9996012717"),
+            ("false_easting", "400000"),
+            ("false_northing", "-100000"),
+        ],
+        axes: AXES_EN,
+        extension: None,
+        description: "National grid of Great Britain",
+    },
+    Projected {
+        code: 2154,
+        name: "RGF93 / Lambert-93",
+        base: &RGF93,
+        projection: "Lambert_Conformal_Conic_2SP",
+        parameters: &[
+            ("standard_parallel_1", "49"),
+            ("standard_parallel_2", "44"),
+            ("latitude_of_origin", "46.5"),
+            ("central_meridian", "3"),
+            ("false_easting", "700000"),
+            ("false_northing", "6600000"),
+        ],
+        axes: AXES_EN,
+        extension: None,
+        description: "National grid of metropolitan France",
+    },
+    Projected {
+        code: 28992,
+        name: "Amersfoort / RD New",
+        base: &AMERSFOORT,
+        projection: "Oblique_Stereographic",
+        parameters: &[
+            ("latitude_of_origin", "52.15616055555555"),
+            ("central_meridian", "5.38763888888889"),
+            ("scale_factor", "0.9999079"),
+            ("false_easting", "155000"),
+            ("false_northing", "463000"),
+        ],
+        axes: AXES_EN,
+        extension: None,
+        description: "National grid of the Netherlands",
+    },
+    Projected {
+        code: 2193,
+        name: "NZGD2000 / New Zealand Transverse Mercator 2000",
+        base: &NZGD2000,
+        projection: "Transverse_Mercator",
+        parameters: &[
+            ("latitude_of_origin", "0"),
+            ("central_meridian", "173"),
+            ("scale_factor", "0.9996"),
+            ("false_easting", "1600000"),
+            ("false_northing", "10000000"),
+        ],
+        axes: AXES_NE,
+        extension: None,
+        description: "National grid of New Zealand",
+    },
+];
+
+impl Projected {
+    fn srs(&self) -> SpatialRefSys {
+        let parameters = self
+            .parameters
+            .iter()
+            .map(|(name, value)| format!(",PARAMETER[\"{}\",{}]", name, value))
+            .collect::<String>();
+        let extension = match self.extension {
+            Some(e) => format!(",{}", e),
+            None => String::new(),
+        };
+        let wkt = format!(
+            "PROJCS[\"{}\",{},PROJECTION[\"{}\"]{},{},{}{},AUTHORITY[\"EPSG\",\"{}\"]]",
+            self.name,
+            self.base.wkt(),
+            self.projection,
+            parameters,
+            UNIT_METRE,
+            self.axes,
+            extension,
+            self.code
+        );
+        epsg_srs(self.code, self.name, wkt, self.description)
+    }
+}
+
+// the UTM zones of a datum, numbered from the first code for zone 1 in each hemisphere
+struct UtmZones {
+    base: &'static Geographic,
+    zones: std::ops::RangeInclusive<i64>,
+    north: Option<i64>,
+    south: Option<i64>,
+}
+
+const UTM: [UtmZones; 3] = [
+    UtmZones {
+        base: &WGS84,
+        zones: 1..=60,
+        north: Some(32600),
+        south: Some(32700),
+    },
+    UtmZones {
+        base: &ETRS89,
+        zones: 28..=38,
+        north: Some(25800),
+        south: None,
+    },
+    UtmZones {
+        base: &NAD83,
+        zones: 1..=23,
+        north: Some(26900),
+        south: None,
+    },
+];
+
+impl UtmZones {
+    fn srs(&self, code: i64) -> Option<SpatialRefSys> {
+        let (zone, north) = match (self.north, self.south) {
+            (Some(n), _) if self.zones.contains(&(code - n)) => (code - n, true),
+            (_, Some(s)) if self.zones.contains(&(code - s)) => (code - s, false),
+            _ => return None,
+        };
+        let hemisphere = if north { "N" } else { "S" };
+        let name = format!("{} / UTM zone {}{}", self.base.name, zone, hemisphere);
+        let central_meridian = zone * 6 - 183;
+        let wkt = format!(
+            "PROJCS[\"{}\",{},PROJECTION[\"Transverse_Mercator\"],PARAMETER[\"latitude_of_origin\",0],PARAMETER[\"central_meridian\",{}],PARAMETER[\"scale_factor\",0.9996],PARAMETER[\"false_easting\",500000],PARAMETER[\"false_northing\",{}],{},{},AUTHORITY[\"EPSG\",\"{}\"]]",
+            name,
+            self.base.wkt(),
+            central_meridian,
+            if north { 0 } else { 10000000 },
+            UNIT_METRE,
+            AXES_EN,
+            code
+        );
+        let description = format!(
+            "Between {} and {}, {} hemisphere",
+            longitude(central_meridian - 3),
+            longitude(central_meridian + 3),
+            if north { "northern" } else { "southern" }
+        );
+        Some(epsg_srs(code, &name, wkt, &description))
+    }
+}
+
+fn longitude(degrees: i64) -> String {
+    match degrees {
+        d if d < 0 => format!("{}°W", -d),
+        d => format!("{}°E", d),
+    }
+}
+
+fn epsg_srs(code: i64, name: &str, wkt: String, description: &str) -> SpatialRefSys {
+    SpatialRefSys::new(code, name)
+        .with_organization("EPSG", code)
+        .with_definition(wkt)
+        .with_description(description)
+}
+
+/// The definition of an EPSG spatial reference system from the catalog, which is None if it isn't in the catalog
+pub(crate) fn lookup(code: i64) -> Option<SpatialRefSys> {
+    if let Some(g) = GEOGRAPHIC.iter().find(|g| g.code == code) {
+        return Some(g.srs());
+    }
+    if let Some(p) = PROJECTED.iter().find(|p| p.code == code) {
+        return Some(p.srs());
+    }
+    UTM.iter().find_map(|u| u.srs(code))
+}
+
+/// Every code in the catalog, in ascending order
+pub(crate) fn codes() -> Vec<i64> {
+    let mut codes: Vec<i64> = GEOGRAPHIC
+        .iter()
+        .map(|g| g.code)
+        .chain(PROJECTED.iter().map(|p| p.code))
+        .collect();
+    for u in &UTM {
+        for first in [u.north, u.south].into_iter().flatten() {
+            codes.extend(u.zones.clone().map(|z| first + z));
+        }
+    }
+    codes.sort_unstable();
+    codes
+}
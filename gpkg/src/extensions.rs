@@ -0,0 +1,46 @@
+use crate::result::Result;
+use crate::sql;
+use rusqlite::{params, Connection};
+
+pub(crate) const SCHEMA_EXTENSION_NAME: &str = "gpkg_schema";
+pub(crate) const SCHEMA_EXTENSION_DEFINITION: &str =
+    "http://www.geopackage.org/spec/#extension_schema";
+pub(crate) const RTREE_EXTENSION_NAME: &str = "gpkg_rtree_index";
+pub(crate) const RTREE_EXTENSION_DEFINITION: &str =
+    "http://www.geopackage.org/spec120/#extension_rtree";
+
+/// Add a row to gpkg_extensions if an identical one isn't already present.
+///
+/// The unique constraint on the table doesn't prevent duplicates when the column name is NULL,
+/// so existence is checked explicitly.
+pub(crate) fn register_extension(
+    conn: &Connection,
+    table_name: Option<&str>,
+    column_name: Option<&str>,
+    extension_name: &str,
+    definition: &str,
+    scope: &str,
+) -> Result<()> {
+    conn.execute_batch(&sql::register_extension_sql(
+        table_name,
+        column_name,
+        extension_name,
+        definition,
+        scope,
+    ))?;
+    Ok(())
+}
+
+/// Whether a geometry column has an RTree spatial index registered in gpkg_extensions
+pub(crate) fn has_spatial_index(
+    conn: &Connection,
+    table_name: &str,
+    column_name: &str,
+) -> Result<bool> {
+    let count: i64 = conn.query_row(
+        "SELECT count(*) FROM gpkg_extensions WHERE table_name = ?1 AND column_name = ?2 AND extension_name = ?3",
+        params![table_name, column_name, RTREE_EXTENSION_NAME],
+        |r| r.get(0),
+    )?;
+    Ok(count > 0)
+}
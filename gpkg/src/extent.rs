@@ -0,0 +1,339 @@
+use crate::extensions::has_spatial_index;
+use crate::gpkg_wkb::BlobHeader;
+use crate::result::{Error, Result};
+use crate::schema::get_geometry_column;
+use crate::sql::{quote_identifier, rtree_name};
+use crate::GPKGModel;
+use byteorder::{BigEndian, LittleEndian, ReadBytesExt};
+use rusqlite::types::{ToSqlOutput, Value, ValueRef};
+use rusqlite::{params, Connection, OptionalExtension, ToSql};
+use std::io::Cursor;
+
+/// A bounding box in the units of a layer's spatial reference system
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Extent {
+    pub min_x: f64,
+    pub min_y: f64,
+    pub max_x: f64,
+    pub max_y: f64,
+}
+
+impl Extent {
+    /// Grow the extent to also cover another one
+    pub fn expand(&mut self, other: &Extent) {
+        self.min_x = self.min_x.min(other.min_x);
+        self.min_y = self.min_y.min(other.min_y);
+        self.max_x = self.max_x.max(other.max_x);
+        self.max_y = self.max_y.max(other.max_y);
+    }
+
+    // whether an edge of this extent is reached by the other one, so this one could shrink without it
+    fn has_edge_in(&self, other: &Extent) -> bool {
+        other.min_x <= self.min_x
+            || other.min_y <= self.min_y
+            || other.max_x >= self.max_x
+            || other.max_y >= self.max_y
+    }
+
+    fn include(&mut self, x: f64, y: f64) {
+        self.expand(&Extent {
+            min_x: x,
+            min_y: y,
+            max_x: x,
+            max_y: y,
+        });
+    }
+}
+
+// the union of two optional extents
+pub(crate) fn union(a: Option<Extent>, b: Option<Extent>) -> Option<Extent> {
+    match (a, b) {
+        (Some(mut a), Some(b)) => {
+            a.expand(&b);
+            Some(a)
+        }
+        (a, b) => a.or(b),
+    }
+}
+
+/// The extent of a geometry stored as a GeoPackage geometry blob, which is None for an empty geometry.
+///
+/// The envelope in the header is used when there is one, otherwise it's computed from the WKB.
+pub(crate) fn from_geometry_blob(blob: &[u8]) -> Result<Option<Extent>> {
+    let header = BlobHeader::parse(blob)?;
+    // an empty geometry has no extent
+    if header.empty {
+        return Ok(None);
+    }
+    if let Some([min_x, max_x, min_y, max_y]) = header.envelope {
+        return Ok(Some(Extent {
+            min_x,
+            min_y,
+            max_x,
+            max_y,
+        }));
+    }
+    let mut extent = None;
+    read_wkb_extent(&mut Cursor::new(header.wkb), &mut extent)?;
+    Ok(extent)
+}
+
+// walk through a WKB geometry, adding every coordinate to the extent
+fn read_wkb_extent(r: &mut Cursor<&[u8]>, extent: &mut Option<Extent>) -> Result<()> {
+    let little_endian = match r.read_u8()? {
+        0 => false,
+        1 => true,
+        _ => return Err(Error::GeomDecodeError),
+    };
+    let geom_type = read_u32(r, little_endian)?;
+    // ISO WKB adds 1000 for Z, 2000 for M and 3000 for ZM
+    let dimensions = match geom_type / 1000 {
+        0 => 2,
+        1 | 2 => 3,
+        3 => 4,
+        _ => return Err(Error::UnsupportedGeometryType),
+    };
+    let mut read_points = |r: &mut Cursor<&[u8]>, count: u32| -> Result<()> {
+        for _ in 0..count {
+            let x = read_f64(r, little_endian)?;
+            let y = read_f64(r, little_endian)?;
+            for _ in 2..dimensions {
+                read_f64(r, little_endian)?;
+            }
+            // an empty point is written with NaN coordinates
+            if x.is_nan() || y.is_nan() {
+                continue;
+            }
+            match extent {
+                Some(e) => e.include(x, y),
+                None => {
+                    *extent = Some(Extent {
+                        min_x: x,
+                        min_y: y,
+                        max_x: x,
+                        max_y: y,
+                    })
+                }
+            }
+        }
+        Ok(())
+    };
+    match geom_type % 1000 {
+        1 => read_points(r, 1)?,
+        2 => {
+            let count = read_u32(r, little_endian)?;
+            read_points(r, count)?;
+        }
+        3 => {
+            let rings = read_u32(r, little_endian)?;
+            for _ in 0..rings {
+                let count = read_u32(r, little_endian)?;
+                read_points(r, count)?;
+            }
+        }
+        4..=7 => {
+            let geometries = read_u32(r, little_endian)?;
+            for _ in 0..geometries {
+                read_wkb_extent(r, extent)?;
+            }
+        }
+        _ => return Err(Error::UnsupportedGeometryType),
+    }
+    Ok(())
+}
+
+pub(crate) fn read_u32(r: &mut Cursor<&[u8]>, little_endian: bool) -> Result<u32> {
+    Ok(match little_endian {
+        true => r.read_u32::<LittleEndian>()?,
+        false => r.read_u32::<BigEndian>()?,
+    })
+}
+
+pub(crate) fn read_f64(r: &mut Cursor<&[u8]>, little_endian: bool) -> Result<f64> {
+    Ok(match little_endian {
+        true => r.read_f64::<LittleEndian>()?,
+        false => r.read_f64::<BigEndian>()?,
+    })
+}
+
+/// The extent of the geometry of a record, which is None if the model has no geometry or the geometry is null or empty
+pub(crate) fn record_extent<'a, T: GPKGModel<'a>>(record: &T) -> Result<Option<Extent>> {
+    with_geometry_blob(record, from_geometry_blob)
+}
+
+/// Call a function with the geometry of a record as it's written to the layer,
+/// which is skipped if the model has no geometry or the geometry is null
+pub(crate) fn with_geometry_blob<'a, T, R, F>(record: &T, f: F) -> Result<Option<R>>
+where
+    T: GPKGModel<'a>,
+    F: FnOnce(&[u8]) -> Result<Option<R>>,
+{
+    let index = match geometry_param_index::<T>() {
+        Some(i) => i,
+        None => return Ok(None),
+    };
+    let params = record.as_params();
+    match params[index].to_sql()? {
+        ToSqlOutput::Owned(Value::Blob(b)) => f(&b),
+        ToSqlOutput::Borrowed(ValueRef::Blob(b)) => f(b),
+        _ => Ok(None),
+    }
+}
+
+/// The index of the geometry in the parameters of a model, which is None if the model has no geometry
+pub(crate) fn geometry_param_index<'a, T: GPKGModel<'a>>() -> Option<usize> {
+    let geom = T::get_geometry_column()?;
+    T::get_columns().iter().position(|c| c.name == geom.name)
+}
+
+/// The extent of the layer as recorded in gpkg_contents, which is None if it hasn't been set
+/// # Errors
+/// Returns [Error::UnknownLayer] if the layer isn't in gpkg_contents.
+pub(crate) fn layer_extent(conn: &Connection, layer_name: &str) -> Result<Option<Extent>> {
+    let extent = conn
+        .query_row(
+            "SELECT min_x, min_y, max_x, max_y FROM gpkg_contents WHERE table_name = ?1",
+            params![layer_name],
+            |r| {
+                Ok(match (r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?) {
+                    (Some(min_x), Some(min_y), Some(max_x), Some(max_y)) => Some(Extent {
+                        min_x,
+                        min_y,
+                        max_x,
+                        max_y,
+                    }),
+                    _ => None,
+                })
+            },
+        )
+        .optional()?;
+    extent.ok_or_else(|| Error::UnknownLayer(layer_name.to_owned()))
+}
+
+fn set_layer_extent(conn: &Connection, layer_name: &str, extent: Option<&Extent>) -> Result<()> {
+    conn.execute(
+        "UPDATE gpkg_contents SET min_x = ?2, min_y = ?3, max_x = ?4, max_y = ?5 WHERE table_name = ?1",
+        params![
+            layer_name,
+            extent.map(|e| e.min_x),
+            extent.map(|e| e.min_y),
+            extent.map(|e| e.max_x),
+            extent.map(|e| e.max_y)
+        ],
+    )?;
+    Ok(())
+}
+
+/// The union of the extents of the geometries in the rows of a layer that match a predicate,
+/// which is None if the layer has no geometry column or none of the rows have a geometry.
+pub(crate) fn rows_extent(
+    conn: &Connection,
+    layer_name: &str,
+    predicate: &str,
+    params: &[&dyn ToSql],
+) -> Result<Option<Extent>> {
+    let geom = match get_geometry_column(conn, layer_name)? {
+        Some(g) => g,
+        None => return Ok(None),
+    };
+    let sql = format!(
+        "SELECT {} FROM {} WHERE ({}) AND {} IS NOT NULL",
+        quote_identifier(&geom.name),
+        quote_identifier(layer_name),
+        predicate,
+        quote_identifier(&geom.name),
+    );
+    let mut stmt = conn.prepare(&sql)?;
+    let mut rows = stmt.query(params)?;
+    let mut extent = None;
+    while let Some(row) = rows.next()? {
+        if let ValueRef::Blob(b) = row.get_ref(0)? {
+            extent = union(extent, from_geometry_blob(b)?);
+        }
+    }
+    Ok(extent)
+}
+
+/// The extent of the geometries in a layer as they are now, unlike the extent in gpkg_contents,
+/// which isn't kept current by writes that don't go through this crate.
+///
+/// The extent is taken from the spatial index of the layer if it has one, since its triggers keep it current.
+pub(crate) fn current_extent(conn: &Connection, layer_name: &str) -> Result<Option<Extent>> {
+    let geom = match get_geometry_column(conn, layer_name)? {
+        Some(g) => g,
+        None => return Ok(None),
+    };
+    if !has_spatial_index(conn, layer_name, &geom.name)? {
+        return rows_extent(conn, layer_name, "1", &[]);
+    }
+    let sql = format!(
+        "SELECT min(minx), min(miny), max(maxx), max(maxy) FROM {}",
+        quote_identifier(&rtree_name(layer_name, &geom.name))
+    );
+    let extent = conn.query_row(&sql, [], |r| {
+        Ok(match (r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?) {
+            (Some(min_x), Some(min_y), Some(max_x), Some(max_y)) => Some(Extent {
+                min_x,
+                min_y,
+                max_x,
+                max_y,
+            }),
+            _ => None,
+        })
+    })?;
+    Ok(extent)
+}
+
+/// Set the extent of the layer in gpkg_contents from the geometries it contains, returning the new extent
+pub(crate) fn recompute_layer_extent(
+    conn: &Connection,
+    layer_name: &str,
+) -> Result<Option<Extent>> {
+    let extent = rows_extent(conn, layer_name, "1", &[])?;
+    set_layer_extent(conn, layer_name, extent.as_ref())?;
+    Ok(extent)
+}
+
+/// Keep the extent of the layer in gpkg_contents current after a write.
+///
+/// `written` covers the geometries that were added to the layer and `removed` the ones that were deleted or replaced.
+/// The extent is grown to cover new geometries, and only computed again from the whole layer when a removed geometry
+/// was on its edge or it hasn't been set yet.
+pub(crate) fn update_layer_extent(
+    conn: &Connection,
+    layer_name: &str,
+    written: Option<&Extent>,
+    removed: Option<&Extent>,
+) -> Result<()> {
+    let current = match layer_extent(conn, layer_name) {
+        // tables that aren't registered as layers have no extent to maintain
+        Err(Error::UnknownLayer(_)) => return Ok(()),
+        current => current?,
+    };
+    match current {
+        Some(current) if removed.is_some_and(|r| current.has_edge_in(r)) => {
+            recompute_layer_extent(conn, layer_name)?;
+        }
+        Some(_) => {
+            if let Some(w) = written {
+                expand_layer_extent(conn, layer_name, w)?;
+            }
+        }
+        // features may have been written before the extent was maintained, so they need to be included too
+        None if written.is_some() => {
+            recompute_layer_extent(conn, layer_name)?;
+        }
+        None => {}
+    }
+    Ok(())
+}
+
+// grow the extent of the layer in gpkg_contents to cover the given extent
+fn expand_layer_extent(conn: &Connection, layer_name: &str, extent: &Extent) -> Result<()> {
+    conn.execute(
+        "UPDATE gpkg_contents SET min_x = min(min_x, ?2), min_y = min(min_y, ?3), max_x = max(max_x, ?4), max_y = max(max_y, ?5)
+        WHERE table_name = ?1",
+        params![layer_name, extent.min_x, extent.min_y, extent.max_x, extent.max_y],
+    )?;
+    Ok(())
+}
@@ -0,0 +1,245 @@
+use crate::extent::{self, Extent};
+use crate::result::{Error, Result};
+use crate::schema::{get_geometry_column, get_table_columns, GeometryColumnDefinition};
+use crate::spatial::decode_geometry;
+use crate::sql::quote_identifier;
+use crate::types::GPKGGeometry;
+use crate::GeoPackageWKB;
+use geo_types::Geometry;
+use rusqlite::types::{FromSql, ToSqlOutput, Value, ValueRef};
+use rusqlite::{Connection, Row, ToSql};
+use std::collections::BTreeMap;
+
+/// A record from any layer, read without a [GPKGModel](crate::GPKGModel) by using the schema of the layer in the GeoPackage
+///
+/// The properties hold every column other than the fid and the geometry column, with the values as they are stored.
+/// # Examples
+/// ```
+/// # use gpkg::{Feature, Value};
+/// let mut feature = Feature::new();
+/// feature.set("name", "Main St").unwrap();
+/// feature.set("lanes", 2).unwrap();
+/// assert_eq!(feature.get("lanes"), Some(&Value::Integer(2)));
+/// assert_eq!(feature.get_as::<String>("name").unwrap(), "Main St");
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Feature {
+    /// The fid of the record, which is assigned on insert when it's None
+    pub fid: Option<i64>,
+    /// The geometry of the record, which is None for attribute layers and null geometries
+    pub geometry: Option<Geometry<f64>>,
+    pub properties: BTreeMap<String, Value>,
+}
+
+impl Feature {
+    /// An empty feature without a fid
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The value of a column, which is None if the feature doesn't have the column
+    pub fn get(&self, column: &str) -> Option<&Value> {
+        self.properties.get(column)
+    }
+
+    /// The value of a column converted to a Rust type in the same way as reading it from a row
+    /// # Errors
+    /// Returns [Error::UnknownColumn] if the feature doesn't have the column,
+    /// or an [Error::SQLiteError] if the value can't be converted.
+    pub fn get_as<T: FromSql>(&self, column: &str) -> Result<T> {
+        let value = self
+            .get(column)
+            .ok_or_else(|| Error::UnknownColumn(column.to_owned()))?;
+        T::column_result(ValueRef::from(value)).map_err(|e| {
+            rusqlite::Error::FromSqlConversionFailure(0, value.data_type(), Box::new(e)).into()
+        })
+    }
+
+    /// Set the value of a column from anything that can be written to SQLite, replacing any value it had
+    pub fn set<V: ToSql>(&mut self, column: &str, value: V) -> Result<()> {
+        let value = match value.to_sql()? {
+            ToSqlOutput::Borrowed(v) => Value::from(v),
+            ToSqlOutput::Owned(v) => v,
+            _ => {
+                return Err(rusqlite::Error::ToSqlConversionFailure(
+                    format!("the value of {} can't be stored in a feature", column).into(),
+                )
+                .into())
+            }
+        };
+        self.properties.insert(column.to_owned(), value);
+        Ok(())
+    }
+}
+
+/// The columns of an existing layer, as found in gpkg_geometry_columns and the table itself
+pub(crate) struct LayerSchema {
+    pub layer_name: String,
+    pub fid_column: String,
+    pub geometry: Option<GeometryColumnDefinition>,
+    /// The other columns, in the order they are declared
+    pub columns: Vec<String>,
+}
+
+impl LayerSchema {
+    pub(crate) fn read(conn: &Connection, layer_name: &str) -> Result<Self> {
+        let table_columns = get_table_columns(conn, layer_name)?;
+        if table_columns.is_empty() {
+            return Err(Error::UnknownLayer(layer_name.to_owned()));
+        }
+        let geometry = get_geometry_column(conn, layer_name)?;
+        // the specification requires an integer primary key, fall back to the rowid for tables without one
+        let fid_column = table_columns
+            .iter()
+            .find(|c| c.primary_key)
+            .map(|c| c.name.clone())
+            .unwrap_or_else(|| String::from("rowid"));
+        let columns = table_columns
+            .into_iter()
+            .filter(|c| {
+                !c.primary_key
+                    && !geometry
+                        .as_ref()
+                        .is_some_and(|g| g.name.eq_ignore_ascii_case(&c.name))
+            })
+            .map(|c| c.name)
+            .collect();
+        Ok(LayerSchema {
+            layer_name: layer_name.to_owned(),
+            fid_column,
+            geometry,
+            columns,
+        })
+    }
+
+    /// A predicate matching the feature with the fid given as a parameter
+    pub(crate) fn fid_predicate(&self) -> String {
+        format!("{} = ?", quote_identifier(&self.fid_column))
+    }
+
+    /// A query selecting the fid, the geometry and then the other columns, optionally filtered by a predicate
+    pub(crate) fn select_sql(&self, predicate: Option<&str>) -> String {
+        let mut names = vec![quote_identifier(&self.fid_column)];
+        if let Some(geom) = &self.geometry {
+            names.push(quote_identifier(&geom.name));
+        }
+        names.extend(self.columns.iter().map(|c| quote_identifier(c)));
+        let mut sql = format!(
+            "SELECT {} FROM {}",
+            names.join(", "),
+            quote_identifier(&self.layer_name)
+        );
+        if let Some(p) = predicate {
+            sql += " WHERE ";
+            sql += p;
+        }
+        sql
+    }
+
+    /// Build a feature from a row of the query from [LayerSchema::select_sql]
+    pub(crate) fn read_feature(&self, row: &Row) -> Result<Feature> {
+        let mut offset = 1;
+        let geometry = match self.geometry {
+            Some(_) => {
+                offset += 1;
+                decode_geometry(row.get_ref(1)?)?
+            }
+            None => None,
+        };
+        let mut properties = BTreeMap::new();
+        for (i, name) in self.columns.iter().enumerate() {
+            properties.insert(name.clone(), row.get::<_, Value>(offset + i)?);
+        }
+        Ok(Feature {
+            fid: Some(row.get(0)?),
+            geometry,
+            properties,
+        })
+    }
+
+    /// Write a feature, inserting it with its fid if it has one, or updating the row with the given fid.
+    ///
+    /// Returns the fid of the row and the extent of the geometry that was written.
+    pub(crate) fn write_feature(
+        &self,
+        conn: &Connection,
+        feature: &Feature,
+        update_fid: Option<i64>,
+    ) -> Result<(i64, Option<Extent>)> {
+        let geometry = self.encode_geometry(feature)?;
+        let written = match &geometry {
+            Some(blob) => extent::from_geometry_blob(blob)?,
+            None => None,
+        };
+        let mut names = Vec::new();
+        let mut params: Vec<&dyn ToSql> = Vec::new();
+        if let (None, Some(fid)) = (update_fid, &feature.fid) {
+            names.push(quote_identifier(&self.fid_column));
+            params.push(fid);
+        }
+        if let Some(geom) = &self.geometry {
+            names.push(quote_identifier(&geom.name));
+            params.push(&geometry);
+        }
+        for (name, value) in &feature.properties {
+            if !self.columns.iter().any(|c| c.eq_ignore_ascii_case(name)) {
+                return Err(Error::UnknownColumn(name.clone()));
+            }
+            names.push(quote_identifier(name));
+            params.push(value);
+        }
+
+        let fid = match update_fid {
+            Some(fid) => {
+                let mut assignments: Vec<String> =
+                    names.iter().map(|n| format!("{} = ?", n)).collect();
+                // still check that the row exists when there's nothing to write
+                if assignments.is_empty() {
+                    let fid_column = quote_identifier(&self.fid_column);
+                    assignments.push(format!("{} = {}", fid_column, fid_column));
+                }
+                let sql = format!(
+                    "UPDATE {} SET {} WHERE {}",
+                    quote_identifier(&self.layer_name),
+                    assignments.join(", "),
+                    self.fid_predicate()
+                );
+                params.push(&fid);
+                if conn.prepare_cached(&sql)?.execute(params.as_slice())? == 0 {
+                    return Err(Error::UnknownFeature(fid));
+                }
+                fid
+            }
+            None if names.is_empty() => {
+                let sql = format!(
+                    "INSERT INTO {} DEFAULT VALUES",
+                    quote_identifier(&self.layer_name)
+                );
+                conn.prepare_cached(&sql)?.insert([])?
+            }
+            None => {
+                let sql = format!(
+                    "INSERT INTO {} ({}) VALUES ({})",
+                    quote_identifier(&self.layer_name),
+                    names.join(", "),
+                    vec!["?"; names.len()].join(", ")
+                );
+                conn.prepare_cached(&sql)?.insert(params.as_slice())?
+            }
+        };
+        Ok((fid, written))
+    }
+
+    // encode the geometry of a feature with the srs of the layer's geometry column
+    fn encode_geometry(&self, feature: &Feature) -> Result<Option<Vec<u8>>> {
+        let (geom, column) = match (&feature.geometry, &self.geometry) {
+            (Some(geom), Some(column)) => (geom, column),
+            (None, _) => return Ok(None),
+            (Some(_), None) => return Err(Error::NoGeometryColumn(self.layer_name.clone())),
+        };
+        let mut blob = GPKGGeometry(geom.clone()).to_wkb()?;
+        // the header is always written as little endian
+        blob[4..8].copy_from_slice(&(column.srs_id as i32).to_le_bytes());
+        Ok(Some(blob))
+    }
+}
@@ -0,0 +1,185 @@
+use crate::sql::quote_identifier;
+use rusqlite::types::{ToSqlOutput, Value};
+use rusqlite::ToSql;
+
+/// A condition on the columns of a layer, with each value bound as a parameter instead of being written into the SQL.
+///
+/// Filters are built from [Filter::column] and combined with [Filter::and], [Filter::or] and [Filter::not].
+/// When a filter is used in a [Query](crate::Query), the columns it refers to are checked against the model.
+/// # Examples
+/// ```
+/// # use gpkg::{Filter, GeoPackage, GPKGModel};
+/// # use tempfile::tempdir;
+/// # let dir = tempdir().unwrap();
+/// # let path = dir.path().join("filter.gpkg");
+/// # let mut gp = GeoPackage::create(path).unwrap();
+/// #[derive(GPKGModel)]
+/// struct Road {
+///     name: String,
+///     lanes: i64,
+///     speed_limit: Option<i64>,
+/// }
+///
+/// gp.create_layer::<Road>().unwrap();
+/// gp.insert_many(vec![
+///     Road { name: String::from("Main St"), lanes: 4, speed_limit: Some(35) },
+///     Road { name: String::from("Elm St"), lanes: 2, speed_limit: None },
+///     Road { name: String::from("Oak Ave"), lanes: 2, speed_limit: Some(25) },
+/// ]).unwrap();
+///
+/// let filter = Filter::column("name")
+///     .like("% St")
+///     .and(Filter::column("speed_limit").is_null().or(Filter::column("lanes").gt(2)));
+/// let roads = gp.query::<Road>().filter(filter).all().unwrap();
+/// assert_eq!(roads.len(), 2);
+/// ```
+#[derive(Debug)]
+pub struct Filter {
+    pub(crate) sql: String,
+    pub(crate) params: Vec<Value>,
+    pub(crate) columns: Vec<String>,
+    // a value that couldn't be converted, which is returned when the filter is used
+    pub(crate) error: Option<rusqlite::Error>,
+}
+
+impl Filter {
+    /// Start a condition on the named column
+    pub fn column(name: &str) -> FilterColumn {
+        FilterColumn {
+            name: name.to_owned(),
+        }
+    }
+
+    /// Match records that match both filters
+    pub fn and(self, other: Filter) -> Filter {
+        self.combine("AND", other)
+    }
+
+    /// Match records that match either filter
+    pub fn or(self, other: Filter) -> Filter {
+        self.combine("OR", other)
+    }
+
+    /// Match records that don't match the filter
+    #[allow(clippy::should_implement_trait)]
+    pub fn not(mut self) -> Filter {
+        self.sql = format!("NOT ({})", self.sql);
+        self
+    }
+
+    fn combine(mut self, operator: &str, other: Filter) -> Filter {
+        self.sql = format!("({}) {} ({})", self.sql, operator, other.sql);
+        self.params.extend(other.params);
+        self.columns.extend(other.columns);
+        self.error = self.error.or(other.error);
+        self
+    }
+}
+
+/// A column that a [Filter] is being built on, created with [Filter::column]
+#[derive(Debug, Clone)]
+pub struct FilterColumn {
+    name: String,
+}
+
+impl FilterColumn {
+    /// Match records where the column is equal to the value. Use [FilterColumn::is_null] to match nulls.
+    pub fn eq<V: ToSql>(self, value: V) -> Filter {
+        self.compare("=", &value)
+    }
+
+    /// Match records where the column is not equal to the value
+    pub fn ne<V: ToSql>(self, value: V) -> Filter {
+        self.compare("<>", &value)
+    }
+
+    /// Match records where the column is less than the value
+    pub fn lt<V: ToSql>(self, value: V) -> Filter {
+        self.compare("<", &value)
+    }
+
+    /// Match records where the column is less than or equal to the value
+    pub fn le<V: ToSql>(self, value: V) -> Filter {
+        self.compare("<=", &value)
+    }
+
+    /// Match records where the column is greater than the value
+    pub fn gt<V: ToSql>(self, value: V) -> Filter {
+        self.compare(">", &value)
+    }
+
+    /// Match records where the column is greater than or equal to the value
+    pub fn ge<V: ToSql>(self, value: V) -> Filter {
+        self.compare(">=", &value)
+    }
+
+    /// Match records where the column matches a SQL LIKE pattern, which is case insensitive for ASCII characters
+    pub fn like(self, pattern: &str) -> Filter {
+        self.compare("LIKE", &pattern)
+    }
+
+    /// Match records where the column is equal to any of the values
+    pub fn is_in<I, V>(self, values: I) -> Filter
+    where
+        I: IntoIterator<Item = V>,
+        V: ToSql,
+    {
+        let mut filter = self.filter(String::new());
+        let mut placeholders = Vec::new();
+        for value in values {
+            match to_value(&value) {
+                Ok(v) => filter.params.push(v),
+                Err(e) => filter.error = filter.error.or(Some(e)),
+            }
+            placeholders.push("?");
+        }
+        filter.sql = format!(
+            "{} IN ({})",
+            quote_identifier(&self.name),
+            placeholders.join(", ")
+        );
+        filter
+    }
+
+    /// Match records where the column is null
+    pub fn is_null(self) -> Filter {
+        let sql = format!("{} IS NULL", quote_identifier(&self.name));
+        self.filter(sql)
+    }
+
+    /// Match records where the column is not null
+    pub fn is_not_null(self) -> Filter {
+        let sql = format!("{} IS NOT NULL", quote_identifier(&self.name));
+        self.filter(sql)
+    }
+
+    fn compare(self, operator: &str, value: &dyn ToSql) -> Filter {
+        let sql = format!("{} {} ?", quote_identifier(&self.name), operator);
+        let mut filter = self.filter(sql);
+        match to_value(value) {
+            Ok(v) => filter.params.push(v),
+            Err(e) => filter.error = Some(e),
+        }
+        filter
+    }
+
+    fn filter(&self, sql: String) -> Filter {
+        Filter {
+            sql,
+            params: Vec::new(),
+            columns: vec![self.name.clone()],
+            error: None,
+        }
+    }
+}
+
+/// Convert a parameter into an owned value, so it can be kept until a query is run
+pub(crate) fn to_value(value: &dyn ToSql) -> rusqlite::Result<Value> {
+    match value.to_sql()? {
+        ToSqlOutput::Borrowed(v) => Ok(v.into()),
+        ToSqlOutput::Owned(v) => Ok(v),
+        _ => Err(rusqlite::Error::ToSqlConversionFailure(
+            "unsupported parameter type".into(),
+        )),
+    }
+}
@@ -0,0 +1,131 @@
+use crate::extent::{self, Extent};
+use crate::gpkg_wkb::BlobHeader;
+use crate::result::Result;
+use crate::spatial::{decode_geometry, SpatialPredicate};
+use crate::wkt;
+use geo_types::Geometry;
+use rusqlite::functions::{Context, FunctionFlags};
+use rusqlite::types::ValueRef;
+use rusqlite::Connection;
+
+/// Register the SQL functions on geometry blobs that the triggers of the RTree extension and the geometry type
+/// triggers written by other tools like GDAL call, which need to be present on every connection that writes to
+/// those layers, along with the spatial predicates used by [Query::spatial](crate::Query::spatial).
+pub(crate) fn register_functions(conn: &Connection) -> rusqlite::Result<()> {
+    let flags = FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC;
+    conn.create_scalar_function("ST_MinX", 1, flags, |ctx| {
+        Ok(geometry_extent(ctx)?.map(|e| e.min_x))
+    })?;
+    conn.create_scalar_function("ST_MaxX", 1, flags, |ctx| {
+        Ok(geometry_extent(ctx)?.map(|e| e.max_x))
+    })?;
+    conn.create_scalar_function("ST_MinY", 1, flags, |ctx| {
+        Ok(geometry_extent(ctx)?.map(|e| e.min_y))
+    })?;
+    conn.create_scalar_function("ST_MaxY", 1, flags, |ctx| {
+        Ok(geometry_extent(ctx)?.map(|e| e.max_y))
+    })?;
+    conn.create_scalar_function("ST_IsEmpty", 1, flags, |ctx| match ctx.get_raw(0) {
+        ValueRef::Blob(b) => Ok(Some(
+            extent::from_geometry_blob(b)
+                .map_err(|e| rusqlite::Error::UserFunctionError(e.into()))?
+                .is_none(),
+        )),
+        _ => Ok(None),
+    })?;
+    conn.create_scalar_function("ST_GeometryType", 1, flags, |ctx| {
+        with_header(ctx, |h| wkt::wkb_geometry_type(h.wkb))
+    })?;
+    conn.create_scalar_function("ST_SRID", 1, flags, |ctx| {
+        with_header(ctx, |h| Ok(h.srs_id))
+    })?;
+    conn.create_scalar_function("ST_AsText", 1, flags, |ctx| {
+        with_header(ctx, |h| wkt::wkb_to_wkt(h.wkb))
+    })?;
+    conn.create_scalar_function("GPKG_IsAssignable", 2, flags, |ctx| {
+        let expected: String = ctx.get(0)?;
+        let actual: String = ctx.get(1)?;
+        Ok(is_assignable(&expected, &actual))
+    })?;
+    conn.create_scalar_function("ST_Intersects", 2, flags, |ctx| {
+        spatial_predicate(ctx, SpatialPredicate::Intersects)
+    })?;
+    conn.create_scalar_function("ST_Contains", 2, flags, |ctx| {
+        spatial_predicate(ctx, SpatialPredicate::Contains)
+    })?;
+    conn.create_scalar_function("ST_Within", 2, flags, |ctx| {
+        spatial_predicate(ctx, SpatialPredicate::Within)
+    })?;
+    conn.create_scalar_function("ST_DWithin", 3, flags, |ctx| {
+        let distance: f64 = ctx.get(2)?;
+        spatial_predicate(ctx, SpatialPredicate::PlanarDistanceWithin(distance))
+    })?;
+    Ok(())
+}
+
+// call a function with the header of the geometry blob given as the first argument, the result is null for a null value
+fn with_header<R, F>(ctx: &Context, f: F) -> rusqlite::Result<Option<R>>
+where
+    F: FnOnce(&BlobHeader) -> Result<R>,
+{
+    match ctx.get_raw(0) {
+        ValueRef::Blob(b) => BlobHeader::parse(b)
+            .and_then(|h| f(&h))
+            .map(Some)
+            .map_err(|e| rusqlite::Error::UserFunctionError(e.into())),
+        _ => Ok(None),
+    }
+}
+
+// whether a geometry of the actual type can be stored in a column of the expected type, following
+// the type hierarchy of https://www.geopackage.org/spec130/#geometry_types. Z and M suffixes are ignored
+fn is_assignable(expected: &str, actual: &str) -> bool {
+    let base = |name: &str| {
+        name.trim()
+            .to_uppercase()
+            .trim_end_matches("ZM")
+            .trim_end_matches('Z')
+            .trim_end_matches('M')
+            .to_owned()
+    };
+    let (expected, actual) = (base(expected), base(actual));
+    expected == actual
+        || match expected.as_str() {
+            "GEOMETRY" => true,
+            "GEOMETRYCOLLECTION" => matches!(
+                actual.as_str(),
+                "MULTIPOINT" | "MULTICURVE" | "MULTILINESTRING" | "MULTISURFACE" | "MULTIPOLYGON"
+            ),
+            "MULTICURVE" => actual == "MULTILINESTRING",
+            "MULTISURFACE" => actual == "MULTIPOLYGON",
+            "CURVE" => matches!(
+                actual.as_str(),
+                "LINESTRING" | "CIRCULARSTRING" | "COMPOUNDCURVE"
+            ),
+            "SURFACE" => matches!(actual.as_str(), "CURVEPOLYGON" | "POLYGON"),
+            "CURVEPOLYGON" => actual == "POLYGON",
+            _ => false,
+        }
+}
+
+// evaluate a predicate between the geometry blobs given as the first two arguments, which is null if either is null.
+// the second geometry is usually a parameter, so it's only decoded once per statement
+fn spatial_predicate(ctx: &Context, predicate: SpatialPredicate) -> rusqlite::Result<Option<bool>> {
+    let other = ctx.get_or_create_aux(1, decode_geometry)?;
+    let other: &Option<Geometry<f64>> = &other;
+    match (decode_geometry(ctx.get_raw(0)), other) {
+        (Ok(Some(record)), Some(other)) => Ok(Some(predicate.evaluate(&record, other))),
+        (Ok(_), _) => Ok(None),
+        (Err(e), _) => Err(rusqlite::Error::UserFunctionError(e.into())),
+    }
+}
+
+// the extent of the geometry blob given as the first argument, which is None for a null or empty geometry
+fn geometry_extent(ctx: &Context) -> rusqlite::Result<Option<Extent>> {
+    match ctx.get_raw(0) {
+        ValueRef::Blob(b) => {
+            extent::from_geometry_blob(b).map_err(|e| rusqlite::Error::UserFunctionError(e.into()))
+        }
+        _ => Ok(None),
+    }
+}
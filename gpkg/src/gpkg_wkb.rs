@@ -1,5 +1,6 @@
 use crate::result::{Error, Result};
 use crate::types::*;
+use crate::MZOption;
 use byteorder::{BigEndian, ByteOrder, LittleEndian, ReadBytesExt, WriteBytesExt};
 use rusqlite::types::{FromSql, FromSqlResult, ToSqlOutput, ValueRef};
 use rusqlite::ToSql;
@@ -9,10 +10,13 @@ use std::io::{Cursor, Read, Write};
 ///
 /// This trait allows for an easier implementation of the rusqlite [ToSql] and [FromSql] traits needed to read and write geometries to a GeoPackage
 pub trait GeoPackageWKB: Sized {
+    /// Encode the geometry with srs_id 4326 in the header, which is replaced with the srs_id
+    /// of the layer's geometry column when the geometry is written as part of a record
     fn to_wkb(&self) -> Result<Vec<u8>>;
     fn from_wkb(wkb: &mut [u8]) -> Result<Self>;
 }
 
+#[allow(clippy::upper_case_acronyms)]
 enum EnvelopeType {
     Missing,
     XY,
@@ -69,6 +73,77 @@ impl GPKGGeomFlags {
     }
 }
 
+/// The header of a GeoPackage geometry blob, along with the WKB geometry that follows it
+pub(crate) struct BlobHeader<'b> {
+    pub srs_id: i32,
+    pub empty: bool,
+    // the envelope ordered as min x, max x, min y, max y, when there is one
+    pub envelope: Option<[f64; 4]>,
+    pub wkb: &'b [u8],
+}
+
+impl<'b> BlobHeader<'b> {
+    // https://www.geopackage.org/spec130/#gpb_format
+    pub(crate) fn parse(blob: &'b [u8]) -> Result<Self> {
+        if blob.len() < 8 || &blob[0..2] != b"GP" {
+            return Err(Error::GeomDecodeError);
+        }
+        let flags = blob[3];
+        let little_endian = flags & 1 == 1;
+        let envelope_length = match (flags >> 1) & 0b111 {
+            0 => 0,
+            1 => 32,
+            2 | 3 => 48,
+            4 => 64,
+            _ => return Err(Error::GeomDecodeError),
+        };
+        if blob.len() < 8 + envelope_length {
+            return Err(Error::GeomDecodeError);
+        }
+        let mut srs_bytes: [u8; 4] = Default::default();
+        srs_bytes.copy_from_slice(&blob[4..8]);
+        let srs_id = match little_endian {
+            true => i32::from_le_bytes(srs_bytes),
+            false => i32::from_be_bytes(srs_bytes),
+        };
+        let envelope = match envelope_length {
+            0 => None,
+            _ => {
+                let mut values = [0.0; 4];
+                let mut r = Cursor::new(&blob[8..]);
+                for v in values.iter_mut() {
+                    *v = match little_endian {
+                        true => r.read_f64::<LittleEndian>()?,
+                        false => r.read_f64::<BigEndian>()?,
+                    };
+                }
+                Some(values)
+            }
+        };
+        Ok(BlobHeader {
+            srs_id,
+            empty: (flags >> 4) & 1 == 1,
+            envelope,
+            wkb: &blob[8 + envelope_length..],
+        })
+    }
+}
+
+/// Set the srs_id in the header of a geometry blob, in the byte order of the header,
+/// returning whether it was changed
+pub(crate) fn set_blob_srs_id(blob: &mut [u8], srs_id: i32) -> Result<bool> {
+    let header = BlobHeader::parse(blob)?;
+    if header.srs_id == srs_id {
+        return Ok(false);
+    }
+    let bytes = match blob[3] & 1 {
+        1 => srs_id.to_le_bytes(),
+        _ => srs_id.to_be_bytes(),
+    };
+    blob[4..8].copy_from_slice(&bytes);
+    Ok(true)
+}
+
 // once there is a GeoPackageWKB impl for the type
 // the to/from sql impls are really simple, so the macro
 // should help with boilerplate
@@ -106,7 +181,42 @@ impl_gpkg_sql_wkb! {
     GPKGMultiPolygon,
     GPKGMultiLineString,
     GPKGPointZ,
-    GPKGLineStringZ
+    GPKGLineStringZ,
+    GPKGGeometry,
+    GPKGGeometryCollection
+}
+
+/// A geometry type that can be stored in a geometry column, along with the type name and Z/M flags the column is declared with
+///
+/// This allows the geometry column of a [GPKGModel](crate::GPKGModel) to be described by a type parameter,
+/// by using `#[geom_field]` without a geometry type.
+pub trait GPKGGeometryType: GeoPackageWKB + ToSql + FromSql {
+    const GEOMETRY_TYPE: &'static str;
+    const Z: MZOption;
+    const M: MZOption;
+}
+
+macro_rules! impl_gpkg_geometry_type {
+    ($($t:ty => $name:literal, $z:ident, $m:ident),*) => {
+        $(
+            impl GPKGGeometryType for $t {
+                const GEOMETRY_TYPE: &'static str = $name;
+                const Z: MZOption = MZOption::$z;
+                const M: MZOption = MZOption::$m;
+            }
+        )*
+    };
+}
+
+impl_gpkg_geometry_type! {
+    GPKGPoint => "POINT", Prohibited, Prohibited,
+    GPKGPolygon => "POLYGON", Prohibited, Prohibited,
+    GPKGLineString => "LINESTRING", Prohibited, Prohibited,
+    GPKGMultiPoint => "MULTIPOINT", Prohibited, Prohibited,
+    GPKGMultiPolygon => "MULTIPOLYGON", Prohibited, Prohibited,
+    GPKGMultiLineString => "MULTILINESTRING", Prohibited, Prohibited,
+    GPKGPointZ => "POINTZ", Mandatory, Prohibited,
+    GPKGLineStringZ => "LINESTRINGZ", Mandatory, Prohibited
 }
 
 impl<T: FullWKB> GeoPackageWKB for T {
@@ -144,7 +254,7 @@ impl<T: FullWKB> GeoPackageWKB for T {
 
         let mut bytes_cursor = Cursor::new(&bytes[geom_start..]);
 
-        Ok(T::read_from_wkb(&mut bytes_cursor)?)
+        T::read_from_wkb(&mut bytes_cursor)
     }
 }
 
@@ -179,7 +289,7 @@ trait WKBBytesRaw: Sized {
     fn read_from_bytes<T: ByteOrder, U: Read>(r: &mut U) -> Result<Self>;
 }
 
-impl WKBBytesRaw for geo_types::Coordinate<f64> {
+impl WKBBytesRaw for geo_types::Coord<f64> {
     fn write_as_bytes(&self, w: &mut impl Write) -> Result<()> {
         w.write_all(&self.x.to_le_bytes())?;
         w.write_all(&self.y.to_le_bytes())?;
@@ -217,7 +327,7 @@ impl WKBBytesRaw for geo_types::LineString<f64> {
         let num_points = r.read_u32::<T>()?;
         let mut out_vec = Vec::with_capacity(num_points as usize);
         for _ in 0..num_points {
-            out_vec.push(geo_types::Coordinate::<f64>::read_from_bytes::<T, _>(r)?);
+            out_vec.push(geo_types::Coord::<f64>::read_from_bytes::<T, _>(r)?);
         }
         Ok(geo_types::LineString::new(out_vec))
     }
@@ -438,90 +548,105 @@ impl FullWKB for geo_types::Geometry<f64> {
         }
     }
 
+    // geo_types only has x and y, so the Z and M values of a geometry with more dimensions are dropped
     fn read_from_wkb(r: &mut impl Read) -> Result<Self> {
-        let endianness = match r.read_u8()? {
-            0 => 0u8,
-            1 => 1u8,
-            _ => return Err(Error::GeomDecodeError),
-        };
-        let geom_type: u32 = match endianness {
-            0 => r.read_u32::<BigEndian>()?,
-            1 => r.read_u32::<LittleEndian>()?,
-            _ => unreachable!(),
-        };
-        return match geom_type {
-            1 => match endianness {
-                1 => Ok(geo_types::Geometry::Point(
-                    geo_types::Point::<f64>::read_from_bytes::<LittleEndian, _>(r)?,
-                )),
-                0 => Ok(geo_types::Geometry::Point(
-                    geo_types::Point::<f64>::read_from_bytes::<BigEndian, _>(r)?,
-                )),
-                _ => unreachable!(),
-            },
-            2 => match endianness {
-                1 => Ok(geo_types::Geometry::LineString(
-                    geo_types::LineString::<f64>::read_from_bytes::<LittleEndian, _>(r)?,
-                )),
-                0 => Ok(geo_types::Geometry::LineString(
-                    geo_types::LineString::<f64>::read_from_bytes::<BigEndian, _>(r)?,
-                )),
-                _ => unreachable!(),
-            },
-            3 => match endianness {
-                1 => Ok(geo_types::Geometry::Polygon(
-                    geo_types::Polygon::<f64>::read_from_bytes::<LittleEndian, _>(r)?,
-                )),
-                0 => Ok(geo_types::Geometry::Polygon(
-                    geo_types::Polygon::<f64>::read_from_bytes::<BigEndian, _>(r)?,
-                )),
-                _ => unreachable!(),
-            },
-            4 => match endianness {
-                1 => Ok(geo_types::Geometry::MultiPoint(
-                    geo_types::MultiPoint::<f64>::read_from_bytes::<LittleEndian, _>(r)?,
-                )),
-                0 => Ok(geo_types::Geometry::MultiPoint(
-                    geo_types::MultiPoint::<f64>::read_from_bytes::<BigEndian, _>(r)?,
-                )),
-                _ => unreachable!(),
-            },
-            5 => match endianness {
-                1 => Ok(geo_types::Geometry::MultiLineString(
-                    geo_types::MultiLineString::<f64>::read_from_bytes::<LittleEndian, _>(r)?,
-                )),
-                0 => Ok(geo_types::Geometry::MultiLineString(
-                    geo_types::MultiLineString::<f64>::read_from_bytes::<BigEndian, _>(r)?,
-                )),
-                _ => unreachable!(),
-            },
-            6 => match endianness {
-                1 => Ok(geo_types::Geometry::MultiPolygon(
-                    geo_types::MultiPolygon::<f64>::read_from_bytes::<LittleEndian, _>(r)?,
-                )),
-                0 => Ok(geo_types::Geometry::MultiPolygon(
-                    geo_types::MultiPolygon::<f64>::read_from_bytes::<BigEndian, _>(r)?,
-                )),
-                _ => unreachable!(),
-            },
-            7 => {
-                let num_geoms = match endianness {
-                    1 => r.read_u32::<LittleEndian>()?,
-                    0 => r.read_u32::<LittleEndian>()?,
-                    _ => unreachable!(),
-                };
-                let mut geoms = Vec::new();
-                for _ in 0..num_geoms {
-                    geoms.push(geo_types::Geometry::read_from_wkb(r)?);
+        match r.read_u8()? {
+            0 => read_any_dimension::<BigEndian>(r),
+            1 => read_any_dimension::<LittleEndian>(r),
+            _ => Err(Error::GeomDecodeError),
+        }
+    }
+}
+
+// read the rest of an ISO WKB geometry after its byte order, where 1000 is added to the type for Z values,
+// 2000 for M values and 3000 for both
+fn read_any_dimension<T: ByteOrder>(r: &mut impl Read) -> Result<geo_types::Geometry<f64>> {
+    let geom_type = r.read_u32::<T>()?;
+    let extra = match geom_type / 1000 {
+        0 => 0,
+        1 | 2 => 1,
+        3 => 2,
+        _ => return Err(Error::UnsupportedGeometryType),
+    };
+    Ok(match geom_type % 1000 {
+        1 => geo_types::Geometry::Point(read_coord::<T>(r, extra)?.into()),
+        2 => geo_types::Geometry::LineString(read_line::<T>(r, extra)?),
+        3 => geo_types::Geometry::Polygon(read_polygon::<T>(r, extra)?),
+        4 => {
+            let mut points = Vec::new();
+            for _ in 0..r.read_u32::<T>()? {
+                match geo_types::Geometry::<f64>::read_from_wkb(r)? {
+                    geo_types::Geometry::Point(p) => points.push(p),
+                    _ => return Err(Error::GeomDecodeError),
                 }
-                Ok(geo_types::Geometry::GeometryCollection(
-                    geo_types::GeometryCollection::new_from(geoms),
-                ))
             }
-            // unimplemented types
-            _ => Err(Error::UnsupportedGeometryType),
-        };
+            geo_types::Geometry::MultiPoint(geo_types::MultiPoint::new(points))
+        }
+        5 => {
+            let mut lines = Vec::new();
+            for _ in 0..r.read_u32::<T>()? {
+                match geo_types::Geometry::<f64>::read_from_wkb(r)? {
+                    geo_types::Geometry::LineString(l) => lines.push(l),
+                    _ => return Err(Error::GeomDecodeError),
+                }
+            }
+            geo_types::Geometry::MultiLineString(geo_types::MultiLineString::new(lines))
+        }
+        6 => {
+            let mut polygons = Vec::new();
+            for _ in 0..r.read_u32::<T>()? {
+                match geo_types::Geometry::<f64>::read_from_wkb(r)? {
+                    geo_types::Geometry::Polygon(p) => polygons.push(p),
+                    _ => return Err(Error::GeomDecodeError),
+                }
+            }
+            geo_types::Geometry::MultiPolygon(geo_types::MultiPolygon::new(polygons))
+        }
+        7 => {
+            let mut geoms = Vec::new();
+            for _ in 0..r.read_u32::<T>()? {
+                geoms.push(geo_types::Geometry::read_from_wkb(r)?);
+            }
+            geo_types::Geometry::GeometryCollection(geo_types::GeometryCollection::new_from(geoms))
+        }
+        // unimplemented types
+        _ => return Err(Error::UnsupportedGeometryType),
+    })
+}
+
+// read x and y, skipping the given number of extra ordinates after them
+fn read_coord<T: ByteOrder>(r: &mut impl Read, extra: usize) -> Result<geo_types::Coord<f64>> {
+    let x = r.read_f64::<T>()?;
+    let y = r.read_f64::<T>()?;
+    for _ in 0..extra {
+        r.read_f64::<T>()?;
+    }
+    Ok((x, y).into())
+}
+
+fn read_line<T: ByteOrder>(r: &mut impl Read, extra: usize) -> Result<geo_types::LineString<f64>> {
+    let num_points = r.read_u32::<T>()?;
+    let mut coords = Vec::with_capacity(num_points as usize);
+    for _ in 0..num_points {
+        coords.push(read_coord::<T>(r, extra)?);
+    }
+    Ok(geo_types::LineString::new(coords))
+}
+
+fn read_polygon<T: ByteOrder>(r: &mut impl Read, extra: usize) -> Result<geo_types::Polygon<f64>> {
+    let num_rings = r.read_u32::<T>()?;
+    if num_rings == 0 {
+        return Ok(geo_types::Polygon::new(
+            geo_types::LineString::new(Vec::new()),
+            Vec::new(),
+        ));
+    }
+    let exterior = read_line::<T>(r, extra)?;
+    let mut interiors = Vec::with_capacity(num_rings as usize - 1);
+    for _ in 1..num_rings {
+        interiors.push(read_line::<T>(r, extra)?);
     }
+    Ok(geo_types::Polygon::new(exterior, interiors))
 }
 
 #[cfg(test)]
@@ -531,23 +656,22 @@ mod tests {
     use super::*;
     use byteorder::{BigEndian, LittleEndian};
     use geo_types::{
-        coord, Coordinate, Geometry, GeometryCollection, LineString, MultiLineString, MultiPoint,
+        coord, Coord, Geometry, GeometryCollection, LineString, MultiLineString, MultiPoint,
         MultiPolygon, Point, Polygon,
     };
 
     fn points_equal(p1: &Point<f64>, p2: &Point<f64>) -> bool {
-        return (p1.x().to_ne_bytes() == p2.x().to_ne_bytes())
-            && (p1.y().to_ne_bytes() == p2.y().to_ne_bytes());
+        (p1.x().to_ne_bytes() == p2.x().to_ne_bytes())
+            && (p1.y().to_ne_bytes() == p2.y().to_ne_bytes())
     }
 
-    fn coords_equal(p1: &Coordinate<f64>, p2: &Coordinate<f64>) -> bool {
-        return (p1.x.to_ne_bytes() == p2.x.to_ne_bytes())
-            && (p1.y.to_ne_bytes() == p2.y.to_ne_bytes());
+    fn coords_equal(p1: &Coord<f64>, p2: &Coord<f64>) -> bool {
+        (p1.x.to_ne_bytes() == p2.x.to_ne_bytes()) && (p1.y.to_ne_bytes() == p2.y.to_ne_bytes())
     }
 
     fn linestrings_equal(l1: &LineString<f64>, l2: &LineString<f64>) -> bool {
         for (a, b) in zip(&l1.0, &l2.0) {
-            if !coords_equal(&a, &b) {
+            if !coords_equal(a, b) {
                 return false;
             }
         }
@@ -558,8 +682,8 @@ mod tests {
         if !linestrings_equal(p1.exterior(), p2.exterior()) {
             return false;
         }
-        for (a, b) in zip(p1.interiors().into_iter(), p1.interiors().into_iter()) {
-            if !linestrings_equal(&a, &b) {
+        for (a, b) in zip(p1.interiors(), p1.interiors()) {
+            if !linestrings_equal(a, b) {
                 return false;
             }
         }
@@ -984,7 +1108,7 @@ mod tests {
         let written_mp = MultiPoint::read_from_wkb(&mut rdr).unwrap();
 
         for (a, b) in zip(&mp.0, &written_mp.0) {
-            assert!(points_equal(&a, &b));
+            assert!(points_equal(a, b));
         }
     }
 
@@ -997,7 +1121,7 @@ mod tests {
         let le_mp = MultiPoint::read_from_wkb(&mut le_rdr).unwrap();
 
         for (a, b) in zip(&mp.0, &le_mp.0) {
-            assert!(points_equal(&a, &b));
+            assert!(points_equal(a, b));
         }
 
         let be_buf = write_test_multipoint_buf::<BigEndian>(0);
@@ -1005,7 +1129,7 @@ mod tests {
         let be_mp = MultiPoint::read_from_wkb(&mut be_rdr).unwrap();
 
         for (a, b) in zip(&mp.0, &be_mp.0) {
-            assert!(points_equal(&a, &b));
+            assert!(points_equal(a, b));
         }
     }
 
@@ -1023,7 +1147,7 @@ mod tests {
         let written_mls = MultiLineString::read_from_wkb(&mut rdr).unwrap();
 
         for (a, b) in zip(&mls, &written_mls) {
-            assert!(linestrings_equal(&a, &b));
+            assert!(linestrings_equal(a, b));
         }
     }
 
@@ -1036,7 +1160,7 @@ mod tests {
         let le_cmp_mls = MultiLineString::read_from_wkb(&mut rdr).unwrap();
 
         for (a, b) in zip(&mls, &le_cmp_mls) {
-            assert!(linestrings_equal(&a, &b));
+            assert!(linestrings_equal(a, b));
         }
 
         let be_buf = write_test_multilinestring_buf::<BigEndian>(0);
@@ -1044,7 +1168,7 @@ mod tests {
         let be_cmp_mls = MultiLineString::read_from_wkb(&mut rdr).unwrap();
 
         for (a, b) in zip(&mls, &be_cmp_mls) {
-            assert!(linestrings_equal(&a, &b));
+            assert!(linestrings_equal(a, b));
         }
     }
 
@@ -1062,7 +1186,7 @@ mod tests {
         let written_mp = MultiPolygon::read_from_wkb(&mut rdr).unwrap();
 
         for (a, b) in zip(&mp, &written_mp) {
-            assert!(polygons_equal(&a, &b));
+            assert!(polygons_equal(a, b));
         }
     }
 
@@ -1075,7 +1199,7 @@ mod tests {
         let le_cmp_mls = MultiPolygon::read_from_wkb(&mut rdr).unwrap();
 
         for (a, b) in zip(&mls, &le_cmp_mls) {
-            assert!(polygons_equal(&a, &b));
+            assert!(polygons_equal(a, b));
         }
 
         let be_buf = write_test_multipolygon_buf::<BigEndian>(0);
@@ -1083,7 +1207,7 @@ mod tests {
         let be_cmp_mls = MultiPolygon::read_from_wkb(&mut rdr).unwrap();
 
         for (a, b) in zip(&mls, &be_cmp_mls) {
-            assert!(polygons_equal(&a, &b));
+            assert!(polygons_equal(a, b));
         }
     }
 
@@ -1092,7 +1216,7 @@ mod tests {
         // because the WKB geometry type is just a union of geometry types, we can reuse their write methods
         let manual_buf = write_test_point_buf::<LittleEndian>(1);
         let mp = get_test_point();
-        let mp_geom = Geometry::from(mp.clone());
+        let mp_geom = Geometry::from(mp);
         let mut auto_buf = Vec::new();
         mp_geom.write_as_wkb(&mut auto_buf).unwrap();
 
@@ -1158,7 +1282,7 @@ mod tests {
 
         if let Geometry::MultiPoint(written_mp) = written_mp_geom {
             for (a, b) in zip(&mp, &written_mp) {
-                assert!(points_equal(&a, &b));
+                assert!(points_equal(a, b));
             }
         } else {
             panic!();
@@ -1178,7 +1302,7 @@ mod tests {
 
         if let Geometry::MultiLineString(written_mp) = written_mp_geom {
             for (a, b) in zip(&mp, &written_mp) {
-                assert!(linestrings_equal(&a, &b));
+                assert!(linestrings_equal(a, b));
             }
         } else {
             panic!();
@@ -1198,7 +1322,7 @@ mod tests {
 
         if let Geometry::MultiPolygon(written_mp) = written_mp_geom {
             for (a, b) in zip(&mp, &written_mp) {
-                assert!(polygons_equal(&a, &b));
+                assert!(polygons_equal(a, b));
             }
         } else {
             panic!();
@@ -0,0 +1,661 @@
+use crate::extensions::{
+    has_spatial_index, register_extension, SCHEMA_EXTENSION_DEFINITION, SCHEMA_EXTENSION_NAME,
+};
+use crate::result::{Error, Result};
+use crate::schema::{
+    base_geometry_type, geometry_type_flags, get_geometry_column, get_table_columns,
+    is_geometry_type_name, primary_key_column, ColumnDefinition, ColumnType, GPKGDataType,
+    GeometryColumnDefinition, IndexDefinition,
+};
+use crate::sql::table_definitions::CREATE_DATA_COLUMNS_TABLE;
+use crate::sql::{self, quote_identifier};
+use crate::srs::check_srs_exists;
+use rusqlite::types::Value;
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension};
+
+// the tables with a table_name column referring to a layer, other than gpkg_contents which some of them reference
+const LAYER_METADATA_TABLES: [&str; 7] = [
+    "gpkg_geometry_columns",
+    "gpkg_extensions",
+    "gpkg_data_columns",
+    "gpkg_tile_matrix",
+    "gpkg_tile_matrix_set",
+    "gpkg_metadata_reference",
+    "gpkg_ogr_contents",
+];
+
+/// Creates a layer whose schema is only known at runtime, created with [GeoPackage::layer_builder](crate::GeoPackage::layer_builder).
+///
+/// The layer is created with the same statements as a layer for a type deriving [GPKGModel](crate::GPKGModel):
+/// a table with an integer fid primary key followed by the geometry column and the other columns,
+/// along with the rows registering it in gpkg_contents and gpkg_geometry_columns.
+/// # Examples
+/// ```
+/// # use gpkg::{ColumnDefinition, ColumnType, Feature, GeoPackage};
+/// # use geo_types::line_string;
+/// # use tempfile::tempdir;
+/// # let dir = tempdir().unwrap();
+/// # let path = dir.path().join("layer_builder.gpkg");
+/// # let gp = GeoPackage::create(path).unwrap();
+/// gp.layer_builder("roads")
+///     .geometry("geom", "LineString", 4326)
+///     .column(ColumnDefinition::new("name", ColumnType::Text).not_null())
+///     .column(ColumnDefinition::new("lanes", ColumnType::Integer).with_default("2").with_check("lanes > 0"))
+///     .index(&["name"])
+///     .spatial_index()
+///     .create()
+///     .unwrap();
+///
+/// let mut road = Feature::new();
+/// road.set("name", "Main St").unwrap();
+/// road.geometry = Some(line_string![(x: 0.0, y: 0.0), (x: 1.0, y: 1.0)].into());
+/// gp.insert_feature_into("roads", &road).unwrap();
+///
+/// let roads = gp.get_features_from("roads").unwrap();
+/// assert_eq!(roads[0].get_as::<i64>("lanes").unwrap(), 2);
+/// ```
+pub struct LayerBuilder<'conn> {
+    conn: &'conn Connection,
+    layer_name: String,
+    data_type: Option<GPKGDataType>,
+    identifier: Option<String>,
+    description: Option<String>,
+    geometry_column: Option<GeometryColumnDefinition>,
+    geometry_not_null: bool,
+    columns: Vec<ColumnDefinition>,
+    indexes: Vec<IndexDefinition>,
+    spatial_index: bool,
+}
+
+impl<'conn> LayerBuilder<'conn> {
+    pub(crate) fn new(conn: &'conn Connection, layer_name: &str) -> Self {
+        LayerBuilder {
+            conn,
+            layer_name: layer_name.to_owned(),
+            data_type: None,
+            identifier: None,
+            description: None,
+            geometry_column: None,
+            geometry_not_null: false,
+            columns: Vec::new(),
+            indexes: Vec::new(),
+            spatial_index: false,
+        }
+    }
+
+    /// Require the layer to have the given data type, which is otherwise features when there is a geometry column
+    /// and attributes when there isn't.
+    ///
+    /// Only features and attributes layers can be created.
+    pub fn data_type(mut self, data_type: GPKGDataType) -> Self {
+        self.data_type = Some(data_type);
+        self
+    }
+
+    /// Set the human readable identifier of the layer, which defaults to the layer name
+    pub fn identifier(mut self, identifier: &str) -> Self {
+        self.identifier = Some(identifier.to_owned());
+        self
+    }
+
+    /// Set the description of the layer in gpkg_contents
+    pub fn description(mut self, description: &str) -> Self {
+        self.description = Some(description.to_owned());
+        self
+    }
+
+    /// Add a geometry column, where the Z and M flags are set from the suffix of the geometry type
+    /// in the same way as the derive, e.g. PointZ has mandatory Z values.
+    /// Only the base type, e.g. POINT, is stored as the type name, as the flags record the rest.
+    pub fn geometry(self, column_name: &str, geometry_type: &str, srs_id: i64) -> Self {
+        let geometry_type = geometry_type.trim().to_uppercase();
+        let (z, m) = geometry_type_flags(&geometry_type);
+        // a name that isn't a geometry type is kept as is, so that creating the layer reports it
+        let geometry_type = match is_geometry_type_name(&geometry_type) {
+            true => base_geometry_type(&geometry_type).to_owned(),
+            false => geometry_type,
+        };
+        self.geometry_column(GeometryColumnDefinition {
+            name: column_name.to_owned(),
+            geometry_type,
+            srs_id,
+            z,
+            m,
+        })
+    }
+
+    /// Add a geometry column with the Z and M flags given by the definition
+    pub fn geometry_column(mut self, definition: GeometryColumnDefinition) -> Self {
+        self.geometry_column = Some(definition);
+        self
+    }
+
+    /// Don't allow null geometries, which are allowed by default
+    pub fn geometry_not_null(mut self) -> Self {
+        self.geometry_not_null = true;
+        self
+    }
+
+    /// Add an attribute column, the columns are created in the order they are added
+    pub fn column(mut self, definition: ColumnDefinition) -> Self {
+        self.columns.push(definition);
+        self
+    }
+
+    /// Add an index on one or more columns
+    pub fn index(mut self, columns: &[&str]) -> Self {
+        self.indexes.push(IndexDefinition {
+            columns: columns.iter().map(|c| String::from(*c)).collect(),
+            unique: false,
+        });
+        self
+    }
+
+    /// Add a unique index on one or more columns, which allows the same value in a column as long as
+    /// the combination of values differs
+    pub fn unique_index(mut self, columns: &[&str]) -> Self {
+        self.indexes.push(IndexDefinition {
+            columns: columns.iter().map(|c| String::from(*c)).collect(),
+            unique: true,
+        });
+        self
+    }
+
+    /// Create an RTree spatial index on the geometry column along with the layer
+    pub fn spatial_index(mut self) -> Self {
+        self.spatial_index = true;
+        self
+    }
+
+    /// The statements that create the layer
+    /// # Errors
+    /// Returns an error if the layer can't be created, see [LayerBuilder::create].
+    pub fn create_sql(&self) -> Result<String> {
+        let columns = self.all_columns();
+        self.check_definition(&columns)?;
+        Ok(sql::create_layer_sql(
+            &self.layer_name,
+            &columns,
+            self.geometry_column.as_ref(),
+            &self.indexes,
+            self.identifier.as_deref().unwrap_or(&self.layer_name),
+            self.description.as_deref().unwrap_or_default(),
+            self.spatial_index,
+        ))
+    }
+
+    /// Create the layer
+    /// # Errors
+    /// Returns [Error::InvalidLayer] if the geometry type isn't one from the specification, a column name is used
+    /// more than once or the layer doesn't match its data type, [Error::UnknownSrs] if the spatial reference system
+    /// of the geometry column doesn't exist, [Error::UnknownColumn] if an index refers to a column that isn't in the
+    /// layer, or [Error::NoGeometryColumn] if a spatial index is added without a geometry column.
+    pub fn create(self) -> Result<()> {
+        let sql = self.create_sql()?;
+        execute_create_sql(self.conn, &sql)
+    }
+
+    // the columns of the table after the fid, starting with the geometry column
+    fn all_columns(&self) -> Vec<ColumnDefinition> {
+        let mut columns = Vec::with_capacity(self.columns.len() + 1);
+        if let Some(geom) = &self.geometry_column {
+            let mut column = ColumnDefinition::new(&geom.name, ColumnType::Blob);
+            column.not_null = self.geometry_not_null;
+            columns.push(column);
+        }
+        columns.extend(self.columns.iter().cloned());
+        columns
+    }
+
+    fn check_definition(&self, columns: &[ColumnDefinition]) -> Result<()> {
+        let invalid = |problem: String| Err(Error::InvalidLayer(problem));
+        match (&self.data_type, &self.geometry_column) {
+            (None, _) => {}
+            (Some(GPKGDataType::Features), None) => {
+                return Err(Error::NoGeometryColumn(self.layer_name.clone()))
+            }
+            (Some(GPKGDataType::Attributes), Some(_)) => {
+                return invalid(String::from(
+                    "an attributes layer can't have a geometry column",
+                ))
+            }
+            (Some(GPKGDataType::Features | GPKGDataType::Attributes), _) => {}
+            (Some(data_type), _) => {
+                return invalid(format!(
+                    "layers with data type {} can't be created",
+                    data_type.as_str()
+                ))
+            }
+        }
+        if let Some(geom) = &self.geometry_column {
+            if !is_geometry_type_name(&geom.geometry_type.to_uppercase()) {
+                return invalid(format!("{} is not a geometry type", geom.geometry_type));
+            }
+            check_srs_exists(self.conn, geom.srs_id)?;
+        } else if self.spatial_index {
+            return Err(Error::NoGeometryColumn(self.layer_name.clone()));
+        }
+        for (i, column) in columns.iter().enumerate() {
+            let duplicate = column.name.eq_ignore_ascii_case("fid")
+                || columns[..i]
+                    .iter()
+                    .any(|c| c.name.eq_ignore_ascii_case(&column.name));
+            if duplicate {
+                return invalid(format!("column {} is declared more than once", column.name));
+            }
+        }
+        check_indexes(columns, &self.indexes)
+    }
+}
+
+/// Check that each index is on columns of the layer, since sqlite would treat a quoted name that
+/// isn't a column as a string, and silently index a constant
+pub(crate) fn check_indexes(
+    columns: &[ColumnDefinition],
+    indexes: &[IndexDefinition],
+) -> Result<()> {
+    for index in indexes {
+        for index_column in &index.columns {
+            if !columns
+                .iter()
+                .any(|c| c.name.eq_ignore_ascii_case(index_column))
+            {
+                return Err(Error::UnknownColumn(index_column.clone()));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Run the statements from [sql::create_layer_sql]
+pub(crate) fn execute_create_sql(conn: &Connection, sql: &str) -> Result<()> {
+    if let Err(e) = conn.execute_batch(sql) {
+        // the statements are wrapped in a transaction, so don't leave it open if one of them fails
+        if !conn.is_autocommit() {
+            conn.execute_batch("ROLLBACK")?;
+        }
+        return Err(e.into());
+    }
+    Ok(())
+}
+
+// whether the schema object with the given name is a table or a view, which is None if there isn't one
+pub(crate) fn object_type(conn: &Connection, name: &str) -> Result<Option<String>> {
+    Ok(conn
+        .query_row(
+            "SELECT type FROM sqlite_master WHERE name = ?1 AND type IN ('table', 'view')",
+            params![name],
+            |r| r.get(0),
+        )
+        .optional()?)
+}
+
+// whether there is a table or view with the name, or a layer registered under it in gpkg_contents
+fn layer_exists(conn: &Connection, layer_name: &str) -> Result<bool> {
+    let registered: bool = conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM gpkg_contents WHERE table_name = ?1)",
+        params![layer_name],
+        |r| r.get(0),
+    )?;
+    Ok(registered || object_type(conn, layer_name)?.is_some())
+}
+
+// the table of a layer that is renamed or copied, which can't be a view
+fn check_layer_table(conn: &Connection, layer_name: &str) -> Result<()> {
+    match object_type(conn, layer_name)?.as_deref() {
+        Some("table") => Ok(()),
+        Some(_) => Err(Error::InvalidLayer(format!(
+            "{} is a view and can only be dropped",
+            layer_name
+        ))),
+        None => Err(Error::UnknownLayer(layer_name.to_owned())),
+    }
+}
+
+fn check_new_layer_name(conn: &Connection, layer_name: &str) -> Result<()> {
+    match layer_exists(conn, layer_name)? {
+        true => Err(Error::LayerExists(layer_name.to_owned())),
+        false => Ok(()),
+    }
+}
+
+// the indexes created on a table with CREATE INDEX, which doesn't include the ones from
+// unique constraints or indexes on expressions
+fn created_indexes(conn: &Connection, table_name: &str) -> Result<Vec<(String, IndexDefinition)>> {
+    let mut stmt =
+        conn.prepare("SELECT name, \"unique\" FROM pragma_index_list(?1) WHERE origin = 'c'")?;
+    let mut column_stmt = conn.prepare("SELECT name FROM pragma_index_info(?1) ORDER BY seqno")?;
+    let indexes = stmt
+        .query_map(params![table_name], |r| Ok((r.get(0)?, r.get(1)?)))?
+        .collect::<rusqlite::Result<Vec<(String, bool)>>>()?;
+    let mut out_vec = Vec::new();
+    for (name, unique) in indexes {
+        let columns = column_stmt
+            .query_map(params![name], |r| r.get(0))?
+            .collect::<rusqlite::Result<Vec<Option<String>>>>()?;
+        if let Some(columns) = columns.into_iter().collect() {
+            out_vec.push((name, IndexDefinition { columns, unique }));
+        }
+    }
+    Ok(out_vec)
+}
+
+/// Drop a layer's table along with its spatial index and every row that refers to it in the metadata tables
+pub(crate) fn drop_layer(conn: &Connection, layer_name: &str) -> Result<()> {
+    if !layer_exists(conn, layer_name)? {
+        return Err(Error::UnknownLayer(layer_name.to_owned()));
+    }
+    if let Some(geom) = get_geometry_column(conn, layer_name)? {
+        conn.execute_batch(&sql::drop_spatial_index_sql(layer_name, &geom.name))?;
+    }
+    match object_type(conn, layer_name)?.as_deref() {
+        Some("view") => {
+            conn.execute_batch(&format!("DROP VIEW {}", quote_identifier(layer_name)))?
+        }
+        Some(_) => conn.execute_batch(&format!("DROP TABLE {}", quote_identifier(layer_name)))?,
+        None => {}
+    }
+    for table in LAYER_METADATA_TABLES.iter().chain(&["gpkg_contents"]) {
+        if object_type(conn, table)?.is_some() {
+            conn.execute(
+                &format!("DELETE FROM {} WHERE table_name = ?1", table),
+                params![layer_name],
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Rename a layer's table and every reference to it in the metadata tables, rebuilding the spatial index
+/// and the indexes named after the layer
+pub(crate) fn rename_layer(conn: &Connection, layer_name: &str, new_name: &str) -> Result<()> {
+    check_layer_table(conn, layer_name)?;
+    check_new_layer_name(conn, new_name)?;
+    // the metadata tables reference gpkg_contents, which is updated along with them
+    conn.execute_batch("PRAGMA defer_foreign_keys = ON")?;
+    let geom = get_geometry_column(conn, layer_name)?;
+    let spatial_index = match &geom {
+        Some(g) if has_spatial_index(conn, layer_name, &g.name)? => Some(g.name.clone()),
+        _ => None,
+    };
+    if let Some(geom) = &geom {
+        conn.execute_batch(&sql::drop_spatial_index_sql(layer_name, &geom.name))?;
+    }
+    let indexes = created_indexes(conn, layer_name)?;
+    conn.execute_batch(&format!(
+        "ALTER TABLE {} RENAME TO {}",
+        quote_identifier(layer_name),
+        quote_identifier(new_name)
+    ))?;
+    for table in LAYER_METADATA_TABLES.iter().chain(&["gpkg_contents"]) {
+        if object_type(conn, table)?.is_some() {
+            conn.execute(
+                &format!("UPDATE {} SET table_name = ?2 WHERE table_name = ?1", table),
+                params![layer_name, new_name],
+            )?;
+        }
+    }
+    // an identifier that was defaulted from the name follows the name
+    conn.execute(
+        "UPDATE gpkg_contents SET identifier = ?2 WHERE table_name = ?2 AND identifier = ?1",
+        params![layer_name, new_name],
+    )?;
+    for (name, index) in indexes {
+        if name == index.name_for(layer_name) {
+            conn.execute_batch(&format!("DROP INDEX {}", quote_identifier(&name)))?;
+            conn.execute_batch(&sql::create_index_sql(new_name, &index))?;
+        }
+    }
+    if let Some(column) = spatial_index {
+        let primary_key = primary_key_column(conn, new_name)?;
+        conn.execute_batch(&sql::spatial_index_sql(new_name, &column, &primary_key))?;
+    }
+    Ok(())
+}
+
+/// Copy a layer's table, records, indexes and metadata from one connection to another, which can be the same one.
+///
+/// The spatial reference systems used by the layer are copied if the destination doesn't have them.
+/// # Errors
+/// Returns [Error::SrsMismatch] if the destination has a system with the same id that is defined by
+/// another organization or code.
+pub(crate) fn copy_layer(
+    src: &Connection,
+    dst: &Connection,
+    layer_name: &str,
+    new_name: &str,
+) -> Result<()> {
+    check_layer_table(src, layer_name)?;
+    check_new_layer_name(dst, new_name)?;
+
+    let srs_ids = src
+        .prepare(
+            "SELECT srs_id FROM gpkg_contents WHERE table_name = ?1 AND srs_id IS NOT NULL
+            UNION SELECT srs_id FROM gpkg_geometry_columns WHERE table_name = ?1",
+        )?
+        .query_map(params![layer_name], |r| r.get(0))?
+        .collect::<rusqlite::Result<Vec<i64>>>()?;
+    let defining_system = |conn: &Connection, srs_id: i64| {
+        conn.query_row(
+            "SELECT organization, organization_coordsys_id FROM gpkg_spatial_ref_sys WHERE srs_id = ?1",
+            params![srs_id],
+            |r| Ok((r.get::<_, String>(0)?, r.get::<_, i64>(1)?)),
+        )
+        .optional()
+    };
+    for srs_id in srs_ids {
+        let existing = defining_system(dst, srs_id)?;
+        if let (Some((organization, code)), Some((src_organization, src_code))) =
+            (&existing, defining_system(src, srs_id)?)
+        {
+            // the same id can stand for another system in a different GeoPackage,
+            // which would silently change what the coordinates of the layer mean
+            if !organization.eq_ignore_ascii_case(&src_organization) || *code != src_code {
+                return Err(Error::SrsMismatch(srs_id));
+            }
+        }
+        if existing.is_none() {
+            copy_rows(
+                src,
+                dst,
+                ("gpkg_spatial_ref_sys", "gpkg_spatial_ref_sys"),
+                "srs_id = ?1",
+                params![srs_id],
+                &[],
+            )?;
+        }
+    }
+
+    let create_sql: String = src.query_row(
+        "SELECT sql FROM sqlite_master WHERE type = 'table' AND name = ?1",
+        params![layer_name],
+        |r| r.get(0),
+    )?;
+    dst.execute_batch(&rename_create_table_sql(&create_sql, new_name))?;
+    copy_rows(src, dst, (layer_name, new_name), "1", [], &[])?;
+
+    // the identifier has to be unique, so the copy is identified by its name unless the identifier is free
+    let identifier: Option<String> = src
+        .query_row(
+            "SELECT identifier FROM gpkg_contents WHERE table_name = ?1",
+            params![layer_name],
+            |r| r.get(0),
+        )
+        .optional()?
+        .flatten();
+    let identifier = match identifier {
+        Some(i) if i != layer_name => {
+            let taken: bool = dst.query_row(
+                "SELECT EXISTS (SELECT 1 FROM gpkg_contents WHERE identifier = ?1)",
+                params![i],
+                |r| r.get(0),
+            )?;
+            Value::Text(if taken { new_name.to_owned() } else { i })
+        }
+        _ => Value::Text(new_name.to_owned()),
+    };
+    let renamed = Value::Text(new_name.to_owned());
+    copy_rows(
+        src,
+        dst,
+        ("gpkg_contents", "gpkg_contents"),
+        "table_name = ?1",
+        params![layer_name],
+        &[("table_name", &renamed), ("identifier", &identifier)],
+    )?;
+    // the data column tables belong to the schema extension, which the destination may not have used yet
+    let register_schema_extension = |table| {
+        register_extension(
+            dst,
+            Some(table),
+            None,
+            SCHEMA_EXTENSION_NAME,
+            SCHEMA_EXTENSION_DEFINITION,
+            "read-write",
+        )
+    };
+    if object_type(src, "gpkg_data_columns")?.is_some() {
+        dst.execute_batch(CREATE_DATA_COLUMNS_TABLE)?;
+        register_schema_extension("gpkg_data_columns")?;
+    }
+    // metadata references point at rows of gpkg_metadata, so they aren't copied
+    for table in LAYER_METADATA_TABLES
+        .iter()
+        .filter(|t| **t != "gpkg_metadata_reference")
+    {
+        if object_type(src, table)?.is_none() || object_type(dst, table)?.is_none() {
+            continue;
+        }
+        // the spatial index is registered when it's created below
+        let predicate = match *table {
+            "gpkg_extensions" => "table_name = ?1 AND extension_name != 'gpkg_rtree_index'",
+            _ => "table_name = ?1",
+        };
+        copy_rows(
+            src,
+            dst,
+            (table, table),
+            predicate,
+            params![layer_name],
+            &[("table_name", &renamed)],
+        )?;
+    }
+    if object_type(src, "gpkg_data_column_constraints")?.is_some() {
+        dst.execute_batch(sql::table_definitions::CREATE_DATA_COLUMN_CONSTRAINTS_TABLE)?;
+        register_schema_extension("gpkg_data_column_constraints")?;
+        let constraints = src
+            .prepare(
+                "SELECT DISTINCT constraint_name FROM gpkg_data_columns
+                WHERE table_name = ?1 AND constraint_name IS NOT NULL",
+            )?
+            .query_map(params![layer_name], |r| r.get(0))?
+            .collect::<rusqlite::Result<Vec<String>>>()?;
+        for constraint in constraints {
+            // constraints are shared by name, so one the destination already has is kept as it is
+            let exists: bool = dst.query_row(
+                "SELECT EXISTS (SELECT 1 FROM gpkg_data_column_constraints WHERE constraint_name = ?1)",
+                params![constraint],
+                |r| r.get(0),
+            )?;
+            if exists {
+                continue;
+            }
+            copy_rows(
+                src,
+                dst,
+                (
+                    "gpkg_data_column_constraints",
+                    "gpkg_data_column_constraints",
+                ),
+                "constraint_name = ?1",
+                params![constraint],
+                &[],
+            )?;
+        }
+    }
+
+    for (_, index) in created_indexes(src, layer_name)? {
+        dst.execute_batch(&sql::create_index_sql(new_name, &index))?;
+    }
+    if let Some(geom) = get_geometry_column(src, layer_name)? {
+        if has_spatial_index(src, layer_name, &geom.name)? {
+            let primary_key = primary_key_column(dst, new_name)?;
+            dst.execute_batch(&sql::spatial_index_sql(new_name, &geom.name, &primary_key))?;
+        }
+    }
+    Ok(())
+}
+
+// replace the name of the table in the statement that created it, keeping the column definitions and table options
+fn rename_create_table_sql(create_sql: &str, new_name: &str) -> String {
+    // the column definitions start at the first parenthesis that isn't in a quoted name
+    let mut quote = None;
+    let mut start = create_sql.len();
+    for (i, c) in create_sql.char_indices() {
+        match (quote, c) {
+            (None, '"' | '`' | '\'') => quote = Some(c),
+            (None, '[') => quote = Some(']'),
+            (None, '(') => {
+                start = i;
+                break;
+            }
+            (Some(q), _) if c == q => quote = None,
+            _ => {}
+        }
+    }
+    format!(
+        "CREATE TABLE {} {}",
+        quote_identifier(new_name),
+        &create_sql[start..]
+    )
+}
+
+// copy the rows of a table that match a predicate into a table of the destination, replacing the values of some columns.
+// only the columns that are in both tables are copied
+fn copy_rows(
+    src: &Connection,
+    dst: &Connection,
+    (src_table, dst_table): (&str, &str),
+    predicate: &str,
+    params: impl rusqlite::Params,
+    replacements: &[(&str, &Value)],
+) -> Result<()> {
+    let dst_columns = get_table_columns(dst, dst_table)?;
+    let columns: Vec<String> = get_table_columns(src, src_table)?
+        .into_iter()
+        .filter(|c| {
+            dst_columns
+                .iter()
+                .any(|d| d.name.eq_ignore_ascii_case(&c.name))
+        })
+        .map(|c| c.name)
+        .collect();
+    let names: Vec<String> = columns.iter().map(|c| quote_identifier(c)).collect();
+    let mut select = src.prepare(&format!(
+        "SELECT {} FROM {} WHERE {}",
+        names.join(", "),
+        quote_identifier(src_table),
+        predicate
+    ))?;
+    let mut insert = dst.prepare(&format!(
+        "INSERT INTO {} ({}) VALUES ({})",
+        quote_identifier(dst_table),
+        names.join(", "),
+        vec!["?"; names.len()].join(", ")
+    ))?;
+    let mut rows = select.query(params)?;
+    while let Some(row) = rows.next()? {
+        let mut values = Vec::with_capacity(columns.len());
+        for (i, column) in columns.iter().enumerate() {
+            match replacements
+                .iter()
+                .find(|(c, _)| c.eq_ignore_ascii_case(column))
+            {
+                Some((_, v)) => values.push((*v).clone()),
+                None => values.push(row.get::<_, Value>(i)?),
+            }
+        }
+        insert.execute(params_from_iter(values))?;
+    }
+    Ok(())
+}
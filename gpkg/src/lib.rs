@@ -1,23 +1,55 @@
 //! gpkg is a crate intended to enable interactions with [GeoPackages](https://www.geopackage.org/)
 
 #![allow(dead_code)]
+// lets the derive macros refer to items in this crate as gpkg::* from inside the crate as well
+extern crate self as gpkg;
+mod epsg;
+mod extensions;
+mod extent;
+mod feature;
+mod filter;
+mod functions;
 mod gpkg_wkb;
+mod layer;
+mod query;
 mod result;
+mod schema;
+mod spatial;
 mod sql;
 mod srs;
 /// A set of geometry types with the required implementations to be used for readung and writing to GeoPackages
 pub mod types;
+mod wkt;
 use crate::sql::table_definitions::*;
 use crate::srs::defaults::*;
 #[doc(inline)]
-pub use gpkg_derive::GPKGModel;
+pub use extent::Extent;
 #[doc(inline)]
-pub use gpkg_wkb::GeoPackageWKB;
+pub use feature::Feature;
+#[doc(inline)]
+pub use filter::{Filter, FilterColumn};
+#[doc(inline)]
+pub use gpkg_derive::{GPKGEnum, GPKGModel};
+#[doc(inline)]
+pub use gpkg_wkb::{GPKGGeometryType, GeoPackageWKB};
+#[doc(inline)]
+pub use layer::LayerBuilder;
+#[doc(inline)]
+pub use query::{PreparedQuery, Query, Records};
 #[doc(inline)]
 pub use result::{Error, Result};
-use rusqlite::{params, Connection, DatabaseName, OpenFlags, OptionalExtension};
+pub use rusqlite::types::Value;
+use rusqlite::{params, Connection, DatabaseName, OpenFlags};
+#[doc(inline)]
+pub use schema::{
+    ColumnDefinition, ColumnType, GPKGDataType, GeometryColumnDefinition, IndexDefinition,
+    LayerDefinition, MZOption, TableColumn,
+};
 #[doc(inline)]
-pub use srs::SpatialRefSys;
+pub use spatial::{DistanceMetric, SpatialPredicate};
+#[doc(inline)]
+pub use srs::{SpatialRefSys, SrsSource};
+use std::borrow::Borrow;
 use std::path::Path;
 
 /// A GeoPackage, upon creation, the necessary tables for conformance to the specification are created,
@@ -33,40 +65,204 @@ pub struct GeoPackage {
 
 /// A trait that allows for easy writes and reads of a struct into a GeoPackage.
 /// Currently usable only for vector features and attribute only data.
+///
+/// A layer has at most one geometry column, so a struct whose own and flattened fields
+/// have more than one geometry fails to compile:
+/// ```compile_fail
+/// # use gpkg::GPKGModel;
+/// # use gpkg::types::GPKGPoint;
+/// #[derive(GPKGModel)]
+/// struct Start {
+///     #[geom_field("Point")]
+///     start: GPKGPoint,
+/// }
+///
+/// #[derive(GPKGModel)]
+/// struct End {
+///     #[geom_field("Point")]
+///     end: GPKGPoint,
+/// }
+///
+/// #[derive(GPKGModel)]
+/// struct Segment {
+///     #[gpkg(flatten)]
+///     start: Start,
+///     #[gpkg(flatten)]
+///     end: End,
+/// }
+/// ```
 pub trait GPKGModel<'a>: Sized {
-    fn get_create_sql() -> &'static str;
+    /// The number of geometry columns of the model, including those of flattened fields,
+    /// which fails to evaluate when there is more than one
+    const GEOMETRY_COLUMNS: usize;
+
+    fn get_create_sql() -> String {
+        Self::get_create_sql_for(Self::get_gpkg_layer_name())
+    }
+
+    /// The SQL that creates a layer for this type with the given name.
+    ///
+    /// The identifier defaults to the given name rather than the name from the derive,
+    /// so that layers created from the same type can be told apart.
+    fn get_create_sql_for(layer_name: &str) -> String {
+        let identifier = if layer_name == Self::get_gpkg_layer_name() {
+            Self::get_identifier().unwrap_or(layer_name)
+        } else {
+            layer_name
+        };
+        sql::create_layer_sql(
+            layer_name,
+            &Self::get_columns(),
+            Self::get_geometry_column().as_ref(),
+            &Self::get_indexes(),
+            identifier,
+            Self::get_description().unwrap_or_default(),
+            Self::has_spatial_index(),
+        )
+    }
+
+    fn get_insert_sql() -> String {
+        Self::get_insert_sql_for(Self::get_gpkg_layer_name())
+    }
+
+    /// The SQL that inserts an instance of this type into the layer with the given name
+    fn get_insert_sql_for(layer_name: &str) -> String {
+        sql::insert_sql(layer_name, &Self::get_columns(), false)
+    }
+
+    /// The SQL that inserts an instance of this type with a given fid into the layer with the given name.
+    ///
+    /// The fid is the parameter before the ones returned by [GPKGModel::as_params].
+    fn get_insert_with_fid_sql_for(layer_name: &str) -> String {
+        sql::insert_sql(layer_name, &Self::get_columns(), true)
+    }
+
+    fn get_update_sql() -> String {
+        Self::get_update_sql_for(Self::get_gpkg_layer_name())
+    }
+
+    /// The SQL that updates every column of the record with a given fid in the layer with the given name.
+    ///
+    /// The fid is the parameter after the ones returned by [GPKGModel::as_params].
+    fn get_update_sql_for(layer_name: &str) -> String {
+        sql::update_sql(layer_name, &Self::get_columns())
+    }
+
+    /// The SQL that inserts a record with a given fid into the layer with the given name,
+    /// or updates the existing record if there is one.
+    ///
+    /// The fid is the parameter before the ones returned by [GPKGModel::as_params].
+    fn get_upsert_sql_for(layer_name: &str) -> String {
+        sql::upsert_sql(layer_name, &Self::get_columns())
+    }
+
+    fn get_delete_sql() -> String {
+        Self::get_delete_sql_for(Self::get_gpkg_layer_name())
+    }
+
+    /// The SQL that deletes the record with a given fid from the layer with the given name
+    fn get_delete_sql_for(layer_name: &str) -> String {
+        sql::delete_sql(layer_name)
+    }
 
-    fn get_insert_sql() -> &'static str;
+    fn get_select_sql() -> String {
+        Self::get_select_sql_for(Self::get_gpkg_layer_name())
+    }
+
+    /// The SQL that selects every instance of this type from the layer with the given name
+    fn get_select_sql_for(layer_name: &str) -> String {
+        sql::select_sql(layer_name, &Self::get_columns(), None)
+    }
+
+    fn get_select_where(predicate: &str) -> String {
+        Self::get_select_where_for(Self::get_gpkg_layer_name(), predicate)
+    }
+
+    /// The SQL that selects the instances of this type matching a predicate from the layer with the given name
+    fn get_select_where_for(layer_name: &str, predicate: &str) -> String {
+        sql::select_sql(layer_name, &Self::get_columns(), Some(predicate))
+    }
+
+    /// Build an instance from a row whose columns are in the order given by [GPKGModel::get_columns]
+    fn from_row(row: &rusqlite::Row) -> rusqlite::Result<Self> {
+        Self::from_row_offset(row, 0)
+    }
+
+    /// Build an instance from a row, with the columns for this type starting at the given index
+    fn from_row_offset(row: &rusqlite::Row, offset: usize) -> rusqlite::Result<Self>;
+
+    /// Build an instance from a row by looking up each field's column by name,
+    /// so the row may contain columns in any order as well as extra columns.
+    fn from_row_named(row: &rusqlite::Row) -> rusqlite::Result<Self> {
+        Self::from_row_prefixed(row, "")
+    }
 
-    fn get_select_sql() -> &'static str;
+    /// Build an instance from a row by name, where each column name starts with the given prefix
+    fn from_row_prefixed(row: &rusqlite::Row, prefix: &str) -> rusqlite::Result<Self>;
 
-    fn get_select_where(predicate: &str) -> String;
+    /// The columns that make up the layer, including the geometry column but not the fid
+    fn get_columns() -> Vec<ColumnDefinition>;
 
-    fn from_row(row: &rusqlite::Row) -> rusqlite::Result<Self>;
+    /// The number of columns returned by [GPKGModel::get_columns]
+    fn get_column_count() -> usize {
+        Self::get_columns().len()
+    }
+
+    fn get_geometry_column() -> Option<GeometryColumnDefinition>;
+
+    /// The secondary indexes created along with the layer
+    fn get_indexes() -> Vec<IndexDefinition> {
+        Vec::new()
+    }
 
     fn as_params(&self) -> Vec<&(dyn rusqlite::ToSql + '_)>;
 
+    /// Whether an RTree spatial index is created on the geometry column along with the layer
+    fn has_spatial_index() -> bool {
+        false
+    }
+
     fn get_gpkg_layer_name() -> &'static str;
+
+    /// The human readable identifier of the layer in gpkg_contents, which defaults to the layer name
+    fn get_identifier() -> Option<&'static str> {
+        None
+    }
+
+    /// The description of the layer in gpkg_contents
+    fn get_description() -> Option<&'static str> {
+        None
+    }
+}
+
+/// A record that can be passed to [GeoPackage::insert_many], which is either a [GPKGModel] or a reference to one.
+///
+/// This is implemented by the GPKGModel derive.
+pub trait GPKGRecord {
+    type Model;
+
+    fn as_model(&self) -> &Self::Model;
 }
 
-#[derive(Debug)]
-enum GPKGDataType {
-    Features,
-    Attributes,
+impl<T: GPKGRecord> GPKGRecord for &T {
+    type Model = T::Model;
+
+    fn as_model(&self) -> &Self::Model {
+        (*self).as_model()
+    }
 }
 
-#[derive(Debug)]
-struct LayerDefinition {
-    name: String,
-    data_type: String,
-    identifier: Option<String>,
-    description: Option<String>,
-    last_change: String,
-    min_x: Option<f64>,
-    min_y: Option<f64>,
-    max_x: Option<f64>,
-    max_y: Option<f64>,
-    srs_id: Option<i64>,
+/// A trait that allows a unit-only enum to be stored in a column of a [GPKGModel],
+/// either as the name or the discriminant of each variant.
+pub trait GPKGEnum: Sized + rusqlite::ToSql + rusqlite::types::FromSql {
+    /// The type of the column that the enum is stored in
+    const COLUMN_TYPE: ColumnType;
+
+    /// The name used for the enum when it's registered as a data column constraint
+    const CONSTRAINT_NAME: &'static str;
+
+    /// The value stored for each variant, along with the name of the variant
+    fn variants() -> Vec<(String, &'static str)>;
 }
 
 impl GeoPackage {
@@ -92,6 +288,7 @@ impl GeoPackage {
             return Err(Error::CreateExistingError);
         }
         let conn = Connection::open(path)?;
+        functions::register_functions(&conn)?;
         let gpkg = GeoPackage { conn };
         gpkg.conn
             .pragma_update(Some(DatabaseName::Main), "application_id", 0x47504B47)?;
@@ -100,9 +297,9 @@ impl GeoPackage {
         // requrement 10
         gpkg.conn.execute(CREATE_SPATIAL_REF_SYS_TABLE, [])?;
         // insert the default SRS as per spec requirement 11
-        gpkg.new_srs(&WGS84)?;
-        gpkg.new_srs(&CARTESIAN)?;
-        gpkg.new_srs(&GEOGRAPHIC)?;
+        gpkg.new_srs(wgs84())?;
+        gpkg.new_srs(undefined_cartesian())?;
+        gpkg.new_srs(undefined_geographic())?;
         // requirement 13
         gpkg.conn.execute(CREATE_CONTENTS_TABLE, [])?;
         gpkg.conn.execute(CREATE_GEOMETRY_COLUMNS_TABLE, [])?;
@@ -130,28 +327,498 @@ impl GeoPackage {
     /// gp.create_layer::<TestLayer>().unwrap();
     /// ```
     pub fn create_layer<'a, T: GPKGModel<'a>>(&self) -> Result<()> {
-        self.conn.execute_batch(T::get_create_sql())?;
+        self.create_layer_as::<T>(T::get_gpkg_layer_name())
+    }
+
+    /// Create a layer for a type with a name chosen at runtime, instead of the one given by the derive.
+    ///
+    /// The same type can be used for any number of layers, which are then accessed with
+    /// the methods ending in `_into` and `_from`.
+    /// # Examples
+    /// ```
+    /// # use gpkg::{GeoPackage, GPKGModel};
+    /// # use tempfile::tempdir;
+    /// # let dir = tempdir().unwrap();
+    /// # let path = dir.path().join("create_layer_as.gpkg");
+    /// # let gp = GeoPackage::create(path).unwrap();
+    /// #[derive(GPKGModel)]
+    /// struct Road {
+    ///     lanes: i64,
+    /// }
+    ///
+    /// gp.create_layer_as::<Road>("roads_2023").unwrap();
+    /// gp.create_layer_as::<Road>("roads_2024").unwrap();
+    ///
+    /// gp.insert_record_into("roads_2024", &Road { lanes: 4 }).unwrap();
+    ///
+    /// assert_eq!(gp.get_all_from::<Road>("roads_2023").unwrap().len(), 0);
+    /// assert_eq!(gp.get_all_from::<Road>("roads_2024").unwrap().len(), 1);
+    /// ```
+    pub fn create_layer_as<'a, T: GPKGModel<'a>>(&self, layer_name: &str) -> Result<()> {
+        if T::has_spatial_index() && T::get_geometry_column().is_none() {
+            return Err(Error::NoGeometryColumn(layer_name.to_owned()));
+        }
+        layer::check_indexes(&T::get_columns(), &T::get_indexes())?;
+        layer::execute_create_sql(&self.conn, &T::get_create_sql_for(layer_name))
+    }
+
+    /// Start building a layer whose columns are only known at runtime, such as from a configuration file,
+    /// see [LayerBuilder]
+    pub fn layer_builder(&self, layer_name: &str) -> LayerBuilder<'_> {
+        LayerBuilder::new(&self.conn, layer_name)
+    }
+
+    /// Drop a layer, removing its table, its spatial index and every row referring to it in the metadata tables,
+    /// in a single transaction.
+    ///
+    /// Layers that are views are dropped as well.
+    /// # Errors
+    /// Returns [Error::UnknownLayer] if there is no layer with the given name.
+    pub fn drop_layer(&mut self, layer_name: &str) -> Result<()> {
+        let tx = self.conn.transaction()?;
+        layer::drop_layer(&tx, layer_name)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Rename a layer in a single transaction, updating the metadata tables and recreating its spatial index
+    /// and the indexes created along with it under the new name.
+    ///
+    /// If the identifier of the layer is its name, the identifier is changed as well.
+    /// # Errors
+    /// Returns [Error::UnknownLayer] if there is no layer with the given name, [Error::LayerExists] if the new name
+    /// is taken, or [Error::InvalidLayer] if the layer is a view.
+    /// # Examples
+    /// ```
+    /// # use gpkg::{GeoPackage, GPKGModel};
+    /// # use tempfile::tempdir;
+    /// # let dir = tempdir().unwrap();
+    /// # let path = dir.path().join("rename_layer.gpkg");
+    /// # let mut gp = GeoPackage::create(path).unwrap();
+    /// #[derive(GPKGModel)]
+    /// #[layer_name = "roads"]
+    /// struct Road {
+    ///     lanes: i64,
+    /// }
+    ///
+    /// gp.create_layer::<Road>().unwrap();
+    /// gp.insert_record(&Road { lanes: 2 }).unwrap();
+    ///
+    /// gp.rename_layer("roads", "roads_2024").unwrap();
+    /// gp.copy_layer("roads_2024", "roads").unwrap();
+    /// gp.drop_layer("roads_2024").unwrap();
+    ///
+    /// let layers: Vec<String> = gp.list_layers().unwrap().into_iter().map(|l| l.name).collect();
+    /// assert_eq!(layers, vec!["roads"]);
+    /// assert_eq!(gp.get_all::<Road>().unwrap()[0].lanes, 2);
+    /// ```
+    pub fn rename_layer(&mut self, layer_name: &str, new_name: &str) -> Result<()> {
+        let tx = self.conn.transaction()?;
+        layer::rename_layer(&tx, layer_name, new_name)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Copy a layer to a new layer in the same GeoPackage in a single transaction, along with its records,
+    /// indexes, spatial index and metadata.
+    ///
+    /// The copy keeps the identifier of the layer unless it's the layer name or already taken, in which case
+    /// the copy is identified by its own name. Metadata references and triggers other than the ones
+    /// maintaining the spatial index aren't copied.
+    /// # Errors
+    /// Returns [Error::UnknownLayer] if there is no layer with the given name, [Error::LayerExists] if the new name
+    /// is taken, or [Error::InvalidLayer] if the layer is a view.
+    pub fn copy_layer(&mut self, layer_name: &str, new_name: &str) -> Result<()> {
+        let tx = self.conn.transaction()?;
+        layer::copy_layer(&tx, &tx, layer_name, new_name)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Copy a layer into another GeoPackage under the given name, in a single transaction on the destination,
+    /// see [GeoPackage::copy_layer].
+    ///
+    /// The spatial reference systems used by the layer are added to the destination if it doesn't have them.
+    /// # Errors
+    /// The errors of [GeoPackage::copy_layer], and [Error::SrsMismatch] if the destination has a spatial reference
+    /// system with the same id as one used by the layer, but a different organization or code.
+    pub fn copy_layer_to(
+        &self,
+        layer_name: &str,
+        destination: &mut GeoPackage,
+        new_name: &str,
+    ) -> Result<()> {
+        let tx = destination.conn.transaction()?;
+        layer::copy_layer(&self.conn, &tx, layer_name, new_name)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Create an [RTree spatial index](https://www.geopackage.org/spec130/#extension_rtree) on the geometry column
+    /// of a layer, filling it from the existing records.
+    ///
+    /// The index is kept up to date by triggers, which call SQL functions that this crate registers
+    /// whenever a GeoPackage is created or opened. Layers can also be created with an index by adding
+    /// `#[gpkg(spatial_index)]` to the struct deriving [GPKGModel].
+    /// # Errors
+    /// Returns [Error::NoGeometryColumn] if the layer doesn't have a geometry column.
+    /// # Examples
+    /// ```
+    /// # use gpkg::{GeoPackage, GPKGModel};
+    /// # use gpkg::types::GPKGPoint;
+    /// # use tempfile::tempdir;
+    /// # let dir = tempdir().unwrap();
+    /// # let path = dir.path().join("create_spatial_index.gpkg");
+    /// # let mut gp = GeoPackage::create(path).unwrap();
+    /// #[derive(GPKGModel)]
+    /// #[layer_name = "stops"]
+    /// struct Stop {
+    ///     #[geom_field("Point")]
+    ///     geom: GPKGPoint,
+    /// }
+    ///
+    /// gp.create_layer::<Stop>().unwrap();
+    /// gp.insert_record(&Stop { geom: GPKGPoint(geo_types::point!(x: 1.0, y: 2.0)) }).unwrap();
+    /// gp.create_spatial_index("stops").unwrap();
+    ///
+    /// let count: i64 = gp.conn.query_row("SELECT count(*) FROM rtree_stops_geom", [], |r| r.get(0)).unwrap();
+    /// assert_eq!(count, 1);
+    /// ```
+    pub fn create_spatial_index(&mut self, layer_name: &str) -> Result<()> {
+        let geom = schema::get_geometry_column(&self.conn, layer_name)?
+            .ok_or_else(|| Error::NoGeometryColumn(layer_name.to_owned()))?;
+        let primary_key = schema::primary_key_column(&self.conn, layer_name)?;
+        let tx = self.conn.transaction()?;
+        tx.execute_batch(&sql::spatial_index_sql(
+            layer_name,
+            &geom.name,
+            &primary_key,
+        ))?;
+        tx.commit()?;
         Ok(())
     }
 
-    pub fn insert_record<'a, T: GPKGModel<'a>>(&self, record: &T) -> Result<()> {
-        let sql = T::get_insert_sql();
-        self.conn.execute(sql, record.as_params().as_slice())?;
+    /// Whether the geometry column of a layer has an RTree spatial index
+    pub fn has_spatial_index(&self, layer_name: &str) -> Result<bool> {
+        match schema::get_geometry_column(&self.conn, layer_name)? {
+            Some(geom) => extensions::has_spatial_index(&self.conn, layer_name, &geom.name),
+            None => Ok(false),
+        }
+    }
+
+    /// Insert a record, returning the fid it was assigned
+    pub fn insert_record<'a, T: GPKGModel<'a>>(&self, record: &T) -> Result<i64> {
+        self.insert_record_into(T::get_gpkg_layer_name(), record)
+    }
+
+    /// Insert a record into the named layer, returning the fid it was assigned, see [GeoPackage::create_layer_as]
+    pub fn insert_record_into<'a, T: GPKGModel<'a>>(
+        &self,
+        layer_name: &str,
+        record: &T,
+    ) -> Result<i64> {
+        self.insert_with(layer_name, None, record)
+    }
+
+    /// Insert a record with the given fid instead of letting one be assigned, such as when copying
+    /// data where the ids need to stay the same.
+    /// # Errors
+    /// Returns an error if there is already a record with the fid.
+    /// # Examples
+    /// ```
+    /// # use gpkg::{GeoPackage, GPKGModel};
+    /// # use tempfile::tempdir;
+    /// # let dir = tempdir().unwrap();
+    /// # let path = dir.path().join("insert_with_fid.gpkg");
+    /// # let gp = GeoPackage::create(path).unwrap();
+    /// #[derive(GPKGModel)]
+    /// struct Item {
+    ///     length: f64,
+    /// }
+    ///
+    /// gp.create_layer::<Item>().unwrap();
+    /// gp.insert_record_with_fid(100, &Item { length: 25.0 }).unwrap();
+    ///
+    /// let fid = gp.insert_record(&Item { length: 5.0 }).unwrap();
+    /// assert_eq!(fid, 101);
+    /// ```
+    pub fn insert_record_with_fid<'a, T: GPKGModel<'a>>(&self, fid: i64, record: &T) -> Result<()> {
+        self.insert_record_with_fid_into(T::get_gpkg_layer_name(), fid, record)
+    }
+
+    /// Insert a record with the given fid into the named layer, see [GeoPackage::insert_record_with_fid]
+    pub fn insert_record_with_fid_into<'a, T: GPKGModel<'a>>(
+        &self,
+        layer_name: &str,
+        fid: i64,
+        record: &T,
+    ) -> Result<()> {
+        self.insert_with(layer_name, Some(fid), record)?;
+        Ok(())
+    }
+
+    fn insert_with<'a, T: GPKGModel<'a>>(
+        &self,
+        layer_name: &str,
+        fid: Option<i64>,
+        record: &T,
+    ) -> Result<i64> {
+        in_savepoint(&self.conn, || {
+            let srs_id = geometry_srs_id(&self.conn, layer_name)?;
+            let geometry = srs::layer_geometry_blob(record, srs_id)?;
+            let assigned = match fid {
+                Some(fid) => {
+                    let mut params: Vec<&dyn rusqlite::ToSql> = vec![&fid];
+                    params.extend(record_params(record, geometry.as_ref()));
+                    self.conn.execute(
+                        &T::get_insert_with_fid_sql_for(layer_name),
+                        params.as_slice(),
+                    )?;
+                    fid
+                }
+                None => {
+                    self.conn.execute(
+                        &T::get_insert_sql_for(layer_name),
+                        record_params(record, geometry.as_ref()).as_slice(),
+                    )?;
+                    self.conn.last_insert_rowid()
+                }
+            };
+            let written = extent::record_extent(record)?;
+            layer_changed(&self.conn, layer_name, written.as_ref(), None)?;
+            Ok(assigned)
+        })
+    }
+
+    /// Insert records in a single transaction, returning the fid assigned to each one in order.
+    ///
+    /// Records can be given by value or by reference from any iterator, so they don't all need to be in memory at once.
+    pub fn insert_many<'a, I>(&mut self, records: I) -> Result<Vec<i64>>
+    where
+        I: IntoIterator,
+        I::Item: GPKGRecord,
+        <I::Item as GPKGRecord>::Model: GPKGModel<'a>,
+    {
+        self.insert_many_into(
+            <I::Item as GPKGRecord>::Model::get_gpkg_layer_name(),
+            records,
+        )
+    }
+
+    /// Insert records into the named layer in a single transaction, returning the fid assigned to each one,
+    /// see [GeoPackage::create_layer_as]
+    pub fn insert_many_into<'a, I>(&mut self, layer_name: &str, records: I) -> Result<Vec<i64>>
+    where
+        I: IntoIterator,
+        I::Item: GPKGRecord,
+        <I::Item as GPKGRecord>::Model: GPKGModel<'a>,
+    {
+        self.insert_many_with(layer_name, records.into_iter().map(|r| (None, r)))
+    }
+
+    /// Insert pairs of a fid and a record in a single transaction, see [GeoPackage::insert_record_with_fid]
+    pub fn insert_many_with_fids<'a, I, R>(&mut self, records: I) -> Result<()>
+    where
+        I: IntoIterator<Item = (i64, R)>,
+        R: GPKGRecord,
+        R::Model: GPKGModel<'a>,
+    {
+        self.insert_many_with_fids_into(R::Model::get_gpkg_layer_name(), records)
+    }
+
+    /// Insert pairs of a fid and a record into the named layer in a single transaction,
+    /// see [GeoPackage::insert_record_with_fid]
+    pub fn insert_many_with_fids_into<'a, I, R>(
+        &mut self,
+        layer_name: &str,
+        records: I,
+    ) -> Result<()>
+    where
+        I: IntoIterator<Item = (i64, R)>,
+        R: GPKGRecord,
+        R::Model: GPKGModel<'a>,
+    {
+        self.insert_many_with(
+            layer_name,
+            records.into_iter().map(|(fid, r)| (Some(fid), r)),
+        )?;
         Ok(())
     }
 
-    pub fn insert_many<'a, T: GPKGModel<'a>>(&mut self, records: &Vec<T>) -> Result<()> {
-        let sql = T::get_insert_sql();
+    fn insert_many_with<'a, I, R>(&mut self, layer_name: &str, records: I) -> Result<Vec<i64>>
+    where
+        I: Iterator<Item = (Option<i64>, R)>,
+        R: GPKGRecord,
+        R::Model: GPKGModel<'a>,
+    {
         let tx = self.conn.transaction()?;
-        // extra block is here so that stmt gets dropped
+        let srs_id = geometry_srs_id(&tx, layer_name)?;
+        let mut written_extent = None;
+        let mut fids = Vec::new();
+        // extra block is here so that the statements get dropped
         {
-            let mut stmt = tx.prepare(sql)?;
-            for record in records {
-                stmt.execute(record.as_params().as_slice())?;
+            let mut stmt = tx.prepare(&R::Model::get_insert_sql_for(layer_name))?;
+            let mut fid_stmt = tx.prepare(&R::Model::get_insert_with_fid_sql_for(layer_name))?;
+            for (fid, record) in records {
+                let model = record.as_model();
+                let geometry = srs::layer_geometry_blob(model, srs_id)?;
+                let params = record_params(model, geometry.as_ref());
+                match fid {
+                    Some(fid) => {
+                        let mut fid_params: Vec<&dyn rusqlite::ToSql> = vec![&fid];
+                        fid_params.extend(params);
+                        fids.push(fid_stmt.insert(fid_params.as_slice())?);
+                    }
+                    None => fids.push(stmt.insert(params.as_slice())?),
+                }
+                written_extent = extent::union(written_extent, extent::record_extent(model)?);
             }
         }
+        layer_changed(&tx, layer_name, written_extent.as_ref(), None)?;
         tx.commit()?;
-        Ok(())
+        Ok(fids)
+    }
+
+    /// Replace the record with the given fid.
+    ///
+    /// The extent of the layer in gpkg_contents is updated to cover the new geometry instead of the old one,
+    /// and a spatial index is kept up to date by the triggers that maintain it.
+    /// # Errors
+    /// Returns [Error::UnknownFeature] if there is no record with the fid.
+    /// # Examples
+    /// ```
+    /// # use gpkg::{GeoPackage, GPKGModel};
+    /// # use tempfile::tempdir;
+    /// # let dir = tempdir().unwrap();
+    /// # let path = dir.path().join("update_record.gpkg");
+    /// # let gp = GeoPackage::create(path).unwrap();
+    /// #[derive(GPKGModel)]
+    /// struct Item {
+    ///     length: f64,
+    /// }
+    ///
+    /// gp.create_layer::<Item>().unwrap();
+    /// gp.insert_record(&Item { length: 25.0 }).unwrap();
+    ///
+    /// gp.update_record(1, &Item { length: 30.0 }).unwrap();
+    /// gp.upsert(2, &Item { length: 5.0 }).unwrap();
+    /// assert_eq!(gp.get_where::<Item>("length > 10").unwrap()[0].length, 30.0);
+    ///
+    /// let deleted = gp.delete_where::<Item>("length < ?", &[&10.0]).unwrap();
+    /// assert_eq!(deleted, 1);
+    /// ```
+    pub fn update_record<'a, T: GPKGModel<'a>>(&self, fid: i64, record: &T) -> Result<()> {
+        self.update_record_in(T::get_gpkg_layer_name(), fid, record)
+    }
+
+    /// Replace the record with the given fid in the named layer, see [GeoPackage::update_record]
+    pub fn update_record_in<'a, T: GPKGModel<'a>>(
+        &self,
+        layer_name: &str,
+        fid: i64,
+        record: &T,
+    ) -> Result<()> {
+        in_savepoint(&self.conn, || {
+            let replaced = extent::rows_extent(&self.conn, layer_name, "fid = ?", params![fid])?;
+            let srs_id = geometry_srs_id(&self.conn, layer_name)?;
+            let geometry = srs::layer_geometry_blob(record, srs_id)?;
+            let mut params = record_params(record, geometry.as_ref());
+            params.push(&fid);
+            let updated = self
+                .conn
+                .execute(&T::get_update_sql_for(layer_name), params.as_slice())?;
+            if updated == 0 {
+                return Err(Error::UnknownFeature(fid));
+            }
+            let written = extent::record_extent(record)?;
+            layer_changed(&self.conn, layer_name, written.as_ref(), replaced.as_ref())
+        })
+    }
+
+    /// Insert a record with the given fid, or replace the record if there already is one with that fid.
+    ///
+    /// See [GeoPackage::update_record] for how the layer metadata is kept up to date.
+    pub fn upsert<'a, T: GPKGModel<'a>>(&self, fid: i64, record: &T) -> Result<()> {
+        self.upsert_into(T::get_gpkg_layer_name(), fid, record)
+    }
+
+    /// Insert or replace a record with the given fid in the named layer, see [GeoPackage::upsert]
+    pub fn upsert_into<'a, T: GPKGModel<'a>>(
+        &self,
+        layer_name: &str,
+        fid: i64,
+        record: &T,
+    ) -> Result<()> {
+        in_savepoint(&self.conn, || {
+            let replaced = extent::rows_extent(&self.conn, layer_name, "fid = ?", params![fid])?;
+            let srs_id = geometry_srs_id(&self.conn, layer_name)?;
+            let geometry = srs::layer_geometry_blob(record, srs_id)?;
+            let mut params: Vec<&dyn rusqlite::ToSql> = vec![&fid];
+            params.extend(record_params(record, geometry.as_ref()));
+            self.conn
+                .execute(&T::get_upsert_sql_for(layer_name), params.as_slice())?;
+            let written = extent::record_extent(record)?;
+            layer_changed(&self.conn, layer_name, written.as_ref(), replaced.as_ref())
+        })
+    }
+
+    /// Delete the record with the given fid, returning whether there was one.
+    ///
+    /// The extent of the layer in gpkg_contents is computed again if the geometry of the record was on its edge.
+    pub fn delete_record<'a, T: GPKGModel<'a>>(&self, fid: i64) -> Result<bool> {
+        self.delete_record_from::<T>(T::get_gpkg_layer_name(), fid)
+    }
+
+    /// Delete the record with the given fid from the named layer, see [GeoPackage::delete_record]
+    pub fn delete_record_from<'a, T: GPKGModel<'a>>(
+        &self,
+        layer_name: &str,
+        fid: i64,
+    ) -> Result<bool> {
+        in_savepoint(&self.conn, || {
+            let removed = extent::rows_extent(&self.conn, layer_name, "fid = ?", params![fid])?;
+            let deleted = self
+                .conn
+                .execute(&T::get_delete_sql_for(layer_name), params![fid])?;
+            if deleted > 0 {
+                layer_changed(&self.conn, layer_name, None, removed.as_ref())?;
+            }
+            Ok(deleted > 0)
+        })
+    }
+
+    /// Delete the records matching a predicate with placeholders for the given parameters,
+    /// returning the number of records deleted.
+    ///
+    /// See [GeoPackage::delete_record] for how the extent of the layer is affected.
+    pub fn delete_where<'a, T: GPKGModel<'a>>(
+        &self,
+        predicate: &str,
+        params: &[&dyn rusqlite::ToSql],
+    ) -> Result<usize> {
+        self.delete_where_from::<T>(T::get_gpkg_layer_name(), predicate, params)
+    }
+
+    /// Delete the records in the named layer matching a predicate, see [GeoPackage::delete_where]
+    pub fn delete_where_from<'a, T: GPKGModel<'a>>(
+        &self,
+        layer_name: &str,
+        predicate: &str,
+        params: &[&dyn rusqlite::ToSql],
+    ) -> Result<usize> {
+        let sql = format!(
+            "DELETE FROM {} WHERE {}",
+            sql::quote_identifier(layer_name),
+            predicate
+        );
+        in_savepoint(&self.conn, || {
+            let removed = extent::rows_extent(&self.conn, layer_name, predicate, params)?;
+            let deleted = self.conn.execute(&sql, params)?;
+            if deleted > 0 {
+                layer_changed(&self.conn, layer_name, None, removed.as_ref())?;
+            }
+            Ok(deleted)
+        })
     }
 
     /// Fetch all records in the layer containing items of this type that
@@ -182,13 +849,12 @@ impl GeoPackage {
     /// assert_eq!(records.len(), 2);
     /// ```
     pub fn get_all<'a, T: GPKGModel<'a>>(&self) -> Result<Vec<T>> {
-        let mut stmt = self.conn.prepare(T::get_select_sql())?;
-        let mut out_vec = Vec::new();
-        let rows = stmt.query_map([], |row| T::from_row(row))?;
-        for r in rows {
-            out_vec.push(r?)
-        }
-        Ok(out_vec)
+        self.get_all_from(T::get_gpkg_layer_name())
+    }
+
+    /// Fetch all records in the named layer, see [GeoPackage::create_layer_as]
+    pub fn get_all_from<'a, T: GPKGModel<'a>>(&self, layer_name: &str) -> Result<Vec<T>> {
+        self.query::<T>().layer(layer_name).all()
     }
 
     /// Fetch all records in the layer containing items of this type that
@@ -219,72 +885,832 @@ impl GeoPackage {
     /// assert_eq!(records.len(), 1);
     /// ```
     pub fn get_where<'a, T: GPKGModel<'a>>(&self, predicate: &str) -> crate::Result<Vec<T>> {
-        let mut stmt = self.conn.prepare(T::get_select_where(predicate).as_str())?;
-        let mut out_vec = Vec::new();
-        let rows = stmt.query_map([], |row| T::from_row(row))?;
-        for r in rows {
-            out_vec.push(r?)
-        }
-        Ok(out_vec)
-    }
-
-    /// Add a new spatial reference system to the geopackage
-    pub fn new_srs(&self, srs: &SpatialRefSys) -> Result<()> {
-        const STMT: &str = "INSERT INTO gpkg_spatial_ref_sys VALUES (?1, ?2, ?3, ?4, ?5, ?6)";
-        self.conn.execute(
-            STMT,
-            params![
-                srs.name,
-                srs.id,
-                srs.organization,
-                srs.organization_coordsys_id,
-                srs.definition,
-                srs.description,
-            ],
-        )?;
-        Ok(())
+        self.get_where_from(T::get_gpkg_layer_name(), predicate)
     }
 
-    /// Retrieve the srs_id for a layer
-    pub fn get_layer_srs_id(&self, layer_name: &str) -> Result<Option<i64>> {
-        let mut stmt = self
-            .conn
-            .prepare("SELECT FROM gpkg_contents WHERE table_name = ?1")?;
-        let temp: Option<i64> = stmt.query_row(params![layer_name], |r| r.get(0).optional())?;
-        Ok(temp)
+    /// Fetch the records in the named layer that match the given predicate, see [GeoPackage::create_layer_as]
+    pub fn get_where_from<'a, T: GPKGModel<'a>>(
+        &self,
+        layer_name: &str,
+        predicate: &str,
+    ) -> Result<Vec<T>> {
+        self.query::<T>()
+            .layer(layer_name)
+            .where_sql(predicate)
+            .all()
     }
 
-    /// Update the SRS ID for a layer.
+    /// Fetch the records matching a predicate with placeholders, which are bound to the given parameters
+    /// instead of being written into the SQL.
+    /// # Examples
+    /// ```
+    /// # use gpkg::{GeoPackage, GPKGModel};
+    /// # use rusqlite::params;
+    /// # use tempfile::tempdir;
+    /// # let dir = tempdir().unwrap();
+    /// # let path = dir.path().join("get_where_with.gpkg");
+    /// # let gp = GeoPackage::create(path).unwrap();
+    /// #[derive(GPKGModel)]
+    /// struct Item {
+    ///     name: String,
+    ///     length: f64,
+    /// }
     ///
-    /// Note that this does not check if the provided SRS has a corresponding entry in the GeoPackage metadata.
-    pub fn update_layer_srs_id(&mut self, layer_name: &str, srs_id: i64) -> Result<()> {
-        let tx = self.conn.transaction()?;
-        tx.execute(
-            "UPDATE gpkg_contents SET srs_id = ?1 WHERE layer_name = ?2",
-            params![srs_id, layer_name],
-        )?;
-        tx.execute(
-            "UPDATE gpkg_geometry_columns SET srs_id = ?1 WHERE layer_name = ?2",
-            params![srs_id, layer_name],
-        )?;
-        tx.commit()?;
-        Ok(())
+    /// gp.create_layer::<Item>().unwrap();
+    /// gp.insert_record(&Item { name: String::from("a'; DROP TABLE Item; --"), length: 25.0 }).unwrap();
+    ///
+    /// let user_input = "a'; DROP TABLE Item; --";
+    /// let records: Vec<Item> = gp
+    ///     .get_where_with("name = ?1 AND length > ?2", params![user_input, 10.0])
+    ///     .unwrap();
+    /// assert_eq!(records.len(), 1);
+    /// ```
+    pub fn get_where_with<'a, T: GPKGModel<'a>>(
+        &self,
+        predicate: &str,
+        params: &[&dyn rusqlite::ToSql],
+    ) -> Result<Vec<T>> {
+        self.query::<T>().where_sql_with(predicate, params).all()
     }
 
-    /// Close the geopackage
+    /// Fetch the records whose geometry has a bounding box that intersects the given one.
+    ///
+    /// The layer's RTree spatial index is used if it has one. To read the records one at a time instead,
+    /// prepare a query with [Query::bbox] and iterate over it.
+    /// # Errors
+    /// Returns [Error::NoGeometryColumn] if the type doesn't have a geometry column.
     /// # Examples
-    /// ```ignore
-    /// # use std::path::Path;
-    /// let path = Path::new("./test.gpkg");
-    /// let gp = GeoPackage::create(path).unwrap();
-    /// // do some things with the GeoPackage
-    /// gp.close();
     /// ```
-    pub fn close(self) {
-        self.conn.close().unwrap();
-    }
-
-    /// Open a geopackage, doing validation of the GeoPackage internals to ensure that operation will work correctly.
+    /// # use gpkg::{GeoPackage, GPKGModel};
+    /// # use gpkg::types::GPKGPoint;
+    /// # use tempfile::tempdir;
+    /// # let dir = tempdir().unwrap();
+    /// # let path = dir.path().join("get_in_bbox.gpkg");
+    /// # let mut gp = GeoPackage::create(path).unwrap();
+    /// #[derive(GPKGModel)]
+    /// #[layer_name = "stops"]
+    /// struct Stop {
+    ///     #[geom_field("Point")]
+    ///     geom: GPKGPoint,
+    /// }
+    ///
+    /// gp.create_layer::<Stop>().unwrap();
+    /// gp.insert_many((0..10).map(|i| Stop { geom: GPKGPoint(geo_types::point!(x: i as f64, y: 0.0)) })).unwrap();
+    ///
+    /// let stops = gp.get_in_bbox::<Stop>(-1.0, -1.0, 3.0, 1.0).unwrap();
+    /// assert_eq!(stops.len(), 4);
+    ///
+    /// gp.create_spatial_index("stops").unwrap();
+    /// let mut stmt = gp.query::<Stop>().bbox(-1.0, -1.0, 3.0, 1.0).prepare().unwrap();
+    /// assert_eq!(stmt.iter().unwrap().count(), 4);
+    /// ```
+    pub fn get_in_bbox<'a, T: GPKGModel<'a>>(
+        &self,
+        min_x: f64,
+        min_y: f64,
+        max_x: f64,
+        max_y: f64,
+    ) -> Result<Vec<T>> {
+        self.query::<T>().bbox(min_x, min_y, max_x, max_y).all()
+    }
+
+    /// Fetch the records in the named layer whose geometry intersects a bounding box, see [GeoPackage::get_in_bbox]
+    pub fn get_in_bbox_from<'a, T: GPKGModel<'a>>(
+        &self,
+        layer_name: &str,
+        min_x: f64,
+        min_y: f64,
+        max_x: f64,
+        max_y: f64,
+    ) -> Result<Vec<T>> {
+        self.query::<T>()
+            .layer(layer_name)
+            .bbox(min_x, min_y, max_x, max_y)
+            .all()
+    }
+
+    /// Fetch the records whose geometry has a spatial relationship to another geometry, such as the parcels
+    /// containing a point.
+    ///
+    /// Candidates are found with the layer's spatial index if it has one, and the relationship is
+    /// checked exactly on the decoded geometries. See [Query::spatial] to combine this with other conditions.
+    /// # Errors
+    /// Returns [Error::NoGeometryColumn] if the type doesn't have a geometry column.
+    /// # Examples
+    /// ```
+    /// # use gpkg::{GeoPackage, GPKGModel, SpatialPredicate};
+    /// # use gpkg::types::GPKGPoint;
+    /// # use geo_types::{point, polygon};
+    /// # use tempfile::tempdir;
+    /// # let dir = tempdir().unwrap();
+    /// # let path = dir.path().join("get_spatial.gpkg");
+    /// # let mut gp = GeoPackage::create(path).unwrap();
+    /// #[derive(GPKGModel)]
+    /// #[layer_name = "hydrants"]
+    /// struct Hydrant {
+    ///     #[geom_field("Point")]
+    ///     geom: GPKGPoint,
+    /// }
+    ///
+    /// gp.create_layer::<Hydrant>().unwrap();
+    /// gp.insert_many((0..10).map(|i| Hydrant { geom: GPKGPoint(point!(x: i as f64, y: i as f64)) })).unwrap();
+    ///
+    /// let block = polygon![(x: 0.5, y: 0.5), (x: 2.5, y: 0.5), (x: 2.5, y: 2.5), (x: 0.5, y: 2.5)];
+    /// assert_eq!(gp.get_spatial::<Hydrant, _>(SpatialPredicate::Within, block).unwrap().len(), 2);
+    ///
+    /// let nearby = gp.get_spatial::<Hydrant, _>(SpatialPredicate::PlanarDistanceWithin(1.5), point!(x: 5.0, y: 5.0)).unwrap();
+    /// assert_eq!(nearby.len(), 3);
+    /// ```
+    pub fn get_spatial<'a, T: GPKGModel<'a>, G: Into<geo_types::Geometry<f64>>>(
+        &self,
+        predicate: SpatialPredicate,
+        geometry: G,
+    ) -> Result<Vec<T>> {
+        self.query::<T>().spatial(predicate, geometry).all()
+    }
+
+    /// Fetch the records in the named layer with a spatial relationship to a geometry, see [GeoPackage::get_spatial]
+    pub fn get_spatial_from<'a, T: GPKGModel<'a>, G: Into<geo_types::Geometry<f64>>>(
+        &self,
+        layer_name: &str,
+        predicate: SpatialPredicate,
+        geometry: G,
+    ) -> Result<Vec<T>> {
+        self.query::<T>()
+            .layer(layer_name)
+            .spatial(predicate, geometry)
+            .all()
+    }
+
+    /// Fetch the `k` records nearest to a point along with their distances, nearest first.
+    ///
+    /// The distances are in meters if the layer has a geographic spatial reference system.
+    /// See [Query::nearest] for the details and to combine this with other conditions.
+    pub fn nearest<'a, T: GPKGModel<'a>>(
+        &self,
+        point: geo_types::Point<f64>,
+        k: usize,
+    ) -> Result<Vec<(T, f64)>> {
+        self.query::<T>().nearest(point, k)
+    }
+
+    /// Fetch the `k` records in the named layer nearest to a point, see [GeoPackage::nearest]
+    pub fn nearest_from<'a, T: GPKGModel<'a>>(
+        &self,
+        layer_name: &str,
+        point: geo_types::Point<f64>,
+        k: usize,
+    ) -> Result<Vec<(T, f64)>> {
+        self.query::<T>().layer(layer_name).nearest(point, k)
+    }
+
+    /// The number of records in the layer for a type, which is counted without reading any of them.
+    ///
+    /// Aggregates of the values in a column are available with [Query::min], [Query::max], [Query::sum] and [Query::avg].
+    /// # Examples
+    /// ```
+    /// # use gpkg::{GeoPackage, GPKGModel};
+    /// # use rusqlite::params;
+    /// # use tempfile::tempdir;
+    /// # let dir = tempdir().unwrap();
+    /// # let path = dir.path().join("count.gpkg");
+    /// # let mut gp = GeoPackage::create(path).unwrap();
+    /// #[derive(GPKGModel)]
+    /// struct Item {
+    ///     length: f64,
+    /// }
+    ///
+    /// gp.create_layer::<Item>().unwrap();
+    /// gp.insert_many((0..10).map(|i| Item { length: i as f64 })).unwrap();
+    ///
+    /// assert_eq!(gp.count::<Item>().unwrap(), 10);
+    /// assert_eq!(gp.count_where::<Item>("length >= ?", params![5.0]).unwrap(), 5);
+    /// assert!(!gp.exists_where::<Item>("length > ?", params![100.0]).unwrap());
+    /// ```
+    pub fn count<'a, T: GPKGModel<'a>>(&self) -> Result<i64> {
+        self.query::<T>().count()
+    }
+
+    /// The number of records matching a predicate with placeholders for the given parameters, see [GeoPackage::count]
+    pub fn count_where<'a, T: GPKGModel<'a>>(
+        &self,
+        predicate: &str,
+        params: &[&dyn rusqlite::ToSql],
+    ) -> Result<i64> {
+        self.query::<T>().where_sql_with(predicate, params).count()
+    }
+
+    /// Whether any records match a predicate with placeholders for the given parameters, see [GeoPackage::count]
+    pub fn exists_where<'a, T: GPKGModel<'a>>(
+        &self,
+        predicate: &str,
+        params: &[&dyn rusqlite::ToSql],
+    ) -> Result<bool> {
+        self.query::<T>().where_sql_with(predicate, params).exists()
+    }
+
+    /// Start a query on the layer for a type, which can read records lazily or in batches
+    /// instead of collecting every record in the layer at once.
+    /// # Examples
+    /// ```
+    /// # use gpkg::{GeoPackage, GPKGModel};
+    /// # use tempfile::tempdir;
+    /// # let dir = tempdir().unwrap();
+    /// # let path = dir.path().join("query.gpkg");
+    /// # let mut gp = GeoPackage::create(path).unwrap();
+    /// #[derive(GPKGModel)]
+    /// struct Item {
+    ///     length: f64,
+    /// }
+    ///
+    /// gp.create_layer::<Item>().unwrap();
+    /// gp.insert_many((0..10).map(|i| Item { length: i as f64 })).unwrap();
+    ///
+    /// let mut batches = 0;
+    /// gp.query::<Item>()
+    ///     .for_each_batch(4, |items| {
+    ///         assert!(items.len() <= 4);
+    ///         batches += 1;
+    ///         Ok(())
+    ///     })
+    ///     .unwrap();
+    /// assert_eq!(batches, 3);
+    /// ```
+    pub fn query<'a, T: GPKGModel<'a>>(&self) -> Query<'_, T> {
+        Query::new(&self.conn)
+    }
+
+    /// Check that the layer for a type can be read into it, comparing the name, type and nullability of each column
+    /// as well as the type and Z/M flags of the geometry column.
+    ///
+    /// Columns in the layer that don't have a corresponding field are ignored.
+    /// # Errors
+    /// Returns [Error::SchemaMismatch] listing every incompatibility found.
+    /// # Examples
+    /// ```
+    /// # use gpkg::{GeoPackage, GPKGModel};
+    /// # use tempfile::tempdir;
+    /// # let dir = tempdir().unwrap();
+    /// # let path = dir.path().join("check_layer_schema.gpkg");
+    /// # let gp = GeoPackage::create(path).unwrap();
+    /// #[derive(GPKGModel)]
+    /// #[layer_name = "items"]
+    /// struct Item {
+    ///     length: f64,
+    /// }
+    ///
+    /// #[derive(GPKGModel)]
+    /// #[layer_name = "items"]
+    /// struct NamedItem {
+    ///     name: String,
+    /// }
+    ///
+    /// gp.create_layer::<Item>().unwrap();
+    ///
+    /// assert!(gp.check_layer_schema::<Item>().is_ok());
+    /// assert!(gp.check_layer_schema::<NamedItem>().is_err());
+    /// ```
+    pub fn check_layer_schema<'a, T: GPKGModel<'a>>(&self) -> Result<()> {
+        self.check_layer_schema_of::<T>(T::get_gpkg_layer_name())
+    }
+
+    /// Check that the named layer can be read into a type, see [GeoPackage::check_layer_schema]
+    pub fn check_layer_schema_of<'a, T: GPKGModel<'a>>(&self, layer_name: &str) -> Result<()> {
+        schema::check_compatibility(
+            &self.conn,
+            layer_name,
+            &T::get_columns(),
+            T::get_geometry_column().as_ref(),
+        )
+    }
+
+    /// Fetch all records in a layer that may not have been created by this crate.
+    ///
+    /// The layer schema is checked with [GeoPackage::check_layer_schema] before reading, and
+    /// fields are mapped to columns by name, so the column order and any extra columns don't matter.
+    /// # Examples
+    /// ```
+    /// # use gpkg::{GeoPackage, GPKGModel};
+    /// # use tempfile::tempdir;
+    /// # let dir = tempdir().unwrap();
+    /// # let path = dir.path().join("get_all_checked.gpkg");
+    /// # let gp = GeoPackage::create(path).unwrap();
+    /// gp.conn.execute_batch(
+    ///     "CREATE TABLE items (fid INTEGER PRIMARY KEY, note TEXT, length DOUBLE NOT NULL);
+    ///     INSERT INTO gpkg_contents (table_name, data_type) VALUES ('items', 'attributes');
+    ///     INSERT INTO items (note, length) VALUES ('long', 25.0);",
+    /// ).unwrap();
+    ///
+    /// #[derive(GPKGModel)]
+    /// #[layer_name = "items"]
+    /// struct Item {
+    ///     length: f64,
+    /// }
+    ///
+    /// let records = gp.get_all_checked::<Item>().unwrap();
+    /// assert_eq!(records[0].length, 25.0);
+    /// ```
+    pub fn get_all_checked<'a, T: GPKGModel<'a>>(&self) -> Result<Vec<T>> {
+        self.get_all_checked_from(T::get_gpkg_layer_name())
+    }
+
+    /// Fetch all records in the named layer after checking its schema, see [GeoPackage::get_all_checked]
+    pub fn get_all_checked_from<'a, T: GPKGModel<'a>>(&self, layer_name: &str) -> Result<Vec<T>> {
+        self.check_layer_schema_of::<T>(layer_name)?;
+        let sql = format!("SELECT * FROM {};", sql::quote_identifier(layer_name));
+        let mut stmt = self.conn.prepare(&sql)?;
+        let mut out_vec = Vec::new();
+        let rows = stmt.query_map([], |row| T::from_row_named(row))?;
+        for r in rows {
+            out_vec.push(r?)
+        }
+        Ok(out_vec)
+    }
+
+    /// Fetch the records that match the given predicate from a layer that may not have been created by this crate.
+    ///
+    /// See [GeoPackage::get_all_checked] for how the layer is checked and read.
+    pub fn get_where_checked<'a, T: GPKGModel<'a>>(&self, predicate: &str) -> Result<Vec<T>> {
+        self.get_where_checked_from(T::get_gpkg_layer_name(), predicate)
+    }
+
+    /// Fetch the records in the named layer that match the given predicate after checking its schema,
+    /// see [GeoPackage::get_all_checked]
+    pub fn get_where_checked_from<'a, T: GPKGModel<'a>>(
+        &self,
+        layer_name: &str,
+        predicate: &str,
+    ) -> Result<Vec<T>> {
+        self.check_layer_schema_of::<T>(layer_name)?;
+        let sql = format!(
+            "SELECT * FROM {} WHERE {};",
+            sql::quote_identifier(layer_name),
+            predicate
+        );
+        let mut stmt = self.conn.prepare(&sql)?;
+        let mut out_vec = Vec::new();
+        let rows = stmt.query_map([], |row| T::from_row_named(row))?;
+        for r in rows {
+            out_vec.push(r?)
+        }
+        Ok(out_vec)
+    }
+
+    /// Fetch every record of a layer as a [Feature], using the schema of the layer in the GeoPackage
+    /// instead of a [GPKGModel], so any layer can be read by name.
+    /// # Errors
+    /// Returns [Error::UnknownLayer] if there is no table with the given name.
+    /// # Examples
+    /// ```
+    /// # use gpkg::{Feature, GeoPackage, GPKGModel};
+    /// # use gpkg::types::GPKGPoint;
+    /// # use tempfile::tempdir;
+    /// # let dir = tempdir().unwrap();
+    /// # let path = dir.path().join("get_features.gpkg");
+    /// # let mut gp = GeoPackage::create(path).unwrap();
+    /// #[derive(GPKGModel)]
+    /// #[layer_name = "stops"]
+    /// struct Stop {
+    ///     name: String,
+    ///     #[geom_field("Point")]
+    ///     geom: GPKGPoint,
+    /// }
+    ///
+    /// gp.create_layer::<Stop>().unwrap();
+    /// gp.insert_record(&Stop { name: String::from("Union"), geom: GPKGPoint(geo_types::point!(x: 1.0, y: 2.0)) }).unwrap();
+    ///
+    /// let mut feature = Feature::new();
+    /// feature.set("name", "Central").unwrap();
+    /// feature.geometry = Some(geo_types::point!(x: 3.0, y: 4.0).into());
+    /// gp.insert_feature_into("stops", &feature).unwrap();
+    ///
+    /// let features = gp.get_features_from("stops").unwrap();
+    /// assert_eq!(features[1].fid, Some(2));
+    /// assert_eq!(features[1].get_as::<String>("name").unwrap(), "Central");
+    /// ```
+    pub fn get_features_from(&self, layer_name: &str) -> Result<Vec<Feature>> {
+        self.get_features_where_from(layer_name, "1", &[])
+    }
+
+    /// Fetch the records of a layer matching a predicate with placeholders for the given parameters as features,
+    /// see [GeoPackage::get_features_from]
+    pub fn get_features_where_from(
+        &self,
+        layer_name: &str,
+        predicate: &str,
+        params: &[&dyn rusqlite::ToSql],
+    ) -> Result<Vec<Feature>> {
+        let schema = feature::LayerSchema::read(&self.conn, layer_name)?;
+        let mut stmt = self.conn.prepare(&schema.select_sql(Some(predicate)))?;
+        let mut rows = stmt.query(params)?;
+        let mut out_vec = Vec::new();
+        while let Some(row) = rows.next()? {
+            out_vec.push(schema.read_feature(row)?);
+        }
+        Ok(out_vec)
+    }
+
+    /// Fetch the record with the given fid from a layer as a feature, which is None if there is no such record
+    pub fn get_feature_from(&self, layer_name: &str, fid: i64) -> Result<Option<Feature>> {
+        let schema = feature::LayerSchema::read(&self.conn, layer_name)?;
+        let predicate = schema.fid_predicate();
+        Ok(self
+            .get_features_where_from(layer_name, &predicate, params![fid])?
+            .pop())
+    }
+
+    /// Insert a feature into a layer, returning its fid, which is the fid of the feature if it has one.
+    ///
+    /// Columns that aren't in the properties of the feature get their default values. The geometry is written
+    /// with the spatial reference system of the layer's geometry column.
+    /// # Errors
+    /// Returns [Error::UnknownColumn] if a property isn't a column of the layer,
+    /// or [Error::NoGeometryColumn] if the feature has a geometry but the layer doesn't.
+    pub fn insert_feature_into(&self, layer_name: &str, feature: &Feature) -> Result<i64> {
+        let schema = feature::LayerSchema::read(&self.conn, layer_name)?;
+        in_savepoint(&self.conn, || {
+            let (fid, written) = schema.write_feature(&self.conn, feature, None)?;
+            layer_changed(&self.conn, layer_name, written.as_ref(), None)?;
+            Ok(fid)
+        })
+    }
+
+    /// Insert features into a layer in a single transaction, returning the fid of each one,
+    /// see [GeoPackage::insert_feature_into]
+    pub fn insert_features_into<I>(&mut self, layer_name: &str, features: I) -> Result<Vec<i64>>
+    where
+        I: IntoIterator,
+        I::Item: Borrow<Feature>,
+    {
+        let tx = self.conn.transaction()?;
+        let schema = feature::LayerSchema::read(&tx, layer_name)?;
+        let mut written_extent = None;
+        let mut fids = Vec::new();
+        for f in features {
+            let (fid, written) = schema.write_feature(&tx, f.borrow(), None)?;
+            fids.push(fid);
+            written_extent = extent::union(written_extent, written);
+        }
+        layer_changed(&tx, layer_name, written_extent.as_ref(), None)?;
+        tx.commit()?;
+        Ok(fids)
+    }
+
+    /// Update the record with the given fid from a feature, ignoring the fid of the feature itself.
+    ///
+    /// The geometry and the columns in the properties of the feature are written, any other columns keep their values.
+    /// # Errors
+    /// Returns [Error::UnknownFeature] if there is no record with the fid, along with the errors from
+    /// [GeoPackage::insert_feature_into].
+    pub fn update_feature_in(&self, layer_name: &str, fid: i64, feature: &Feature) -> Result<()> {
+        let schema = feature::LayerSchema::read(&self.conn, layer_name)?;
+        in_savepoint(&self.conn, || {
+            let replaced = extent::rows_extent(
+                &self.conn,
+                layer_name,
+                &schema.fid_predicate(),
+                params![fid],
+            )?;
+            let (_, written) = schema.write_feature(&self.conn, feature, Some(fid))?;
+            layer_changed(&self.conn, layer_name, written.as_ref(), replaced.as_ref())
+        })
+    }
+
+    /// Delete the record with the given fid from a layer of any schema, returning whether there was one
+    pub fn delete_feature_from(&self, layer_name: &str, fid: i64) -> Result<bool> {
+        let schema = feature::LayerSchema::read(&self.conn, layer_name)?;
+        let predicate = schema.fid_predicate();
+        in_savepoint(&self.conn, || {
+            let removed = extent::rows_extent(&self.conn, layer_name, &predicate, params![fid])?;
+            let deleted = self.conn.execute(
+                &format!(
+                    "DELETE FROM {} WHERE {}",
+                    sql::quote_identifier(layer_name),
+                    predicate
+                ),
+                params![fid],
+            )?;
+            if deleted > 0 {
+                layer_changed(&self.conn, layer_name, None, removed.as_ref())?;
+            }
+            Ok(deleted > 0)
+        })
+    }
+
+    /// List every layer registered in gpkg_contents, ordered by name, including tile and extension layers
+    /// # Examples
+    /// ```
+    /// # use gpkg::{GeoPackage, GPKGDataType, GPKGModel};
+    /// # use gpkg::types::GPKGPoint;
+    /// # use tempfile::tempdir;
+    /// # let dir = tempdir().unwrap();
+    /// # let path = dir.path().join("list_layers.gpkg");
+    /// # let gp = GeoPackage::create(path).unwrap();
+    /// #[derive(GPKGModel)]
+    /// #[layer_name = "stops"]
+    /// struct Stop {
+    ///     name: String,
+    ///     #[geom_field("Point")]
+    ///     geom: GPKGPoint,
+    /// }
+    ///
+    /// gp.create_layer::<Stop>().unwrap();
+    ///
+    /// let layers = gp.list_layers().unwrap();
+    /// assert_eq!(layers[0].name, "stops");
+    /// assert_eq!(layers[0].data_type, GPKGDataType::Features);
+    /// assert_eq!(layers[0].srs_id, Some(4326));
+    ///
+    /// let columns = gp.get_layer_columns("stops").unwrap();
+    /// assert_eq!(columns[0].name, "fid");
+    /// assert!(columns[0].primary_key);
+    /// assert_eq!(gp.get_geometry_column("stops").unwrap().unwrap().geometry_type, "POINT");
+    /// ```
+    pub fn list_layers(&self) -> Result<Vec<LayerDefinition>> {
+        schema::get_layers(&self.conn, None)
+    }
+
+    /// The entry for a layer in gpkg_contents
+    /// # Errors
+    /// Returns [Error::UnknownLayer] if there is no layer with the given name.
+    pub fn get_layer_definition(&self, layer_name: &str) -> Result<LayerDefinition> {
+        schema::get_layers(&self.conn, Some(layer_name))?
+            .pop()
+            .ok_or_else(|| Error::UnknownLayer(layer_name.to_owned()))
+    }
+
+    /// The columns of a layer's table in the order they are declared, including the fid and geometry columns
+    /// # Errors
+    /// Returns [Error::UnknownLayer] if there is no table with the given name.
+    pub fn get_layer_columns(&self, layer_name: &str) -> Result<Vec<TableColumn>> {
+        let columns = schema::get_table_columns(&self.conn, layer_name)?;
+        if columns.is_empty() {
+            return Err(Error::UnknownLayer(layer_name.to_owned()));
+        }
+        Ok(columns)
+    }
+
+    /// The geometry column of a layer from gpkg_geometry_columns, which is None for layers without one
+    pub fn get_geometry_column(
+        &self,
+        layer_name: &str,
+    ) -> Result<Option<GeometryColumnDefinition>> {
+        schema::get_geometry_column(&self.conn, layer_name)
+    }
+
+    /// Set the human readable identifier of a layer in gpkg_contents, which is shown by clients such as QGIS
+    /// # Errors
+    /// Returns [Error::UnknownLayer] if there is no layer with the given name.
+    pub fn set_layer_identifier(&self, layer_name: &str, identifier: Option<&str>) -> Result<()> {
+        let updated = self.conn.execute(
+            "UPDATE gpkg_contents SET identifier = ?1 WHERE table_name = ?2",
+            params![identifier, layer_name],
+        )?;
+        if updated == 0 {
+            return Err(Error::UnknownLayer(layer_name.to_owned()));
+        }
+        Ok(())
+    }
+
+    /// Set the description of a layer in gpkg_contents
+    /// # Errors
+    /// Returns [Error::UnknownLayer] if there is no layer with the given name.
+    /// # Examples
+    /// ```
+    /// # use gpkg::{GeoPackage, GPKGModel};
+    /// # use tempfile::tempdir;
+    /// # let dir = tempdir().unwrap();
+    /// # let path = dir.path().join("set_layer_description.gpkg");
+    /// # let gp = GeoPackage::create(path).unwrap();
+    /// /// Centerlines of every public road
+    /// #[derive(GPKGModel)]
+    /// #[layer_name = "roads"]
+    /// #[gpkg(identifier = "Roads")]
+    /// struct Road {
+    ///     lanes: i64,
+    /// }
+    ///
+    /// gp.create_layer::<Road>().unwrap();
+    /// gp.set_layer_description("roads", "Centerlines of every road").unwrap();
+    /// ```
+    pub fn set_layer_description(&self, layer_name: &str, description: &str) -> Result<()> {
+        let updated = self.conn.execute(
+            "UPDATE gpkg_contents SET description = ?1 WHERE table_name = ?2",
+            params![description, layer_name],
+        )?;
+        if updated == 0 {
+            return Err(Error::UnknownLayer(layer_name.to_owned()));
+        }
+        Ok(())
+    }
+
+    /// The extent of a layer as recorded in gpkg_contents, which is None if it hasn't been set.
+    ///
+    /// The extent is kept up to date as records are inserted, updated and deleted through this crate.
+    /// # Errors
+    /// Returns [Error::UnknownLayer] if there is no layer with the given name.
+    pub fn get_layer_extent(&self, layer_name: &str) -> Result<Option<Extent>> {
+        extent::layer_extent(&self.conn, layer_name)
+    }
+
+    /// Compute the extent of a layer from the geometries it contains and store it in gpkg_contents,
+    /// returning the new extent.
+    ///
+    /// This repairs the extent of layers that were written without maintaining it.
+    /// The extent is cleared if the layer has no geometries.
+    /// # Errors
+    /// Returns [Error::UnknownLayer] if there is no layer with the given name.
+    /// # Examples
+    /// ```
+    /// # use gpkg::{GeoPackage, GPKGModel};
+    /// # use gpkg::types::GPKGPoint;
+    /// # use tempfile::tempdir;
+    /// # let dir = tempdir().unwrap();
+    /// # let path = dir.path().join("recompute_extent.gpkg");
+    /// # let gp = GeoPackage::create(path).unwrap();
+    /// #[derive(GPKGModel)]
+    /// #[layer_name = "stops"]
+    /// struct Stop {
+    ///     #[geom_field("Point")]
+    ///     geom: GPKGPoint,
+    /// }
+    ///
+    /// gp.create_layer::<Stop>().unwrap();
+    /// gp.insert_record(&Stop { geom: GPKGPoint(geo_types::point!(x: 1.0, y: 2.0)) }).unwrap();
+    /// gp.insert_record(&Stop { geom: GPKGPoint(geo_types::point!(x: -3.0, y: 4.0)) }).unwrap();
+    ///
+    /// let extent = gp.recompute_extent("stops").unwrap().unwrap();
+    /// assert_eq!((extent.min_x, extent.max_y), (-3.0, 4.0));
+    /// assert_eq!(gp.get_layer_extent("stops").unwrap(), Some(extent));
+    /// ```
+    pub fn recompute_extent(&self, layer_name: &str) -> Result<Option<Extent>> {
+        extent::layer_extent(&self.conn, layer_name)?;
+        extent::recompute_layer_extent(&self.conn, layer_name)
+    }
+
+    /// Register the allowed values of an enum as a constraint on a column, using the
+    /// [schema extension](https://www.geopackage.org/spec130/#extension_schema).
+    ///
+    /// The tables for the extension are created if they don't already exist.
+    /// # Examples
+    /// ```
+    /// # use gpkg::{GeoPackage, GPKGEnum, GPKGModel};
+    /// # use tempfile::tempdir;
+    /// # let dir = tempdir().unwrap();
+    /// # let path = dir.path().join("add_enum_constraint.gpkg");
+    /// # let mut gp = GeoPackage::create(path).unwrap();
+    /// #[derive(GPKGEnum)]
+    /// enum Surface {
+    ///     Paved,
+    ///     Gravel,
+    /// }
+    ///
+    /// #[derive(GPKGModel)]
+    /// #[layer_name = "roads"]
+    /// struct Road {
+    ///     surface: Surface,
+    /// }
+    ///
+    /// gp.create_layer::<Road>().unwrap();
+    /// gp.add_enum_constraint::<Surface>("roads", "surface").unwrap();
+    /// ```
+    pub fn add_enum_constraint<E: GPKGEnum>(
+        &mut self,
+        table_name: &str,
+        column_name: &str,
+    ) -> Result<()> {
+        let tx = self.conn.transaction()?;
+        tx.execute(CREATE_DATA_COLUMNS_TABLE, [])?;
+        tx.execute(CREATE_DATA_COLUMN_CONSTRAINTS_TABLE, [])?;
+        for table in ["gpkg_data_columns", "gpkg_data_column_constraints"] {
+            extensions::register_extension(
+                &tx,
+                Some(table),
+                None,
+                extensions::SCHEMA_EXTENSION_NAME,
+                extensions::SCHEMA_EXTENSION_DEFINITION,
+                "read-write",
+            )?;
+        }
+        for (value, description) in E::variants() {
+            tx.execute(
+                "INSERT OR IGNORE INTO gpkg_data_column_constraints (constraint_name, constraint_type, value, description) VALUES (?1, 'enum', ?2, ?3)",
+                params![E::CONSTRAINT_NAME, value, description],
+            )?;
+        }
+        tx.execute(
+            "INSERT INTO gpkg_data_columns (table_name, column_name, name, constraint_name) VALUES (?1, ?2, ?2, ?3)
+            ON CONFLICT (table_name, column_name) DO UPDATE SET constraint_name = excluded.constraint_name",
+            params![table_name, column_name, E::CONSTRAINT_NAME],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Add a new spatial reference system to the geopackage, given either as a [SpatialRefSys]
+    /// or as the EPSG code of one in the bundled catalog, see [SpatialRefSys::from_epsg]
+    /// # Errors
+    /// Returns [Error::UnknownEpsgCode] for an EPSG code that isn't in the catalog,
+    /// or an [Error::SQLiteError] if there already is a system with the same srs_id.
+    /// # Examples
+    /// ```
+    /// # use gpkg::{GeoPackage, SpatialRefSys};
+    /// # use tempfile::tempdir;
+    /// # let dir = tempdir().unwrap();
+    /// # let path = dir.path().join("new_srs.gpkg");
+    /// let gp = GeoPackage::create(path).unwrap();
+    /// gp.new_srs(3857).unwrap();
+    /// gp.new_srs(SpatialRefSys::new(100000, "Site grid").with_organization("ACME", 7)).unwrap();
+    /// assert_eq!(gp.get_srs(3857).unwrap().unwrap().name, "WGS 84 / Pseudo-Mercator");
+    /// ```
+    pub fn new_srs<S: SrsSource>(&self, srs: S) -> Result<()> {
+        srs::insert_srs(&self.conn, &*srs.to_srs()?)
+    }
+
+    /// List the spatial reference systems in the GeoPackage, ordered by srs_id
+    /// # Examples
+    /// ```
+    /// # use gpkg::GeoPackage;
+    /// # use tempfile::tempdir;
+    /// # let dir = tempdir().unwrap();
+    /// # let path = dir.path().join("list_srs.gpkg");
+    /// let gp = GeoPackage::create(path).unwrap();
+    /// let ids: Vec<i64> = gp.list_srs().unwrap().iter().map(|s| s.id).collect();
+    /// assert_eq!(ids, vec![-1, 0, 4326]);
+    /// ```
+    pub fn list_srs(&self) -> Result<Vec<SpatialRefSys>> {
+        srs::list_srs(&self.conn)
+    }
+
+    /// Get a spatial reference system by its srs_id, which is None if the GeoPackage doesn't have it
+    pub fn get_srs(&self, srs_id: i64) -> Result<Option<SpatialRefSys>> {
+        srs::get_srs(&self.conn, srs_id)
+    }
+
+    /// Replace the name, organization, definition and description of the spatial reference system with the same srs_id
+    /// # Errors
+    /// Returns [Error::UnknownSrs] if the GeoPackage doesn't have the system.
+    pub fn update_srs(&self, srs: &SpatialRefSys) -> Result<()> {
+        srs::update_srs(&self.conn, srs)
+    }
+
+    /// Remove a spatial reference system that isn't used by any layer
+    /// # Errors
+    /// Returns [Error::UnknownSrs] if the GeoPackage doesn't have the system, [Error::SrsInUse] if a layer or
+    /// tile matrix set still uses it, or [Error::RequiredSrs] for the systems every GeoPackage has to contain.
+    /// # Examples
+    /// ```
+    /// # use gpkg::GeoPackage;
+    /// # use tempfile::tempdir;
+    /// # let dir = tempdir().unwrap();
+    /// # let path = dir.path().join("delete_srs.gpkg");
+    /// let gp = GeoPackage::create(path).unwrap();
+    /// gp.new_srs(3857).unwrap();
+    /// let mut web_mercator = gp.get_srs(3857).unwrap().unwrap();
+    /// web_mercator.description = String::from("Web Mercator");
+    /// gp.update_srs(&web_mercator).unwrap();
+    /// assert_eq!(gp.get_srs(3857).unwrap(), Some(web_mercator));
+    /// gp.delete_srs(3857).unwrap();
+    /// assert_eq!(gp.get_srs(3857).unwrap(), None);
+    /// ```
+    pub fn delete_srs(&self, srs_id: i64) -> Result<()> {
+        srs::delete_srs(&self.conn, srs_id)
+    }
+
+    /// Retrieve the srs_id for a layer, which is None if the layer doesn't have one, e.g. an attributes layer
+    /// # Errors
+    /// Returns [Error::UnknownLayer] if the layer isn't in gpkg_contents.
+    pub fn get_layer_srs_id(&self, layer_name: &str) -> Result<Option<i64>> {
+        srs::get_layer_srs_id(&self.conn, layer_name)
+    }
+
+    /// Update the SRS ID for a layer, in gpkg_contents, gpkg_geometry_columns and the header of every geometry in the layer.
+    ///
+    /// The coordinates aren't transformed, so this corrects the system a layer was declared with rather than reprojecting it.
+    /// # Errors
+    /// Returns [Error::UnknownLayer] if the layer isn't in gpkg_contents,
+    /// or [Error::UnknownSrs] if the GeoPackage doesn't have the system.
+    pub fn update_layer_srs_id(&mut self, layer_name: &str, srs_id: i64) -> Result<()> {
+        let tx = self.conn.transaction()?;
+        srs::set_layer_srs_id(&tx, layer_name, srs_id)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Close the geopackage
+    /// # Examples
+    /// ```ignore
+    /// # use std::path::Path;
+    /// let path = Path::new("./test.gpkg");
+    /// let gp = GeoPackage::create(path).unwrap();
+    /// // do some things with the GeoPackage
+    /// gp.close();
+    /// ```
+    pub fn close(self) {
+        self.conn.close().unwrap();
+    }
+
+    /// Open a geopackage, doing validation of the GeoPackage internals to ensure that operation will work correctly.
     pub fn open<P: AsRef<Path>>(path: P) -> Result<GeoPackage> {
         let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_WRITE)?;
         // check the user application_id and user_version as per requirement 2
@@ -295,312 +1721,2803 @@ impl GeoPackage {
         if application_id != 0x47504B47 {
             return Err(Error::ValidationError);
         }
-        // what do we do with the user version?
-        // it doesn't seem safe to just fail if this doesn't match something
-        // maybe this should just have an acceptable range?
-        let _user_version: u32 =
-            conn.query_row("SELECT * FROM pragma_user_version()", [], |row| row.get(0))?;
-        // integrity check from requirement 6
-        let integrity_check: String =
-            conn.query_row("SELECT * FROM pragma_integrity_check()", [], |row| {
-                row.get(0)
-            })?;
-        if integrity_check.as_str() != "ok" {
-            return Err(Error::ValidationError);
+        // what do we do with the user version?
+        // it doesn't seem safe to just fail if this doesn't match something
+        // maybe this should just have an acceptable range?
+        let _user_version: u32 =
+            conn.query_row("SELECT * FROM pragma_user_version()", [], |row| row.get(0))?;
+        // integrity check from requirement 6
+        let integrity_check: String =
+            conn.query_row("SELECT * FROM pragma_integrity_check()", [], |row| {
+                row.get(0)
+            })?;
+        if integrity_check.as_str() != "ok" {
+            return Err(Error::ValidationError);
+        }
+        // check that there are no foreign keys as per spec requirement 7
+        // use a block to force a drop of stmt and release the borrow
+        // so that we can move conn
+        {
+            let mut stmt = conn.prepare("SELECT * FROM pragma_foreign_key_check()")?;
+            let mut rows = stmt.query([])?;
+            if rows.next()?.is_some() {
+                return Err(Error::ValidationError);
+            }
+        }
+
+        functions::register_functions(&conn)?;
+        Ok(GeoPackage { conn })
+    }
+}
+
+// keep the metadata of a layer current after records in it have been written or removed
+fn layer_changed(
+    conn: &Connection,
+    layer_name: &str,
+    written: Option<&Extent>,
+    removed: Option<&Extent>,
+) -> Result<()> {
+    conn.execute(sql::UPDATE_LAST_CHANGE, params![layer_name])?;
+    extent::update_layer_extent(conn, layer_name, written, removed)
+}
+
+// run a write together with its layer_changed call so that either both or neither take effect,
+// with a savepoint rather than a transaction since it only needs a shared connection and nests
+// inside a transaction the caller already started
+fn in_savepoint<T>(conn: &Connection, write: impl FnOnce() -> Result<T>) -> Result<T> {
+    conn.execute_batch(sql::BEGIN_WRITE)?;
+    match write() {
+        Ok(value) => {
+            conn.execute_batch(sql::COMMIT_WRITE)?;
+            Ok(value)
+        }
+        Err(e) => {
+            // the error of the write says what went wrong, so it's returned even if the rollback fails too
+            let _ = conn.execute_batch(sql::ROLLBACK_WRITE);
+            Err(e)
+        }
+    }
+}
+
+// the srs_id of the geometry column of a layer, which the geometries written to it are encoded with
+fn geometry_srs_id(conn: &Connection, layer_name: &str) -> Result<Option<i64>> {
+    Ok(schema::get_geometry_column(conn, layer_name)?.map(|g| g.srs_id))
+}
+
+// the parameters of a record, with the geometry replaced by the one from srs::layer_geometry_blob if there is one
+fn record_params<'r, 'a, T: GPKGModel<'a>>(
+    record: &'r T,
+    geometry: Option<&'r Vec<u8>>,
+) -> Vec<&'r dyn rusqlite::ToSql> {
+    let mut params = record.as_params();
+    if let (Some(blob), Some(index)) = (geometry, extent::geometry_param_index::<T>()) {
+        params[index] = blob;
+    }
+    params
+}
+
+#[cfg(test)]
+mod tests {
+    use geo_types::*;
+    use std::borrow::Cow;
+    use std::fs;
+    use tempfile::tempdir;
+
+    use crate::types::*;
+
+    use super::*;
+
+    #[derive(GPKGModel, Debug)]
+    #[layer_name = "test"]
+    struct TestTableGeom {
+        start_node: Option<i64>,
+        end_node: i64,
+        rev_cost: String,
+        // #[geom_field("LineStringZ")]
+        #[geom_field("LineStringZ")]
+        geom: types::GPKGLineStringZ,
+    }
+
+    #[derive(GPKGModel, Debug, PartialEq, Eq)]
+    #[layer_name = "test"]
+    struct TestTableAttr {
+        field1: Option<i64>,
+        field2: i64,
+        field3: String,
+    }
+
+    #[test]
+    fn create_table() {
+        let dir = tempdir().unwrap();
+        let filename = dir.path().join("create.gpkg");
+
+        let gp = GeoPackage::create(&filename).unwrap();
+        gp.create_layer::<TestTableGeom>().unwrap();
+
+        // how do we get a check that the table exists?
+        // make sure that we've got something in gpkg_contents
+
+        gp.close();
+        fs::remove_file(filename).unwrap();
+    }
+
+    #[test]
+    #[should_panic]
+    fn fail_on_existing() {
+        let dir = tempdir().unwrap();
+        let filename = dir.path().join("fail_exists.gpkg");
+
+        let gp = GeoPackage::create(&filename).unwrap();
+        gp.create_layer::<TestTableGeom>().unwrap();
+
+        // how do we get a check that the table exists?
+        // make sure that we've got something in gpkg_contents
+
+        gp.close();
+
+        let _shouldnt_open = GeoPackage::create(&filename).unwrap();
+
+        fs::remove_file(filename).unwrap();
+    }
+
+    #[test]
+    fn insert_row() {
+        let dir = tempdir().unwrap();
+        let filename = dir.path().join("create.gpkg");
+
+        let test_val = TestTableAttr {
+            field1: Some(999),
+            field2: 420,
+            field3: String::from("blah"),
+        };
+
+        let gp = GeoPackage::create(&filename).unwrap();
+        gp.create_layer::<TestTableAttr>().unwrap();
+        gp.insert_record(&test_val).unwrap();
+
+        // how do we get a check that the table exists?
+        // make sure that we've got something in gpkg_contents
+        let retrieved = &gp.get_all::<TestTableAttr>().unwrap();
+
+        assert_eq!(test_val, retrieved[0]);
+
+        gp.close();
+        fs::remove_file(filename).unwrap();
+    }
+
+    #[test]
+    fn insert_many() {
+        let dir = tempdir().unwrap();
+        let filename = dir.path().join("insert_many.gpkg");
+        let mut gp = GeoPackage::create(&filename).unwrap();
+        gp.create_layer::<TestTableGeom>()
+            .expect("Problem creating table");
+        let val = TestTableGeom {
+            start_node: Some(42),
+            end_node: 918,
+            rev_cost: "Test values".to_owned(),
+            geom: GPKGLineStringZ(vec![
+                GPKGPointZ {
+                    x: 40.0,
+                    y: -105.0,
+                    z: 5280.0,
+                },
+                GPKGPointZ {
+                    x: 41.0,
+                    y: -106.0,
+                    z: 5280.0,
+                },
+            ]),
+        };
+        let val2 = TestTableGeom {
+            start_node: Some(45),
+            end_node: 918,
+            rev_cost: "Test values".to_owned(),
+            geom: GPKGLineStringZ(vec![
+                GPKGPointZ {
+                    x: 40.0,
+                    y: -105.0,
+                    z: 5280.0,
+                },
+                GPKGPointZ {
+                    x: 41.0,
+                    y: -106.0,
+                    z: 5280.0,
+                },
+            ]),
+        };
+        let val3 = TestTableGeom {
+            start_node: Some(48),
+            end_node: 918,
+            rev_cost: "Test values".to_owned(),
+            geom: GPKGLineStringZ(vec![
+                GPKGPointZ {
+                    x: 40.0,
+                    y: -105.0,
+                    z: 5280.0,
+                },
+                GPKGPointZ {
+                    x: 41.0,
+                    y: -106.0,
+                    z: 5280.0,
+                },
+            ]),
+        };
+        let vec_for_insert = vec![val, val2, val3];
+        gp.insert_many(&vec_for_insert).unwrap();
+
+        gp.close();
+        let db2 = GeoPackage::open(&filename).unwrap();
+        let retrieved = db2.get_all::<TestTableGeom>().unwrap();
+        assert!(retrieved.len() == vec_for_insert.len());
+    }
+
+    #[test]
+    fn multipoint_test() {
+        #[derive(GPKGModel)]
+        struct MPTest {
+            id: i64,
+            #[geom_field("MultiPoint")]
+            geom: GPKGMultiPoint,
+        }
+
+        let filename = Path::new("./test_data/multipoint.gpkg");
+        let gp = GeoPackage::create(filename).unwrap();
+
+        gp.create_layer::<MPTest>().unwrap();
+
+        let sample = MPTest {
+            id: 99,
+            geom: GPKGMultiPoint(geo_types::MultiPoint::new(vec![
+                (coord! {x: 1.0, y: 1.0}).into(),
+                (coord! {x: 3.0, y: 3.0}).into(),
+                (coord! {x: 5.0, y: 5.0}).into(),
+                (coord! {x: 7.0, y: 7.0}).into(),
+            ])),
+        };
+
+        gp.insert_record(&sample).unwrap();
+
+        gp.close();
+    }
+
+    fn get_test_multilinestring() -> geo_types::MultiLineString<f64> {
+        let ls1: geo_types::LineString<f64> = geo_types::LineString::new(vec![
+            coord! {x: -105.0, y: 40.0},
+            coord! {x: -106.0, y: 41.5},
+            coord! {x: -107.0, y: 43.0},
+        ]);
+
+        let ls2: geo_types::LineString<f64> = geo_types::LineString::new(vec![
+            coord! {x: -15.0, y: 4.0},
+            coord! {x: -16.0, y: 4.5},
+            coord! {x: -17.0, y: 4.0},
+        ]);
+        geo_types::MultiLineString::new(vec![ls1, ls2])
+    }
+
+    #[test]
+    fn multilinestring_test() {
+        #[derive(GPKGModel)]
+        struct MPTest {
+            id: i64,
+            #[geom_field("MultiLineString")]
+            geom: GPKGMultiLineString,
+        }
+
+        let filename = Path::new("./test_data/multilinestring.gpkg");
+        let gp = GeoPackage::create(filename).unwrap();
+
+        gp.create_layer::<MPTest>().unwrap();
+
+        let test_geom = GPKGMultiLineString(get_test_multilinestring());
+        dbg!(test_geom.to_wkb().unwrap());
+
+        let sample = MPTest {
+            id: 99,
+            geom: test_geom,
+        };
+
+        gp.insert_record(&sample).unwrap();
+
+        gp.close();
+    }
+
+    fn get_test_multipolygon() -> MultiPolygon<f64> {
+        let poly1_exterior: LineString<f64> = LineString::new(vec![
+            coord! {x: -105.0, y: 40.0},
+            coord! {x: -106.0, y: 43.5},
+            coord! {x: -107.0, y: 41.0},
+            coord! {x: -105.0, y: 40.0},
+        ]);
+        let poly1 = Polygon::new(poly1_exterior, vec![]);
+
+        let poly2_exterior: LineString<f64> = LineString::new(vec![
+            coord! {x: -15.0, y: 4.0},
+            coord! {x: 16.0, y: 4.5},
+            coord! {x: -1.0, y: 10.0},
+            coord! {x: -10.0, y: 10.0},
+            coord! {x: -15.0, y: 4.0},
+        ]);
+
+        let poly2_interior: LineString<f64> = LineString::new(vec![
+            coord! {x: -1.53, y: 4.999},
+            coord! {x: 1.609, y: 5.67},
+            coord! {x: -2.345, y: 6.2},
+            coord! {x: -1.53, y: 4.999},
+        ]);
+        let poly2 = Polygon::new(poly2_exterior, vec![poly2_interior]);
+
+        MultiPolygon::new(vec![poly1, poly2])
+    }
+
+    #[test]
+    fn multipolygon_test() {
+        #[derive(GPKGModel)]
+        struct MPTest {
+            id: i64,
+            #[geom_field("MultiPolygon")]
+            geom: GPKGMultiPolygon,
+        }
+
+        let filename = Path::new("./test_data/multipolygon.gpkg");
+        let gp = GeoPackage::create(filename).unwrap();
+
+        gp.create_layer::<MPTest>().unwrap();
+
+        let test_geom = GPKGMultiPolygon(get_test_multipolygon());
+        dbg!(test_geom.to_wkb().unwrap());
+
+        let sample = MPTest {
+            id: 99,
+            geom: test_geom,
+        };
+
+        gp.insert_record(&sample).unwrap();
+
+        gp.close();
+    }
+
+    #[test]
+    fn check_layer_schema() {
+        #[derive(GPKGModel, Debug)]
+        #[layer_name = "test"]
+        struct WrongGeom {
+            end_node: i64,
+            #[geom_field("PointZ")]
+            geom: types::GPKGPointZ,
+        }
+
+        #[derive(GPKGModel, Debug)]
+        #[layer_name = "test"]
+        struct WrongTypes {
+            start_node: i64,
+            rev_cost: f64,
+            missing: Option<String>,
+        }
+
+        let dir = tempdir().unwrap();
+        let filename = dir.path().join("check_schema.gpkg");
+        let gp = GeoPackage::create(&filename).unwrap();
+        gp.create_layer::<TestTableGeom>().unwrap();
+
+        gp.check_layer_schema::<TestTableGeom>().unwrap();
+
+        match gp.check_layer_schema::<WrongGeom>() {
+            Err(crate::Error::SchemaMismatch { problems, .. }) => assert_eq!(problems.len(), 1),
+            other => panic!("expected a schema mismatch, got {:?}", other),
+        }
+        match gp.check_layer_schema::<WrongTypes>() {
+            // start_node is nullable, rev_cost is TEXT and missing doesn't exist
+            Err(crate::Error::SchemaMismatch { problems, .. }) => assert_eq!(problems.len(), 3),
+            other => panic!("expected a schema mismatch, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn get_all_checked_foreign_layer() {
+        #[derive(GPKGModel, Debug)]
+        #[layer_name = "roads"]
+        struct Road {
+            name: Option<String>,
+            lanes: i64,
+            #[geom_field("LineString")]
+            geom: Option<GPKGLineString>,
         }
-        // check that there are no foreign keys as per spec requirement 7
-        // use a block to force a drop of stmt and release the borrow
-        // so that we can move conn
-        {
-            let mut stmt = conn.prepare("SELECT * FROM pragma_foreign_key_check()")?;
-            let mut rows = stmt.query([])?;
-            if !(rows.next()?.is_none()) {
-                return Err(Error::ValidationError);
+
+        let dir = tempdir().unwrap();
+        let filename = dir.path().join("foreign.gpkg");
+        let gp = GeoPackage::create(&filename).unwrap();
+        // mimic a layer written by another tool, with a different column order and an extra column
+        gp.conn
+            .execute_batch(
+                "CREATE TABLE roads (
+                    fid INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+                    geom LINESTRING,
+                    surface TEXT(20),
+                    lanes MEDIUMINT NOT NULL,
+                    name TEXT
+                );
+                INSERT INTO gpkg_contents (table_name, data_type, srs_id) VALUES ('roads', 'features', 4326);
+                INSERT INTO gpkg_geometry_columns VALUES ('roads', 'geom', 'LINESTRING', 4326, 0, 0);",
+            )
+            .unwrap();
+        let geom = GPKGLineString(LineString::new(vec![
+            coord! {x: -105.0, y: 40.0},
+            coord! {x: -106.0, y: 41.5},
+        ]));
+        gp.conn
+            .execute(
+                "INSERT INTO roads (geom, surface, lanes, name) VALUES (?1, 'gravel', 2, 'Main St')",
+                params![geom],
+            )
+            .unwrap();
+
+        let roads = gp.get_all_checked::<Road>().unwrap();
+        assert_eq!(roads.len(), 1);
+        assert_eq!(roads[0].name.as_deref(), Some("Main St"));
+        assert_eq!(roads[0].lanes, 2);
+        assert_eq!(roads[0].geom.as_ref().unwrap().0, geom.0);
+
+        let roads = gp.get_where_checked::<Road>("lanes > 2").unwrap();
+        assert!(roads.is_empty());
+    }
+
+    #[test]
+    fn get_all_checked_gdal_3d_layer() {
+        #[derive(GPKGModel, Debug)]
+        #[layer_name = "contours"]
+        struct Contour {
+            elevation: f64,
+            #[geom_field("LineStringZ")]
+            geom: Option<GPKGLineStringZ>,
+        }
+
+        let dir = tempdir().unwrap();
+        let filename = dir.path().join("gdal_3d.gpkg");
+        let gp = GeoPackage::create(&filename).unwrap();
+        // GDAL and QGIS register 3D layers with the base type name and the z flag set
+        gp.conn
+            .execute_batch(
+                "CREATE TABLE contours (
+                    fid INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+                    geom LINESTRING,
+                    elevation REAL NOT NULL
+                );
+                INSERT INTO gpkg_contents (table_name, data_type, srs_id) VALUES ('contours', 'features', 4326);
+                INSERT INTO gpkg_geometry_columns VALUES ('contours', 'geom', 'LINESTRING', 4326, 1, 0);",
+            )
+            .unwrap();
+        let geom = GPKGLineStringZ(vec![
+            GPKGPointZ {
+                x: -105.0,
+                y: 40.0,
+                z: 1600.0,
+            },
+            GPKGPointZ {
+                x: -105.1,
+                y: 40.2,
+                z: 1600.0,
+            },
+        ]);
+        gp.conn
+            .execute(
+                "INSERT INTO contours (geom, elevation) VALUES (?1, 1600.0)",
+                params![geom],
+            )
+            .unwrap();
+
+        let contours = gp.get_all_checked::<Contour>().unwrap();
+        assert_eq!(contours.len(), 1);
+        assert_eq!(contours[0].elevation, 1600.0);
+        assert_eq!(contours[0].geom.as_ref().unwrap().0[1].z, 1600.0);
+
+        // without the z flag the layer is 2D, which the model can't read
+        gp.conn
+            .execute("UPDATE gpkg_geometry_columns SET z = 0", [])
+            .unwrap();
+        match gp.get_all_checked::<Contour>() {
+            Err(crate::Error::SchemaMismatch { problems, .. }) => {
+                assert_eq!(problems.len(), 1);
+                assert!(problems[0].contains("z flag"));
             }
+            r => panic!("expected a schema mismatch, got {:?}", r),
         }
+    }
 
-        Ok(GeoPackage { conn })
+    #[derive(GPKGEnum, Debug, PartialEq, Eq)]
+    enum Surface {
+        Paved,
+        Gravel,
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use geo_types::*;
-    use std::fs;
-    use tempfile::tempdir;
+    #[derive(GPKGEnum, Debug, PartialEq, Eq)]
+    #[gpkg(storage = "integer")]
+    enum RoadClass {
+        Highway = 1,
+        Arterial = 2,
+        Local = 5,
+    }
 
-    use crate::types::*;
+    #[derive(GPKGModel, Debug, PartialEq, Eq)]
+    #[layer_name = "roads"]
+    struct Road {
+        name: String,
+        surface: Surface,
+        class: Option<RoadClass>,
+    }
 
-    use super::*;
+    #[test]
+    fn enum_fields() {
+        let dir = tempdir().unwrap();
+        let filename = dir.path().join("enums.gpkg");
+        let mut gp = GeoPackage::create(&filename).unwrap();
+        gp.create_layer::<Road>().unwrap();
+        gp.check_layer_schema::<Road>().unwrap();
+
+        let roads = vec![
+            Road {
+                name: String::from("Main St"),
+                surface: Surface::Paved,
+                class: Some(RoadClass::Local),
+            },
+            Road {
+                name: String::from("Old Mill Rd"),
+                surface: Surface::Gravel,
+                class: None,
+            },
+        ];
+        gp.insert_many(&roads).unwrap();
+
+        let surface: String = gp
+            .conn
+            .query_row(
+                "SELECT surface FROM roads WHERE name = 'Main St'",
+                [],
+                |r| r.get(0),
+            )
+            .unwrap();
+        assert_eq!(surface, "Paved");
+        let class: i64 = gp
+            .conn
+            .query_row("SELECT class FROM roads WHERE name = 'Main St'", [], |r| {
+                r.get(0)
+            })
+            .unwrap();
+        assert_eq!(class, 5);
+
+        assert_eq!(gp.get_all::<Road>().unwrap(), roads);
+
+        gp.conn
+            .execute("UPDATE roads SET surface = 'Dirt'", [])
+            .unwrap();
+        assert!(gp.get_all::<Road>().is_err());
+    }
+
+    #[test]
+    fn enum_constraint() {
+        let dir = tempdir().unwrap();
+        let filename = dir.path().join("enum_constraint.gpkg");
+        let mut gp = GeoPackage::create(&filename).unwrap();
+        gp.create_layer::<Road>().unwrap();
+        gp.add_enum_constraint::<RoadClass>("roads", "class")
+            .unwrap();
+        gp.conn
+            .execute(
+                "UPDATE gpkg_data_columns SET title = 'Class', description = 'Road class' WHERE column_name = 'class'",
+                [],
+            )
+            .unwrap();
+        // registering twice shouldn't duplicate anything, or lose the other metadata of the column
+        gp.add_enum_constraint::<RoadClass>("roads", "class")
+            .unwrap();
+
+        let values: Vec<(String, String)> = {
+            let mut stmt = gp
+                .conn
+                .prepare("SELECT value, description FROM gpkg_data_column_constraints WHERE constraint_name = 'road_class' ORDER BY value")
+                .unwrap();
+            let rows = stmt.query_map([], |r| Ok((r.get(0)?, r.get(1)?))).unwrap();
+            rows.map(|r| r.unwrap()).collect()
+        };
+        assert_eq!(
+            values,
+            vec![
+                (String::from("1"), String::from("Highway")),
+                (String::from("2"), String::from("Arterial")),
+                (String::from("5"), String::from("Local")),
+            ]
+        );
+        let column: (String, String, String) = gp
+            .conn
+            .query_row(
+                "SELECT constraint_name, title, description FROM gpkg_data_columns WHERE table_name = 'roads' AND column_name = 'class'",
+                [],
+                |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)),
+            )
+            .unwrap();
+        assert_eq!(
+            column,
+            (
+                String::from("road_class"),
+                String::from("Class"),
+                String::from("Road class")
+            )
+        );
+        let extension_rows: i64 = gp
+            .conn
+            .query_row(
+                "SELECT count(*) FROM gpkg_extensions WHERE extension_name = 'gpkg_schema'",
+                [],
+                |r| r.get(0),
+            )
+            .unwrap();
+        assert_eq!(extension_rows, 2);
+    }
+
+    #[derive(GPKGModel, Debug, PartialEq)]
+    struct Audit {
+        created_by: String,
+        revision: i64,
+    }
+
+    #[derive(GPKGModel, Debug, PartialEq)]
+    struct Address {
+        street: String,
+        city: Option<String>,
+    }
+
+    #[derive(GPKGModel, Debug, PartialEq)]
+    #[layer_name = "buildings"]
+    struct Building {
+        height: f64,
+        #[gpkg(flatten, prefix = "mail_")]
+        mailing: Address,
+        #[gpkg(flatten, prefix = "site_")]
+        site: Address,
+        #[gpkg(flatten)]
+        audit: Audit,
+    }
+
+    #[test]
+    fn flattened_fields() {
+        let names: Vec<String> = Building::get_columns()
+            .into_iter()
+            .map(|c| c.name)
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                "height",
+                "mail_street",
+                "mail_city",
+                "site_street",
+                "site_city",
+                "created_by",
+                "revision"
+            ]
+        );
+        assert_eq!(Building::get_column_count(), 7);
+
+        let dir = tempdir().unwrap();
+        let filename = dir.path().join("flatten.gpkg");
+        let gp = GeoPackage::create(&filename).unwrap();
+        gp.create_layer::<Building>().unwrap();
+
+        let building = Building {
+            height: 12.5,
+            mailing: Address {
+                street: String::from("PO Box 12"),
+                city: None,
+            },
+            site: Address {
+                street: String::from("1 Main St"),
+                city: Some(String::from("Denver")),
+            },
+            audit: Audit {
+                created_by: String::from("survey"),
+                revision: 3,
+            },
+        };
+        gp.insert_record(&building).unwrap();
+
+        assert_eq!(gp.get_all::<Building>().unwrap(), vec![building]);
+        let checked = gp.get_all_checked::<Building>().unwrap();
+        assert_eq!(checked[0].site.city.as_deref(), Some("Denver"));
+        let filtered = gp
+            .get_where::<Building>("site_street = '1 Main St'")
+            .unwrap();
+        assert_eq!(filtered[0].audit.revision, 3);
+    }
 
     #[derive(GPKGModel, Debug)]
-    #[layer_name = "test"]
-    struct TestTableGeom {
-        start_node: Option<i64>,
-        end_node: i64,
-        rev_cost: String,
-        // #[geom_field("LineStringZ")]
-        #[geom_field("LineStringZ")]
-        geom: types::GPKGLineStringZ,
+    #[layer_name = "hydrants"]
+    #[gpkg(index = "district, zone")]
+    #[gpkg(unique_index = "district, asset_number")]
+    struct Hydrant {
+        #[gpkg(unique)]
+        serial: String,
+        #[gpkg(index)]
+        district: i64,
+        zone: Option<String>,
+        asset_number: i64,
+        #[gpkg(default = "'unknown'")]
+        status: String,
+        #[gpkg(check = "pressure >= 0")]
+        pressure: f64,
     }
 
-    #[derive(GPKGModel, Debug, PartialEq, Eq)]
-    #[layer_name = "test"]
-    struct TestTableAttr {
-        field1: Option<i64>,
-        field2: i64,
-        field3: String,
+    #[test]
+    fn constraints_and_indexes() {
+        let dir = tempdir().unwrap();
+        let filename = dir.path().join("constraints.gpkg");
+        let gp = GeoPackage::create(&filename).unwrap();
+        gp.create_layer::<Hydrant>().unwrap();
+
+        let mut index_names: Vec<String> = {
+            let mut stmt = gp
+                .conn
+                .prepare("SELECT name FROM pragma_index_list('hydrants') WHERE origin = 'c'")
+                .unwrap();
+            let rows = stmt.query_map([], |r| r.get(0)).unwrap();
+            rows.map(|r| r.unwrap()).collect()
+        };
+        index_names.sort();
+        assert_eq!(
+            index_names,
+            vec![
+                "idx_hydrants_district",
+                "idx_hydrants_district_asset_number",
+                "idx_hydrants_district_zone"
+            ]
+        );
+
+        let hydrant = Hydrant {
+            serial: String::from("H-1"),
+            district: 4,
+            zone: None,
+            asset_number: 100,
+            status: String::from("active"),
+            pressure: 60.0,
+        };
+        gp.insert_record(&hydrant).unwrap();
+        // unique column
+        assert!(gp.insert_record(&hydrant).is_err());
+        // check constraint
+        let bad_pressure = Hydrant {
+            serial: String::from("H-2"),
+            asset_number: 101,
+            pressure: -1.0,
+            ..hydrant
+        };
+        assert!(gp.insert_record(&bad_pressure).is_err());
+
+        gp.conn
+            .execute(
+                "INSERT INTO hydrants (serial, district, asset_number, pressure) VALUES ('H-3', 4, 102, 55.0)",
+                [],
+            )
+            .unwrap();
+        let status: String = gp
+            .conn
+            .query_row(
+                "SELECT status FROM hydrants WHERE serial = 'H-3'",
+                [],
+                |r| r.get(0),
+            )
+            .unwrap();
+        assert_eq!(status, "unknown");
     }
 
     #[test]
-    fn create_table() {
+    fn failed_create_rolls_back() {
+        #[derive(GPKGModel)]
+        #[layer_name = "broken"]
+        #[gpkg(index = "missing_column")]
+        struct Broken {
+            value: i64,
+        }
+
+        #[derive(GPKGModel)]
+        #[layer_name = "broken"]
+        struct Duplicate {
+            #[gpkg(index)]
+            value: i64,
+        }
+
         let dir = tempdir().unwrap();
-        let filename = dir.path().join("create.gpkg");
+        let filename = dir.path().join("rollback.gpkg");
+        let gp = GeoPackage::create(&filename).unwrap();
+        assert!(matches!(
+            gp.create_layer::<Broken>(),
+            Err(crate::Error::UnknownColumn(_))
+        ));
+
+        // the contents entry will conflict after the table and index have been created
+        gp.conn
+            .execute(
+                "INSERT INTO gpkg_contents (table_name, data_type) VALUES ('broken', 'attributes')",
+                [],
+            )
+            .unwrap();
+        assert!(gp.create_layer::<Duplicate>().is_err());
+        assert!(gp.conn.is_autocommit());
+        let tables: i64 = gp
+            .conn
+            .query_row(
+                "SELECT count(*) FROM sqlite_master WHERE name = 'broken'",
+                [],
+                |r| r.get(0),
+            )
+            .unwrap();
+        assert_eq!(tables, 0);
+    }
+
+    /// Sidewalks and paths
+    /// maintained by the city
+    #[derive(GPKGModel)]
+    #[layer_name = "paths"]
+    struct Sidewalk {
+        width: f64,
+    }
 
+    #[derive(GPKGModel)]
+    #[layer_name = "trails"]
+    #[gpkg(identifier = "Trails", description = "Backcountry trails")]
+    struct Trail {
+        length: f64,
+    }
+
+    fn get_contents_metadata(gp: &GeoPackage, layer: &str) -> (Option<String>, String, String) {
+        gp.conn
+            .query_row(
+                "SELECT identifier, description, last_change FROM gpkg_contents WHERE table_name = ?1",
+                params![layer],
+                |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)),
+            )
+            .unwrap()
+    }
+
+    #[test]
+    fn layer_identifier_and_description() {
+        let dir = tempdir().unwrap();
+        let filename = dir.path().join("contents_metadata.gpkg");
         let gp = GeoPackage::create(&filename).unwrap();
-        gp.create_layer::<TestTableGeom>().unwrap();
+        gp.create_layer::<Sidewalk>().unwrap();
+        gp.create_layer::<Trail>().unwrap();
 
-        // how do we get a check that the table exists?
-        // make sure that we've got something in gpkg_contents
+        let (identifier, description, _) = get_contents_metadata(&gp, "paths");
+        assert_eq!(identifier.as_deref(), Some("paths"));
+        assert_eq!(description, "Sidewalks and paths\nmaintained by the city");
+
+        let (identifier, description, _) = get_contents_metadata(&gp, "trails");
+        assert_eq!(identifier.as_deref(), Some("Trails"));
+        assert_eq!(description, "Backcountry trails");
+
+        gp.set_layer_identifier("trails", None).unwrap();
+        gp.set_layer_description("trails", "Hiking trails").unwrap();
+        let (identifier, description, _) = get_contents_metadata(&gp, "trails");
+        assert_eq!(identifier, None);
+        assert_eq!(description, "Hiking trails");
+
+        assert!(matches!(
+            gp.set_layer_description("missing", "nothing"),
+            Err(crate::Error::UnknownLayer(_))
+        ));
+    }
+
+    #[test]
+    fn last_change_updated_on_insert() {
+        let dir = tempdir().unwrap();
+        let filename = dir.path().join("last_change.gpkg");
+        let mut gp = GeoPackage::create(&filename).unwrap();
+        gp.create_layer::<Trail>().unwrap();
+        gp.conn
+            .execute(
+                "UPDATE gpkg_contents SET last_change = '2000-01-01T00:00:00.000Z'",
+                [],
+            )
+            .unwrap();
+
+        gp.insert_record(&Trail { length: 5.0 }).unwrap();
+        let (_, _, last_change) = get_contents_metadata(&gp, "trails");
+        assert!(last_change.as_str() > "2000-01-01T00:00:00.000Z");
+
+        gp.conn
+            .execute(
+                "UPDATE gpkg_contents SET last_change = '2000-01-01T00:00:00.000Z'",
+                [],
+            )
+            .unwrap();
+        gp.insert_many(&vec![Trail { length: 1.0 }]).unwrap();
+        let (_, _, last_change) = get_contents_metadata(&gp, "trails");
+        assert!(last_change.as_str() > "2000-01-01T00:00:00.000Z");
+    }
+
+    #[derive(GPKGModel)]
+    struct Centerline {
+        lanes: i64,
+        #[geom_field("LineString")]
+        geom: GPKGLineString,
+    }
+
+    #[test]
+    fn runtime_layer_names() {
+        let dir = tempdir().unwrap();
+        let filename = dir.path().join("runtime_names.gpkg");
+        let mut gp = GeoPackage::create(&filename).unwrap();
+        let line = |lanes| Centerline {
+            lanes,
+            geom: GPKGLineString(line_string![(x: 0.0, y: 0.0), (x: 1.0, y: lanes as f64)]),
+        };
+
+        gp.create_layer_as::<Centerline>("roads_2023").unwrap();
+        gp.create_layer_as::<Centerline>("roads \"2024\"").unwrap();
+        assert!(gp.create_layer_as::<Centerline>("roads_2023").is_err());
+
+        gp.insert_record_into("roads_2023", &line(2)).unwrap();
+        gp.insert_many_into("roads \"2024\"", &vec![line(2), line(4)])
+            .unwrap();
+
+        let roads_2023: Vec<Centerline> = gp.get_all_from("roads_2023").unwrap();
+        assert_eq!(roads_2023.len(), 1);
+        let roads_2024: Vec<Centerline> = gp.get_where_from("roads \"2024\"", "lanes > 2").unwrap();
+        assert_eq!(roads_2024.len(), 1);
+        assert_eq!(roads_2024[0].geom.0, line(4).geom.0);
+
+        gp.check_layer_schema_of::<Centerline>("roads \"2024\"")
+            .unwrap();
+        let checked: Vec<Centerline> = gp
+            .get_where_checked_from("roads \"2024\"", "lanes = 2")
+            .unwrap();
+        assert_eq!(checked.len(), 1);
+
+        let geom_layers: i64 = gp
+            .conn
+            .query_row(
+                "SELECT count(*) FROM gpkg_geometry_columns WHERE table_name IN ('roads_2023', 'roads \"2024\"')",
+                [],
+                |r| r.get(0),
+            )
+            .unwrap();
+        assert_eq!(geom_layers, 2);
+        let (identifier, _, _) = get_contents_metadata(&gp, "roads \"2024\"");
+        assert_eq!(identifier.as_deref(), Some("roads \"2024\""));
+    }
+
+    #[derive(GPKGModel)]
+    struct Feature<G: GPKGGeometryType> {
+        name: String,
+        #[geom_field]
+        geom: G,
+    }
+
+    #[derive(GPKGModel)]
+    #[layer_name = "labels"]
+    struct Label<'a, T>
+    where
+        T: GPKGEnum,
+    {
+        text: Cow<'a, str>,
+        note: Option<Cow<'a, str>>,
+        surface: T,
+    }
+
+    #[derive(GPKGModel)]
+    #[layer_name = "labels"]
+    struct BorrowedLabel<'a> {
+        text: &'a str,
+        note: Option<&'a str>,
+        surface: Surface,
+    }
+
+    #[test]
+    fn generic_models() {
+        let dir = tempdir().unwrap();
+        let filename = dir.path().join("generics.gpkg");
+        let gp = GeoPackage::create(&filename).unwrap();
+
+        gp.create_layer_as::<Feature<GPKGPoint>>("points").unwrap();
+        gp.create_layer_as::<Feature<GPKGLineString>>("lines")
+            .unwrap();
+        gp.insert_record_into(
+            "points",
+            &Feature {
+                name: String::from("a"),
+                geom: GPKGPoint(point!(x: 1.0, y: 2.0)),
+            },
+        )
+        .unwrap();
+        gp.insert_record_into(
+            "lines",
+            &Feature {
+                name: String::from("b"),
+                geom: GPKGLineString(line_string![(x: 0.0, y: 0.0), (x: 1.0, y: 1.0)]),
+            },
+        )
+        .unwrap();
+
+        let points: Vec<Feature<GPKGPoint>> = gp.get_all_from("points").unwrap();
+        assert_eq!(points[0].geom.0, point!(x: 1.0, y: 2.0));
+        let lines: Vec<Feature<GPKGLineString>> = gp.get_all_checked_from("lines").unwrap();
+        assert_eq!(lines[0].name, "b");
+        assert!(gp
+            .check_layer_schema_of::<Feature<GPKGPoint>>("lines")
+            .is_err());
+        let geometry_type: String = gp
+            .conn
+            .query_row(
+                "SELECT geometry_type_name FROM gpkg_geometry_columns WHERE table_name = 'lines'",
+                [],
+                |r| r.get(0),
+            )
+            .unwrap();
+        assert_eq!(geometry_type, "LINESTRING");
+    }
+
+    #[test]
+    fn borrowed_fields() {
+        let dir = tempdir().unwrap();
+        let filename = dir.path().join("borrowed.gpkg");
+        let gp = GeoPackage::create(&filename).unwrap();
+        gp.create_layer::<BorrowedLabel>().unwrap();
+
+        let text = String::from("Main St");
+        gp.insert_record(&BorrowedLabel {
+            text: &text,
+            note: None,
+            surface: Surface::Paved,
+        })
+        .unwrap();
+        gp.insert_record(&Label {
+            text: Cow::Borrowed("Elm St"),
+            note: Some(Cow::Borrowed("closed")),
+            surface: Surface::Gravel,
+        })
+        .unwrap();
+
+        let labels: Vec<Label<Surface>> = gp.get_all().unwrap();
+        assert_eq!(labels[0].text, "Main St");
+        assert_eq!(labels[0].note, None);
+        assert_eq!(labels[1].note.as_deref(), Some("closed"));
+        let checked: Vec<Label<Surface>> = gp.get_all_checked().unwrap();
+        assert_eq!(checked.len(), 2);
+
+        assert!(matches!(
+            gp.get_all::<BorrowedLabel>(),
+            Err(crate::Error::SQLiteError(
+                rusqlite::Error::FromSqlConversionFailure(..)
+            ))
+        ));
+    }
+
+    #[test]
+    fn streaming_reads_and_inserts() {
+        let dir = tempdir().unwrap();
+        let filename = dir.path().join("streaming.gpkg");
+        let mut gp = GeoPackage::create(&filename).unwrap();
+        gp.create_layer::<Trail>().unwrap();
+        gp.create_layer_as::<Trail>("trails_copy").unwrap();
+        gp.insert_many((0..1000).map(|i| Trail { length: i as f64 }))
+            .unwrap();
+
+        // stream one layer into another without holding every record
+        let copy = GeoPackage::open(&filename).unwrap();
+        let mut stmt = copy
+            .query::<Trail>()
+            .where_sql("length < 500")
+            .prepare()
+            .unwrap();
+        gp.insert_many_into("trails_copy", stmt.iter().unwrap().map(|t| t.unwrap()))
+            .unwrap();
+        // a prepared query can be run again
+        assert_eq!(stmt.iter().unwrap().count(), 500);
+        assert_eq!(gp.get_all_from::<Trail>("trails_copy").unwrap().len(), 500);
+
+        let mut sizes = Vec::new();
+        gp.query::<Trail>()
+            .layer("trails_copy")
+            .for_each_batch(200, |batch| {
+                sizes.push(batch.len());
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(sizes, vec![200, 200, 100]);
+
+        let mut calls = 0;
+        let result = gp.query::<Trail>().for_each_batch(10, |_| {
+            calls += 1;
+            Err(crate::Error::UnknownLayer(String::from("stop")))
+        });
+        assert!(matches!(result, Err(crate::Error::UnknownLayer(_))));
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn parameterized_filters() {
+        let dir = tempdir().unwrap();
+        let filename = dir.path().join("filters.gpkg");
+        let mut gp = GeoPackage::create(&filename).unwrap();
+        gp.create_layer::<Road>().unwrap();
+        gp.insert_many(vec![
+            Road {
+                name: String::from("Main St"),
+                surface: Surface::Paved,
+                class: Some(RoadClass::Arterial),
+            },
+            Road {
+                name: String::from("Quarry Rd"),
+                surface: Surface::Gravel,
+                class: None,
+            },
+            Road {
+                name: String::from("O'Brien Way"),
+                surface: Surface::Paved,
+                class: Some(RoadClass::Local),
+            },
+        ])
+        .unwrap();
+
+        let quoted: Vec<Road> = gp
+            .get_where_with("name = ?", params!["O'Brien Way"])
+            .unwrap();
+        assert_eq!(quoted.len(), 1);
+
+        let paved = gp
+            .query::<Road>()
+            .filter(Filter::column("surface").eq(Surface::Paved))
+            .filter(Filter::column("class").is_in([RoadClass::Local, RoadClass::Highway]))
+            .all()
+            .unwrap();
+        assert_eq!(paved.len(), 1);
+        assert_eq!(paved[0].name, "O'Brien Way");
+
+        // parameters are bound in the order the conditions were added
+        let mixed = gp
+            .query::<Road>()
+            .where_sql_with("name <> ?", params!["Main St"])
+            .filter(Filter::column("class").is_not_null().not())
+            .filter(Filter::column("fid").le(2))
+            .all()
+            .unwrap();
+        assert_eq!(mixed.len(), 1);
+        assert_eq!(mixed[0].name, "Quarry Rd");
+
+        assert!(matches!(
+            gp.query::<Road>()
+                .filter(Filter::column("lanes").gt(2))
+                .all(),
+            Err(crate::Error::UnknownColumn(_))
+        ));
+    }
+
+    #[test]
+    fn ordering_and_paging() {
+        let dir = tempdir().unwrap();
+        let filename = dir.path().join("paging.gpkg");
+        let mut gp = GeoPackage::create(&filename).unwrap();
+        gp.create_layer::<Trail>().unwrap();
+        gp.insert_many((0..10).map(|i| Trail {
+            length: (i % 3) as f64,
+        }))
+        .unwrap();
+
+        let ordered = gp
+            .query::<Trail>()
+            .order_by_desc("length")
+            .order_by("fid")
+            .all_with_fids()
+            .unwrap();
+        let fids: Vec<i64> = ordered.iter().map(|(fid, _)| *fid).collect();
+        assert_eq!(fids, vec![3, 6, 9, 2, 5, 8, 1, 4, 7, 10]);
+
+        let skipped = gp.query::<Trail>().order_by("fid").offset(8).all().unwrap();
+        assert_eq!(skipped.len(), 2);
+        let page = gp
+            .query::<Trail>()
+            .where_sql("length > 0")
+            .after_fid(5)
+            .limit(2)
+            .all_with_fids()
+            .unwrap();
+        let fids: Vec<i64> = page.iter().map(|(fid, _)| *fid).collect();
+        assert_eq!(fids, vec![6, 8]);
+
+        assert!(matches!(
+            gp.query::<Trail>().order_by("width").all(),
+            Err(crate::Error::UnknownColumn(_))
+        ));
+        assert!(matches!(
+            gp.query::<Trail>().order_by("length").after_fid(1).all(),
+            Err(crate::Error::InvalidQuery(_))
+        ));
+    }
+
+    #[test]
+    fn update_upsert_and_delete() {
+        let dir = tempdir().unwrap();
+        let filename = dir.path().join("update.gpkg");
+        let gp = GeoPackage::create(&filename).unwrap();
+        let line = |lanes, x: f64| Centerline {
+            lanes,
+            geom: GPKGLineString(line_string![(x: 0.0, y: 0.0), (x: x, y: 1.0)]),
+        };
+        gp.create_layer::<Centerline>().unwrap();
+        gp.insert_record(&line(2, 1.0)).unwrap();
+        let get_extent = || {
+            gp.get_layer_extent("Centerline")
+                .unwrap()
+                .map(|e| (e.min_x, e.max_x))
+        };
+        assert_eq!(get_extent(), Some((0.0, 1.0)));
+
+        gp.update_record(1, &line(4, 10.0)).unwrap();
+        assert_eq!(gp.get_all::<Centerline>().unwrap()[0].lanes, 4);
+        assert_eq!(get_extent(), Some((0.0, 10.0)));
+        assert!(matches!(
+            gp.update_record(7, &line(1, 1.0)),
+            Err(crate::Error::UnknownFeature(7))
+        ));
+
+        gp.upsert(1, &line(6, -5.0)).unwrap();
+        gp.upsert(7, &line(1, 1.0)).unwrap();
+        let records = gp.query::<Centerline>().all_with_fids().unwrap();
+        let lanes: Vec<(i64, i64)> = records.iter().map(|(fid, r)| (*fid, r.lanes)).collect();
+        assert_eq!(lanes, vec![(1, 6), (7, 1)]);
+        // the replaced geometry was on the edge of the extent, so it shrinks
+        assert_eq!(get_extent(), Some((-5.0, 1.0)));
+
+        assert!(gp.delete_record::<Centerline>(7).unwrap());
+        assert_eq!(get_extent(), Some((-5.0, 0.0)));
+        assert!(!gp.delete_record::<Centerline>(7).unwrap());
+        assert_eq!(
+            gp.delete_where::<Centerline>("lanes > ?", params![5])
+                .unwrap(),
+            1
+        );
+        assert_eq!(gp.get_all::<Centerline>().unwrap().len(), 0);
+        assert_eq!(get_extent(), None);
+    }
+
+    #[test]
+    fn failed_metadata_update_rolls_back_write() {
+        let dir = tempdir().unwrap();
+        let filename = dir.path().join("write_rollback.gpkg");
+        let gp = GeoPackage::create(&filename).unwrap();
+        let line = |lanes, x: f64| Centerline {
+            lanes,
+            geom: GPKGLineString(line_string![(x: 0.0, y: 0.0), (x: x, y: 1.0)]),
+        };
+        gp.create_layer::<Centerline>().unwrap();
+        gp.insert_record(&line(2, 1.0)).unwrap();
+        gp.conn
+            .execute_batch(
+                "CREATE TRIGGER contents_read_only BEFORE UPDATE ON gpkg_contents
+                 BEGIN SELECT RAISE(ABORT, 'gpkg_contents is read only'); END;",
+            )
+            .unwrap();
+
+        assert!(gp.insert_record(&line(3, 5.0)).is_err());
+        assert!(gp.insert_record_with_fid(9, &line(3, 5.0)).is_err());
+        assert!(gp.update_record(1, &line(4, 10.0)).is_err());
+        assert!(gp.upsert(1, &line(5, 10.0)).is_err());
+        assert!(gp.delete_record::<Centerline>(1).is_err());
+        assert!(gp
+            .delete_where::<Centerline>("lanes = ?", params![2])
+            .is_err());
+        let mut feature = crate::Feature::new();
+        feature.set("lanes", 6).unwrap();
+        feature.geometry = Some(line_string![(x: 0.0, y: 0.0), (x: 2.0, y: 2.0)].into());
+        assert!(gp.insert_feature_into("Centerline", &feature).is_err());
+        assert!(gp.update_feature_in("Centerline", 1, &feature).is_err());
+        assert!(gp.delete_feature_from("Centerline", 1).is_err());
+
+        let records = gp.query::<Centerline>().all_with_fids().unwrap();
+        let lanes: Vec<(i64, i64)> = records.iter().map(|(fid, r)| (*fid, r.lanes)).collect();
+        assert_eq!(lanes, vec![(1, 2)]);
+        assert!(gp.conn.is_autocommit());
+    }
+
+    #[test]
+    fn extent_of_z_geometry() {
+        let point = GPKGPointZ {
+            x: 3.0,
+            y: -2.0,
+            z: 100.0,
+        };
+        let blob = point.to_wkb().unwrap();
+        let extent = extent::from_geometry_blob(&blob).unwrap().unwrap();
+        assert_eq!(
+            extent,
+            Extent {
+                min_x: 3.0,
+                min_y: -2.0,
+                max_x: 3.0,
+                max_y: -2.0
+            }
+        );
+    }
+
+    #[test]
+    fn inserts_return_fids() {
+        let dir = tempdir().unwrap();
+        let filename = dir.path().join("fids.gpkg");
+        let mut gp = GeoPackage::create(&filename).unwrap();
+        gp.create_layer::<Trail>().unwrap();
+
+        assert_eq!(gp.insert_record(&Trail { length: 1.0 }).unwrap(), 1);
+        let fids = gp
+            .insert_many(vec![Trail { length: 2.0 }, Trail { length: 3.0 }])
+            .unwrap();
+        assert_eq!(fids, vec![2, 3]);
+
+        gp.insert_many_with_fids(vec![
+            (10, Trail { length: 10.0 }),
+            (20, Trail { length: 20.0 }),
+        ])
+        .unwrap();
+        gp.insert_record_with_fid(15, &Trail { length: 15.0 })
+            .unwrap();
+        assert!(gp
+            .insert_record_with_fid(15, &Trail { length: 0.0 })
+            .is_err());
+        assert_eq!(gp.insert_record(&Trail { length: 21.0 }).unwrap(), 21);
+
+        let records = gp.query::<Trail>().all_with_fids().unwrap();
+        for (fid, trail) in records {
+            assert_eq!(fid as f64, trail.length);
+        }
+    }
+
+    #[test]
+    fn counts_and_aggregates() {
+        let dir = tempdir().unwrap();
+        let filename = dir.path().join("aggregates.gpkg");
+        let mut gp = GeoPackage::create(&filename).unwrap();
+        gp.create_layer_as::<Trail>("trails_2024").unwrap();
+        gp.insert_many_into("trails_2024", (1..=20).map(|i| Trail { length: i as f64 }))
+            .unwrap();
+
+        let query = || gp.query::<Trail>().layer("trails_2024");
+        assert_eq!(query().count().unwrap(), 20);
+        assert_eq!(query().offset(15).count().unwrap(), 5);
+        assert_eq!(
+            query()
+                .order_by_desc("length")
+                .limit(3)
+                .sum::<f64>("length")
+                .unwrap(),
+            Some(57.0)
+        );
+        assert_eq!(
+            query()
+                .filter(Filter::column("length").lt(5.0))
+                .max::<f64>("length")
+                .unwrap(),
+            Some(4.0)
+        );
+        assert_eq!(
+            query().after_fid(18).min::<f64>("length").unwrap(),
+            Some(19.0)
+        );
+        assert!(query().where_sql("length = 20").exists().unwrap());
+        assert!(matches!(
+            query().sum::<f64>("width"),
+            Err(crate::Error::UnknownColumn(_))
+        ));
+        // the layer for the type itself doesn't exist
+        assert!(gp.count::<Trail>().is_err());
+    }
+
+    #[test]
+    fn extent_maintenance() {
+        let dir = tempdir().unwrap();
+        let filename = dir.path().join("extent.gpkg");
+        let mut gp = GeoPackage::create(&filename).unwrap();
+        let line = |x1: f64, y1: f64, x2: f64, y2: f64| Centerline {
+            lanes: 2,
+            geom: GPKGLineString(line_string![(x: x1, y: y1), (x: x2, y: y2)]),
+        };
+        let extent = |min_x, min_y, max_x, max_y| Extent {
+            min_x,
+            min_y,
+            max_x,
+            max_y,
+        };
+        gp.create_layer::<Centerline>().unwrap();
+        assert_eq!(gp.get_layer_extent("Centerline").unwrap(), None);
+
+        gp.insert_many(vec![line(0.0, 0.0, 1.0, 1.0), line(2.0, -1.0, 3.0, 0.5)])
+            .unwrap();
+        assert_eq!(
+            gp.get_layer_extent("Centerline").unwrap(),
+            Some(extent(0.0, -1.0, 3.0, 1.0))
+        );
+        gp.insert_record(&line(0.5, 0.5, 0.6, 4.0)).unwrap();
+        assert_eq!(
+            gp.get_layer_extent("Centerline").unwrap(),
+            Some(extent(0.0, -1.0, 3.0, 4.0))
+        );
+
+        // removing a geometry inside the extent leaves it as it is
+        gp.insert_record(&line(1.0, 0.0, 1.5, 0.0)).unwrap();
+        gp.delete_record::<Centerline>(4).unwrap();
+        assert_eq!(
+            gp.get_layer_extent("Centerline").unwrap(),
+            Some(extent(0.0, -1.0, 3.0, 4.0))
+        );
+        gp.delete_where::<Centerline>("fid > ?", params![1])
+            .unwrap();
+        assert_eq!(
+            gp.get_layer_extent("Centerline").unwrap(),
+            Some(extent(0.0, 0.0, 1.0, 1.0))
+        );
+
+        // a file written without maintaining the extent is repaired
+        gp.conn
+            .execute(
+                "UPDATE gpkg_contents SET min_x = 5, min_y = 5, max_x = 6, max_y = 6",
+                [],
+            )
+            .unwrap();
+        assert_eq!(
+            gp.recompute_extent("Centerline").unwrap(),
+            Some(extent(0.0, 0.0, 1.0, 1.0))
+        );
+        gp.conn
+            .execute("UPDATE gpkg_contents SET min_x = NULL", [])
+            .unwrap();
+        gp.insert_record(&line(-1.0, 0.0, 0.0, 0.0)).unwrap();
+        assert_eq!(
+            gp.get_layer_extent("Centerline").unwrap(),
+            Some(extent(-1.0, 0.0, 1.0, 1.0))
+        );
+        assert!(matches!(
+            gp.recompute_extent("missing"),
+            Err(crate::Error::UnknownLayer(_))
+        ));
+    }
+
+    #[derive(GPKGModel)]
+    #[layer_name = "stops"]
+    #[gpkg(spatial_index)]
+    struct Stop {
+        name: String,
+        #[geom_field("Point")]
+        geom: Option<GPKGPoint>,
+    }
+
+    #[test]
+    fn spatial_index() {
+        let dir = tempdir().unwrap();
+        let filename = dir.path().join("rtree.gpkg");
+        let mut gp = GeoPackage::create(&filename).unwrap();
+        let stop = |name: &str, geom: Option<(f64, f64)>| Stop {
+            name: name.to_owned(),
+            geom: geom.map(|(x, y)| GPKGPoint(point!(x: x, y: y))),
+        };
+        let index_rows = |gp: &GeoPackage, rtree: &str| -> Vec<(i64, f64, f64)> {
+            let mut stmt = gp
+                .conn
+                .prepare(&format!("SELECT id, minx, maxy FROM {} ORDER BY id", rtree))
+                .unwrap();
+            let rows = stmt
+                .query_map([], |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)))
+                .unwrap();
+            rows.map(|r| r.unwrap()).collect()
+        };
+
+        gp.create_layer::<Stop>().unwrap();
+        assert!(gp.has_spatial_index("stops").unwrap());
+        gp.insert_many(vec![
+            stop("a", Some((1.0, 2.0))),
+            stop("b", None),
+            stop("c", Some((3.0, 4.0))),
+        ])
+        .unwrap();
+        assert_eq!(
+            index_rows(&gp, "rtree_stops_geom"),
+            vec![(1, 1.0, 2.0), (3, 3.0, 4.0)]
+        );
+        gp.update_record(1, &stop("a", Some((5.0, 6.0)))).unwrap();
+        gp.update_record(3, &stop("c", None)).unwrap();
+        gp.upsert(2, &stop("b", Some((7.0, 8.0)))).unwrap();
+        assert_eq!(
+            index_rows(&gp, "rtree_stops_geom"),
+            vec![(1, 5.0, 6.0), (2, 7.0, 8.0)]
+        );
+        gp.delete_record::<Stop>(1).unwrap();
+        assert_eq!(index_rows(&gp, "rtree_stops_geom"), vec![(2, 7.0, 8.0)]);
+
+        // the functions the triggers need are registered when the file is opened again
+        gp.close();
+        let mut gp = GeoPackage::open(&filename).unwrap();
+        gp.insert_record(&stop("d", Some((-1.0, 0.0)))).unwrap();
+        assert_eq!(index_rows(&gp, "rtree_stops_geom").len(), 2);
+
+        // an index can be added to a layer that already has records
+        gp.create_layer_as::<Centerline>("lines").unwrap();
+        gp.insert_record_into(
+            "lines",
+            &Centerline {
+                lanes: 2,
+                geom: GPKGLineString(line_string![(x: 0.0, y: 0.0), (x: 2.0, y: 3.0)]),
+            },
+        )
+        .unwrap();
+        assert!(!gp.has_spatial_index("lines").unwrap());
+        gp.create_spatial_index("lines").unwrap();
+        assert!(gp.has_spatial_index("lines").unwrap());
+        assert_eq!(index_rows(&gp, "rtree_lines_geom"), vec![(1, 0.0, 3.0)]);
+
+        // the ids in the index are the values of the layer's primary key, whatever its name
+        gp.conn
+            .execute_batch(
+                "CREATE TABLE parcels (OBJECTID INTEGER PRIMARY KEY, shape POINT);
+                INSERT INTO gpkg_contents (table_name, data_type, identifier, srs_id) VALUES ('parcels', 'features', 'parcels', 4326);
+                INSERT INTO gpkg_geometry_columns VALUES ('parcels', 'shape', 'POINT', 4326, 0, 0);",
+            )
+            .unwrap();
+        let insert_parcel = |gp: &GeoPackage, id: i64, x: f64| {
+            gp.conn
+                .execute(
+                    "INSERT INTO parcels VALUES (?1, ?2)",
+                    params![id, GPKGPoint(point!(x: x, y: 1.0))],
+                )
+                .unwrap();
+        };
+        insert_parcel(&gp, 10, 1.0);
+        gp.create_spatial_index("parcels").unwrap();
+        insert_parcel(&gp, 20, 2.0);
+        gp.conn
+            .execute_batch(
+                "UPDATE parcels SET OBJECTID = 30 WHERE OBJECTID = 10;
+                DELETE FROM parcels WHERE OBJECTID = 20;",
+            )
+            .unwrap();
+        assert_eq!(index_rows(&gp, "rtree_parcels_shape"), vec![(30, 1.0, 1.0)]);
+        let registrations: i64 = gp
+            .conn
+            .query_row(
+                "SELECT count(*) FROM gpkg_extensions WHERE table_name = 'parcels'",
+                [],
+                |r| r.get(0),
+            )
+            .unwrap();
+        assert_eq!(registrations, 1);
 
-        gp.close();
-        fs::remove_file(filename).unwrap();
+        gp.create_layer::<Trail>().unwrap();
+        assert!(matches!(
+            gp.create_spatial_index("Trail"),
+            Err(crate::Error::NoGeometryColumn(_))
+        ));
     }
 
     #[test]
-    #[should_panic]
-    fn fail_on_existing() {
+    fn bbox_queries() {
         let dir = tempdir().unwrap();
-        let filename = dir.path().join("fail_exists.gpkg");
-
-        let gp = GeoPackage::create(&filename).unwrap();
-        gp.create_layer::<TestTableGeom>().unwrap();
-
-        // how do we get a check that the table exists?
-        // make sure that we've got something in gpkg_contents
+        let filename = dir.path().join("bbox.gpkg");
+        let mut gp = GeoPackage::create(&filename).unwrap();
+        let line = |lanes, x: f64| Centerline {
+            lanes,
+            geom: GPKGLineString(line_string![(x: x, y: 0.0), (x: x + 1.0, y: 1.0)]),
+        };
+        for layer in ["indexed", "unindexed"] {
+            gp.create_layer_as::<Centerline>(layer).unwrap();
+            gp.insert_many_into(layer, (0..10).map(|i| line(i, i as f64 * 2.0)))
+                .unwrap();
+        }
+        gp.create_spatial_index("indexed").unwrap();
 
-        gp.close();
+        for layer in ["indexed", "unindexed"] {
+            // lines 2 and 3 cover x 4 to 5 and 6 to 7
+            let lanes: Vec<i64> = gp
+                .get_in_bbox_from::<Centerline>(layer, 4.5, 0.5, 6.0, 2.0)
+                .unwrap()
+                .iter()
+                .map(|l| l.lanes)
+                .collect();
+            assert_eq!(lanes, vec![2, 3]);
+            let query = || gp.query::<Centerline>().layer(layer);
+            // numbered placeholders in other conditions still refer to their own parameters
+            assert_eq!(
+                query()
+                    .where_sql_with("lanes = ?1 OR lanes = ?2", params![3, 9])
+                    .bbox(-100.0, -100.0, 100.0, 100.0)
+                    .count()
+                    .unwrap(),
+                2
+            );
+            assert_eq!(
+                query()
+                    .bbox(0.0, 0.0, 100.0, 100.0)
+                    .filter(Filter::column("lanes").ge(5))
+                    .limit(2)
+                    .count()
+                    .unwrap(),
+                2
+            );
+            assert_eq!(query().bbox(30.0, 0.0, 40.0, 1.0).count().unwrap(), 0);
+        }
+        assert!(matches!(
+            gp.get_in_bbox::<Trail>(0.0, 0.0, 1.0, 1.0),
+            Err(crate::Error::NoGeometryColumn(_))
+        ));
+    }
 
-        let _shouldnt_open = GeoPackage::create(&filename).unwrap();
+    #[test]
+    fn spatial_predicates() {
+        let dir = tempdir().unwrap();
+        let filename = dir.path().join("spatial.gpkg");
+        let mut gp = GeoPackage::create(&filename).unwrap();
+        let line = |lanes, x: f64| Centerline {
+            lanes,
+            geom: GPKGLineString(line_string![(x: x, y: 0.0), (x: x + 1.0, y: 1.0)]),
+        };
+        for layer in ["indexed", "unindexed"] {
+            gp.create_layer_as::<Centerline>(layer).unwrap();
+            gp.insert_many_into(layer, (0..10).map(|i| line(i, i as f64 * 2.0)))
+                .unwrap();
+        }
+        gp.create_spatial_index("indexed").unwrap();
+        let lanes =
+            |records: Vec<Centerline>| -> Vec<i64> { records.iter().map(|l| l.lanes).collect() };
 
-        fs::remove_file(filename).unwrap();
+        for layer in ["indexed", "unindexed"] {
+            // the box of line 1 covers the point, but the line itself doesn't
+            let point = point!(x: 2.2, y: 0.8);
+            assert_eq!(
+                lanes(
+                    gp.get_spatial_from(layer, SpatialPredicate::Intersects, point)
+                        .unwrap()
+                ),
+                Vec::<i64>::new()
+            );
+            assert_eq!(
+                lanes(
+                    gp.get_spatial_from(
+                        layer,
+                        SpatialPredicate::Intersects,
+                        point!(x: 2.5, y: 0.5)
+                    )
+                    .unwrap()
+                ),
+                vec![1]
+            );
+            let area =
+                polygon![(x: 1.5, y: -1.0), (x: 6.5, y: -1.0), (x: 6.5, y: 2.0), (x: 1.5, y: 2.0)];
+            assert_eq!(
+                lanes(
+                    gp.get_spatial_from(layer, SpatialPredicate::Within, area.clone())
+                        .unwrap()
+                ),
+                vec![1, 2]
+            );
+            assert_eq!(
+                lanes(
+                    gp.get_spatial_from(layer, SpatialPredicate::Intersects, area)
+                        .unwrap()
+                ),
+                vec![1, 2, 3]
+            );
+            let near = gp
+                .query::<Centerline>()
+                .layer(layer)
+                .spatial(
+                    SpatialPredicate::PlanarDistanceWithin(1.6),
+                    point!(x: 10.5, y: 1.5),
+                )
+                .filter(Filter::column("lanes").ne(4))
+                .order_by_desc("lanes")
+                .all()
+                .unwrap();
+            assert_eq!(lanes(near), vec![5]);
+            assert_eq!(
+                gp.query::<Centerline>()
+                    .layer(layer)
+                    .spatial(SpatialPredicate::Contains, point!(x: 0.5, y: 0.5))
+                    .count()
+                    .unwrap(),
+                1
+            );
+        }
+        assert!(matches!(
+            gp.get_spatial::<Trail, _>(SpatialPredicate::Intersects, point!(x: 0.0, y: 0.0)),
+            Err(crate::Error::NoGeometryColumn(_))
+        ));
     }
 
     #[test]
-    fn insert_row() {
+    fn nearest_records() {
         let dir = tempdir().unwrap();
-        let filename = dir.path().join("create.gpkg");
-
-        let test_val = TestTableAttr {
-            field1: Some(999),
-            field2: 420,
-            field3: String::from("blah"),
+        let filename = dir.path().join("nearest.gpkg");
+        let mut gp = GeoPackage::create(&filename).unwrap();
+        let stop = |i: i64| Stop {
+            name: format!("{}", i),
+            geom: match i % 7 {
+                0 => None,
+                _ => Some(GPKGPoint(point!(x: (i % 10) as f64, y: (i / 10) as f64))),
+            },
         };
+        for layer in ["indexed", "unindexed"] {
+            gp.create_layer_as::<Stop>(layer).unwrap();
+            gp.insert_many_into(layer, (0..100).map(stop)).unwrap();
+            // an undefined cartesian srs, so distances are planar
+            gp.conn
+                .execute(
+                    "UPDATE gpkg_geometry_columns SET srs_id = -1 WHERE table_name = ?1",
+                    params![layer],
+                )
+                .unwrap();
+        }
+        gp.conn
+            .execute_batch(
+                "DROP TRIGGER rtree_unindexed_geom_insert;
+                DELETE FROM gpkg_extensions WHERE table_name = 'unindexed';",
+            )
+            .unwrap();
 
-        let gp = GeoPackage::create(&filename).unwrap();
-        gp.create_layer::<TestTableAttr>().unwrap();
-        gp.insert_record(&test_val).unwrap();
+        let target = point!(x: 3.2, y: 5.1);
+        let mut expected: Vec<(String, f64)> = (0..100)
+            .map(stop)
+            .filter_map(|s| {
+                let p = s.geom?.0;
+                Some((
+                    s.name,
+                    ((p.x() - 3.2).powi(2) + (p.y() - 5.1).powi(2)).sqrt(),
+                ))
+            })
+            .collect();
+        expected.sort_by(|a, b| a.1.total_cmp(&b.1));
+        for layer in ["indexed", "unindexed"] {
+            for k in [1, 5, 30, 200] {
+                let nearest: Vec<(String, f64)> = gp
+                    .nearest_from::<Stop>(layer, target, k)
+                    .unwrap()
+                    .into_iter()
+                    .map(|(s, d)| (s.name, d))
+                    .collect();
+                // records at the same distance can come in either order
+                assert_eq!(nearest.len(), k.min(expected.len()));
+                for ((_, d), (_, e)) in nearest.iter().zip(&expected) {
+                    assert!((d - e).abs() < 1e-9);
+                }
+                assert_eq!(nearest[0].0, "53");
+            }
+            // far outside of the layer
+            let far = gp
+                .nearest_from::<Stop>(layer, point!(x: 1000.0, y: -500.0), 1)
+                .unwrap();
+            assert_eq!(far[0].0.name, "9");
+            let filtered = gp
+                .query::<Stop>()
+                .layer(layer)
+                .where_sql("CAST(name AS INTEGER) >= 80")
+                .nearest(target, 1)
+                .unwrap();
+            assert_eq!(filtered[0].0.name, "83");
+        }
 
-        // how do we get a check that the table exists?
-        // make sure that we've got something in gpkg_contents
-        let retrieved = &gp.get_all::<TestTableAttr>().unwrap();
+        // a record written without updating the extent in gpkg_contents is still found
+        for layer in ["indexed", "unindexed"] {
+            gp.conn
+                .execute(
+                    &format!("INSERT INTO {} (name, geom) VALUES ('far', ?1)", layer),
+                    params![GPKGPoint(point!(x: 50.0, y: 50.0))],
+                )
+                .unwrap();
+            let all = gp.nearest_from::<Stop>(layer, target, 200).unwrap();
+            assert_eq!(all.len(), expected.len() + 1);
+            assert_eq!(all.last().unwrap().0.name, "far");
+        }
 
-        assert_eq!(test_val, retrieved[0]);
+        // the layers for the types themselves are in WGS 84
+        gp.create_layer::<Stop>().unwrap();
+        gp.insert_many(vec![stop(11), stop(12), stop(33)]).unwrap();
+        let nearest = gp.nearest::<Stop>(point!(x: 1.0, y: 0.0), 2).unwrap();
+        assert_eq!(nearest[0].0.name, "11");
+        // a degree of latitude
+        assert!((nearest[0].1 - 111_195.0).abs() < 1.0);
+        assert_eq!(nearest[1].0.name, "12");
 
-        gp.close();
-        fs::remove_file(filename).unwrap();
+        // distances wrap around the antimeridian, even though bounding boxes don't
+        gp.create_layer_as::<Stop>("dateline").unwrap();
+        let east = (0..990).map(|i| Stop {
+            name: format!("east {}", i),
+            geom: Some(GPKGPoint(point!(x: 170.0 + i as f64 * 0.01, y: 0.0))),
+        });
+        let west = Stop {
+            name: "west".to_owned(),
+            geom: Some(GPKGPoint(point!(x: -179.999, y: 0.0))),
+        };
+        gp.insert_many_into("dateline", east.chain([west])).unwrap();
+        let nearest = gp
+            .nearest_from::<Stop>("dateline", point!(x: 179.995, y: 0.0), 1)
+            .unwrap();
+        assert_eq!(nearest[0].0.name, "west");
+        assert!(nearest[0].1 < 1000.0);
+
+        assert!(matches!(
+            gp.query::<Stop>().limit(2).nearest(target, 1),
+            Err(crate::Error::InvalidQuery(_))
+        ));
+        gp.create_layer::<Trail>().unwrap();
+        assert!(matches!(
+            gp.nearest::<Trail>(target, 1),
+            Err(crate::Error::NoGeometryColumn(_))
+        ));
     }
 
     #[test]
-    fn insert_many() {
+    fn sql_functions() {
         let dir = tempdir().unwrap();
-        let filename = dir.path().join("insert_many.gpkg");
-        let mut gp = GeoPackage::create(&filename).unwrap();
-        gp.create_layer::<TestTableGeom>()
-            .expect("Problem creating table");
-        let val = TestTableGeom {
-            start_node: Some(42),
-            end_node: 918,
-            rev_cost: "Test values".to_owned(),
-            geom: GPKGLineStringZ(vec![
-                GPKGPointZ {
-                    x: 40.0,
-                    y: -105.0,
-                    z: 5280.0,
-                },
-                GPKGPointZ {
-                    x: 41.0,
-                    y: -106.0,
-                    z: 5280.0,
-                },
-            ]),
-        };
-        let val2 = TestTableGeom {
-            start_node: Some(45),
-            end_node: 918,
-            rev_cost: "Test values".to_owned(),
-            geom: GPKGLineStringZ(vec![
-                GPKGPointZ {
-                    x: 40.0,
-                    y: -105.0,
-                    z: 5280.0,
-                },
-                GPKGPointZ {
-                    x: 41.0,
-                    y: -106.0,
-                    z: 5280.0,
-                },
-            ]),
-        };
-        let val3 = TestTableGeom {
-            start_node: Some(48),
-            end_node: 918,
-            rev_cost: "Test values".to_owned(),
+        let filename = dir.path().join("sql_functions.gpkg");
+        let gp = GeoPackage::create(&filename).unwrap();
+        gp.create_layer::<TestTableGeom>().unwrap();
+        gp.insert_record(&TestTableGeom {
+            start_node: None,
+            end_node: 1,
+            rev_cost: "".to_owned(),
             geom: GPKGLineStringZ(vec![
                 GPKGPointZ {
-                    x: 40.0,
-                    y: -105.0,
-                    z: 5280.0,
+                    x: 1.5,
+                    y: 2.0,
+                    z: 10.0,
                 },
                 GPKGPointZ {
-                    x: 41.0,
-                    y: -106.0,
-                    z: 5280.0,
+                    x: 3.0,
+                    y: -4.0,
+                    z: 12.5,
                 },
             ]),
-        };
-        let vec_for_insert = vec![val, val2, val3];
-        gp.insert_many(&vec_for_insert).unwrap();
+        })
+        .unwrap();
+        let row: (String, i32, String, f64, f64) = gp
+            .conn
+            .query_row(
+                "SELECT ST_GeometryType(geom), ST_SRID(geom), ST_AsText(geom), ST_MinY(geom), ST_MaxX(geom)
+                FROM test",
+                [],
+                |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?, r.get(4)?)),
+            )
+            .unwrap();
+        assert_eq!(
+            row,
+            (
+                "LINESTRINGZ".to_owned(),
+                4326,
+                "LINESTRING Z (1.5 2 10, 3 -4 12.5)".to_owned(),
+                -4.0,
+                3.0
+            )
+        );
+
+        let text: String = gp
+            .conn
+            .query_row(
+                "SELECT ST_AsText(?1)",
+                params![GPKGGeometryCollection(geo_types::GeometryCollection(vec![
+                    Geometry::Point(point!(x: 1.0, y: 2.0)),
+                    Geometry::MultiPoint(
+                        vec![point!(x: 3.0, y: 4.0), point!(x: 5.0, y: 6.0)].into()
+                    ),
+                    Geometry::Polygon(
+                        polygon![(x: 0.0, y: 0.0), (x: 1.0, y: 0.0), (x: 1.0, y: 1.0)]
+                    ),
+                ]))],
+                |r| r.get(0),
+            )
+            .unwrap();
+        assert_eq!(
+            text,
+            "GEOMETRYCOLLECTION (POINT (1 2), MULTIPOINT ((3 4), (5 6)), POLYGON ((0 0, 1 0, 1 1, 0 0)))"
+        );
+        let nulls: (Option<String>, Option<i32>, Option<String>) = gp
+            .conn
+            .query_row(
+                "SELECT ST_GeometryType(NULL), ST_SRID(NULL), ST_AsText(NULL)",
+                [],
+                |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)),
+            )
+            .unwrap();
+        assert_eq!(nulls, (None, None, None));
+
+        for (expected, actual, assignable) in [
+            ("GEOMETRY", "POINTZ", true),
+            ("LINESTRING", "LINESTRING", true),
+            ("LINESTRINGZ", "LINESTRING", true),
+            ("CURVE", "LINESTRING", true),
+            ("GEOMETRYCOLLECTION", "MULTIPOLYGON", true),
+            ("MULTISURFACE", "MULTIPOLYGON", true),
+            ("POINT", "LINESTRING", false),
+            ("MULTIPOINT", "POINT", false),
+            ("LINESTRING", "GEOMETRY", false),
+        ] {
+            let result: bool = gp
+                .conn
+                .query_row(
+                    "SELECT GPKG_IsAssignable(?1, ?2)",
+                    params![expected, actual],
+                    |r| r.get(0),
+                )
+                .unwrap();
+            assert_eq!(result, assignable, "{} from {}", expected, actual);
+        }
 
+        // the geometry type trigger written by GDAL for layers created with it
+        gp.create_layer_as::<Centerline>("roads").unwrap();
+        gp.conn
+            .execute_batch(
+                "CREATE TRIGGER roads_geom_type BEFORE INSERT ON roads FOR EACH ROW BEGIN
+                SELECT RAISE(ABORT, 'insert on roads violates constraint: ST_GeometryType(NEW.geom) is not assignable')
+                WHERE (SELECT geometry_type_name FROM gpkg_geometry_columns WHERE Lower(table_name) = Lower('roads')
+                AND Lower(column_name) = Lower('geom') AND gpkg_IsAssignable(geometry_type_name, ST_GeometryType(NEW.geom)) = 0) IS NOT NULL;
+                END;",
+            )
+            .unwrap();
+        gp.insert_record_into(
+            "roads",
+            &Centerline {
+                lanes: 2,
+                geom: GPKGLineString(line_string![(x: 0.0, y: 0.0), (x: 1.0, y: 1.0)]),
+            },
+        )
+        .unwrap();
+        assert!(gp
+            .conn
+            .execute(
+                "INSERT INTO roads (lanes, geom) VALUES (1, ?1)",
+                params![GPKGPoint(point!(x: 1.0, y: 2.0))],
+            )
+            .is_err());
+
+        // the functions are registered again when the file is reopened
         gp.close();
-        let db2 = GeoPackage::open(&filename).unwrap();
-        let retrieved = db2.get_all::<TestTableGeom>().unwrap();
-        assert!(retrieved.len() == vec_for_insert.len());
+        let gp = GeoPackage::open(&filename).unwrap();
+        let srid: i32 = gp
+            .conn
+            .query_row("SELECT ST_SRID(geom) FROM roads", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(srid, 4326);
     }
 
     #[test]
-    fn multipoint_test() {
-        #[derive(GPKGModel)]
-        struct MPTest {
-            id: i64,
-            #[geom_field("MultiPoint")]
-            geom: GPKGMultiPoint,
-        }
+    fn dynamic_features() {
+        let dir = tempdir().unwrap();
+        let filename = dir.path().join("dynamic_features.gpkg");
+        let mut gp = GeoPackage::create(&filename).unwrap();
+        gp.create_layer_as::<Centerline>("roads").unwrap();
+        gp.insert_record_into(
+            "roads",
+            &Centerline {
+                lanes: 4,
+                geom: GPKGLineString(line_string![(x: 0.0, y: 0.0), (x: 1.0, y: 1.0)]),
+            },
+        )
+        .unwrap();
+        let roads = gp.get_features_from("roads").unwrap();
+        assert_eq!(roads.len(), 1);
+        assert_eq!(roads[0].fid, Some(1));
+        assert_eq!(
+            roads[0].geometry,
+            Some(line_string![(x: 0.0, y: 0.0), (x: 1.0, y: 1.0)].into())
+        );
+        assert_eq!(roads[0].properties.len(), 1);
+        assert_eq!(roads[0].get_as::<i64>("lanes").unwrap(), 4);
+        assert!(matches!(
+            roads[0].get_as::<String>("lanes"),
+            Err(crate::Error::SQLiteError(_))
+        ));
+        assert!(matches!(
+            roads[0].get_as::<i64>("name"),
+            Err(crate::Error::UnknownColumn(_))
+        ));
 
-        let filename = Path::new("./test_data/multipoint.gpkg");
-        let gp = GeoPackage::create(&filename).unwrap();
+        // a layer written by another tool, with its own primary key name and srs
+        gp.conn
+            .execute_batch(
+                "CREATE TABLE parcels (OBJECTID INTEGER PRIMARY KEY AUTOINCREMENT, owner TEXT, area REAL DEFAULT 0, shape POLYGON);
+                INSERT INTO gpkg_contents (table_name, data_type, identifier, srs_id) VALUES ('parcels', 'features', 'parcels', 0);
+                INSERT INTO gpkg_geometry_columns VALUES ('parcels', 'shape', 'POLYGON', 0, 0, 0);",
+            )
+            .unwrap();
+        let mut parcel = crate::Feature::new();
+        parcel.set("owner", "Smith").unwrap();
+        parcel.set("area", 100.0).unwrap();
+        parcel.geometry = Some(
+            polygon![(x: 0.0, y: 0.0), (x: 10.0, y: 0.0), (x: 10.0, y: 10.0), (x: 0.0, y: 10.0)]
+                .into(),
+        );
+        let first = gp.insert_feature_into("parcels", &parcel).unwrap();
+        let mut unowned = crate::Feature::new();
+        unowned.fid = Some(10);
+        unowned.geometry = Some(point!(x: -5.0, y: 20.0).into());
+        let mut empty = crate::Feature::new();
+        empty.set("owner", Value::Null).unwrap();
+        let fids = gp
+            .insert_features_into("parcels", vec![unowned, empty])
+            .unwrap();
+        assert_eq!((first, fids), (1, vec![10, 11]));
 
-        gp.create_layer::<MPTest>().unwrap();
+        let stored = gp.get_feature_from("parcels", 10).unwrap().unwrap();
+        assert_eq!(stored.get("owner"), Some(&Value::Null));
+        assert_eq!(stored.get("area"), Some(&Value::Real(0.0)));
+        let srid: i32 = gp
+            .conn
+            .query_row(
+                "SELECT ST_SRID(shape) FROM parcels WHERE OBJECTID = 1",
+                [],
+                |r| r.get(0),
+            )
+            .unwrap();
+        assert_eq!(srid, 0);
+        assert_eq!(
+            gp.get_layer_extent("parcels").unwrap(),
+            Some(Extent {
+                min_x: -5.0,
+                min_y: 0.0,
+                max_x: 10.0,
+                max_y: 20.0
+            })
+        );
 
-        let sample = MPTest {
-            id: 99,
-            geom: GPKGMultiPoint(geo_types::MultiPoint::new(vec![
-                (coord! {x: 1.0, y: 1.0}).into(),
-                (coord! {x: 3.0, y: 3.0}).into(),
-                (coord! {x: 5.0, y: 5.0}).into(),
-                (coord! {x: 7.0, y: 7.0}).into(),
-            ])),
-        };
+        // only the geometry and the given properties are written
+        let mut sold = crate::Feature::new();
+        sold.set("owner", "Jones").unwrap();
+        sold.geometry = Some(point!(x: 1.0, y: 1.0).into());
+        gp.update_feature_in("parcels", 1, &sold).unwrap();
+        let updated = gp
+            .get_features_where_from("parcels", "owner = ?", params!["Jones"])
+            .unwrap();
+        assert_eq!(updated.len(), 1);
+        assert_eq!(updated[0].get_as::<f64>("area").unwrap(), 100.0);
+        assert_eq!(gp.get_layer_extent("parcels").unwrap().unwrap().max_x, 1.0);
+        assert!(matches!(
+            gp.update_feature_in("parcels", 99, &sold),
+            Err(crate::Error::UnknownFeature(99))
+        ));
 
-        gp.insert_record(&sample).unwrap();
+        assert!(gp.delete_feature_from("parcels", 10).unwrap());
+        assert!(!gp.delete_feature_from("parcels", 10).unwrap());
+        assert_eq!(
+            gp.get_layer_extent("parcels").unwrap(),
+            Some(Extent {
+                min_x: 1.0,
+                min_y: 1.0,
+                max_x: 1.0,
+                max_y: 1.0
+            })
+        );
+        assert_eq!(gp.get_feature_from("parcels", 10).unwrap(), None);
 
-        gp.close();
+        let mut wrong = crate::Feature::new();
+        wrong.set("zoning", "R1").unwrap();
+        assert!(matches!(
+            gp.insert_feature_into("parcels", &wrong),
+            Err(crate::Error::UnknownColumn(_))
+        ));
+        gp.create_layer::<Trail>().unwrap();
+        assert!(matches!(
+            gp.insert_feature_into("trails", &sold),
+            Err(crate::Error::NoGeometryColumn(_))
+        ));
+        assert!(matches!(
+            gp.get_features_from("missing"),
+            Err(crate::Error::UnknownLayer(_))
+        ));
     }
 
-    fn get_test_multilinestring() -> geo_types::MultiLineString<f64> {
-        let ls1: geo_types::LineString<f64> = geo_types::LineString::new(vec![
-            coord! {x: -105.0, y: 40.0},
-            coord! {x: -106.0, y: 41.5},
-            coord! {x: -107.0, y: 43.0},
-        ]);
+    #[test]
+    fn features_of_z_and_m_layers() {
+        let dir = tempdir().unwrap();
+        let filename = dir.path().join("z_features.gpkg");
+        let gp = GeoPackage::create(&filename).unwrap();
+        gp.create_layer::<TestTableGeom>().unwrap();
+        let point = |x, y, z| GPKGPointZ { x, y, z };
+        gp.insert_record(&TestTableGeom {
+            start_node: None,
+            end_node: 1,
+            rev_cost: "a".to_owned(),
+            geom: GPKGLineStringZ(vec![point(1.0, 2.0, 3.0), point(4.0, 5.0, 6.0)]),
+        })
+        .unwrap();
+        let features = gp.get_features_from("test").unwrap();
+        assert_eq!(
+            features[0].geometry,
+            Some(line_string![(x: 1.0, y: 2.0), (x: 4.0, y: 5.0)].into())
+        );
 
-        let ls2: geo_types::LineString<f64> = geo_types::LineString::new(vec![
-            coord! {x: -15.0, y: 4.0},
-            coord! {x: -16.0, y: 4.5},
-            coord! {x: -17.0, y: 4.0},
-        ]);
-        geo_types::MultiLineString::new(vec![ls1, ls2])
+        // a big endian multipoint with Z and M values, whose points have their own headers
+        let mut blob = vec![b'G', b'P', 0, 0];
+        blob.extend(4326_i32.to_be_bytes());
+        blob.push(0);
+        blob.extend(3004_u32.to_be_bytes());
+        blob.extend(2_u32.to_be_bytes());
+        for (x, y) in [(7.0_f64, 8.0_f64), (9.0, 10.0)] {
+            blob.push(0);
+            blob.extend(3001_u32.to_be_bytes());
+            for ordinate in [x, y, 100.0, 200.0] {
+                blob.extend(ordinate.to_be_bytes());
+            }
+        }
+        gp.conn
+            .execute_batch(
+                "CREATE TABLE stations (fid INTEGER PRIMARY KEY AUTOINCREMENT, geom MULTIPOINT);
+                INSERT INTO gpkg_contents (table_name, data_type, identifier, srs_id) VALUES ('stations', 'features', 'stations', 4326);
+                INSERT INTO gpkg_geometry_columns VALUES ('stations', 'geom', 'MULTIPOINT', 4326, 1, 1);",
+            )
+            .unwrap();
+        gp.conn
+            .execute("INSERT INTO stations (geom) VALUES (?1)", params![blob])
+            .unwrap();
+        let station = gp.get_feature_from("stations", 1).unwrap().unwrap();
+        assert_eq!(
+            station.geometry,
+            Some(MultiPoint::from(vec![(7.0, 8.0), (9.0, 10.0)]).into())
+        );
     }
 
     #[test]
-    fn multilinestring_test() {
-        #[derive(GPKGModel)]
-        struct MPTest {
-            id: i64,
-            #[geom_field("MultiLineString")]
-            geom: GPKGMultiLineString,
-        }
+    fn layer_introspection() {
+        let dir = tempdir().unwrap();
+        let filename = dir.path().join("introspection.gpkg");
+        let mut gp = GeoPackage::create(&filename).unwrap();
+        assert!(gp.list_layers().unwrap().is_empty());
+        gp.create_layer::<Trail>().unwrap();
+        gp.create_layer::<Stop>().unwrap();
+        gp.insert_many(vec![
+            Stop {
+                name: String::from("a"),
+                geom: Some(GPKGPoint(point!(x: 1.0, y: 2.0))),
+            },
+            Stop {
+                name: String::from("b"),
+                geom: Some(GPKGPoint(point!(x: 3.0, y: -1.0))),
+            },
+        ])
+        .unwrap();
+        gp.conn
+            .execute(
+                "INSERT INTO gpkg_contents (table_name, data_type, srs_id) VALUES ('elevation', '2d-gridded-coverage', 4326)",
+                [],
+            )
+            .unwrap();
 
-        let filename = Path::new("./test_data/multilinestring.gpkg");
+        let layers = gp.list_layers().unwrap();
+        let names: Vec<&str> = layers.iter().map(|l| l.name.as_str()).collect();
+        assert_eq!(names, vec!["elevation", "stops", "trails"]);
+        assert_eq!(
+            layers[0].data_type,
+            GPKGDataType::Other(String::from("2d-gridded-coverage"))
+        );
+        assert_eq!(layers[0].data_type.as_str(), "2d-gridded-coverage");
+        assert_eq!(layers[1].data_type, GPKGDataType::Features);
+        assert_eq!(
+            layers[1].extent,
+            Some(Extent {
+                min_x: 1.0,
+                min_y: -1.0,
+                max_x: 3.0,
+                max_y: 2.0
+            })
+        );
+        assert_eq!(layers[1].srs_id, Some(4326));
+        assert!(layers[1].last_change.as_str() > "2000-01-01T00:00:00.000Z");
+
+        let trails = gp.get_layer_definition("trails").unwrap();
+        assert_eq!(trails.data_type, GPKGDataType::Attributes);
+        assert_eq!(trails.identifier.as_deref(), Some("Trails"));
+        assert_eq!(trails.description.as_deref(), Some("Backcountry trails"));
+        assert_eq!(trails.extent, None);
+        assert_eq!(trails.srs_id, None);
+        assert!(matches!(
+            gp.get_layer_definition("missing"),
+            Err(crate::Error::UnknownLayer(_))
+        ));
+
+        let columns = gp.get_layer_columns("stops").unwrap();
+        assert_eq!(
+            columns,
+            vec![
+                TableColumn {
+                    name: String::from("fid"),
+                    declared_type: String::from("INTEGER"),
+                    not_null: false,
+                    primary_key: true,
+                },
+                TableColumn {
+                    name: String::from("name"),
+                    declared_type: String::from("TEXT"),
+                    not_null: true,
+                    primary_key: false,
+                },
+                TableColumn {
+                    name: String::from("geom"),
+                    declared_type: String::from("BLOB"),
+                    not_null: false,
+                    primary_key: false,
+                },
+            ]
+        );
+        assert_eq!(columns[2].column_type(), Some(ColumnType::Blob));
+        assert!(matches!(
+            gp.get_layer_columns("missing"),
+            Err(crate::Error::UnknownLayer(_))
+        ));
+
+        assert_eq!(
+            gp.get_geometry_column("stops").unwrap(),
+            Some(GeometryColumnDefinition {
+                name: String::from("geom"),
+                geometry_type: String::from("POINT"),
+                srs_id: 4326,
+                z: MZOption::Prohibited,
+                m: MZOption::Prohibited,
+            })
+        );
+        assert_eq!(gp.get_geometry_column("trails").unwrap(), None);
+    }
+
+    #[test]
+    fn layer_builder() {
+        let dir = tempdir().unwrap();
+        let filename = dir.path().join("layer_builder.gpkg");
         let gp = GeoPackage::create(&filename).unwrap();
 
-        gp.create_layer::<MPTest>().unwrap();
+        // the same layer as the derive creates for Centerline
+        gp.layer_builder("roads")
+            .column(ColumnDefinition::new("lanes", ColumnType::Integer).not_null())
+            .geometry("geom", "LineString", 4326)
+            .geometry_not_null()
+            .create()
+            .unwrap();
+        gp.check_layer_schema_of::<Centerline>("roads").unwrap();
+        assert_eq!(
+            gp.get_layer_definition("roads").unwrap().data_type,
+            GPKGDataType::Features
+        );
 
-        let test_geom = GPKGMultiLineString(get_test_multilinestring());
-        dbg!(test_geom.to_wkb().unwrap());
+        gp.layer_builder("elevations")
+            .identifier("Spot elevations")
+            .description("Surveyed points")
+            .geometry("location", "pointzm", 0)
+            .column(ColumnDefinition::new("code", ColumnType::Text).unique())
+            .column(
+                ColumnDefinition::new("height", ColumnType::Real)
+                    .with_default("0")
+                    .with_check("height >= 0"),
+            )
+            .unique_index(&["code", "height"])
+            // column names are case insensitive, as they are in sqlite
+            .index(&["Height"])
+            .spatial_index()
+            .create()
+            .unwrap();
+        let layer = gp.get_layer_definition("elevations").unwrap();
+        assert_eq!(layer.identifier.as_deref(), Some("Spot elevations"));
+        assert_eq!(layer.description.as_deref(), Some("Surveyed points"));
+        assert_eq!(layer.srs_id, Some(0));
+        let geom = gp.get_geometry_column("elevations").unwrap().unwrap();
+        assert_eq!(
+            (
+                geom.name.as_str(),
+                geom.geometry_type.as_str(),
+                geom.z,
+                geom.m
+            ),
+            (
+                "location",
+                "POINT",
+                MZOption::Mandatory,
+                MZOption::Mandatory
+            )
+        );
+        assert!(gp.has_spatial_index("elevations").unwrap());
+        let names: Vec<String> = gp
+            .get_layer_columns("elevations")
+            .unwrap()
+            .into_iter()
+            .map(|c| c.name)
+            .collect();
+        assert_eq!(names, vec!["fid", "location", "code", "height"]);
+        assert!(gp
+            .conn
+            .execute("INSERT INTO elevations (code, height) VALUES ('a', -1)", [])
+            .is_err());
 
-        let sample = MPTest {
-            id: 99,
-            geom: test_geom,
+        gp.layer_builder("notes")
+            .data_type(GPKGDataType::Attributes)
+            .column(ColumnDefinition::new("text", ColumnType::Text))
+            .create()
+            .unwrap();
+        assert_eq!(
+            gp.get_layer_definition("notes").unwrap().data_type,
+            GPKGDataType::Attributes
+        );
+        let sql = gp
+            .layer_builder("notes")
+            .column(ColumnDefinition::new("text", ColumnType::Text))
+            .create_sql()
+            .unwrap();
+        assert!(sql.starts_with("BEGIN;\nCREATE TABLE \"notes\""));
+        assert!(gp.layer_builder("notes").create().is_err());
+
+        assert!(matches!(
+            gp.layer_builder("a")
+                .geometry("geom", "Blob", 4326)
+                .create(),
+            Err(crate::Error::InvalidLayer(_))
+        ));
+        assert!(matches!(
+            gp.layer_builder("a")
+                .geometry("geom", "PointZZ", 4326)
+                .create(),
+            Err(crate::Error::InvalidLayer(_))
+        ));
+        assert!(matches!(
+            gp.layer_builder("a")
+                .column(ColumnDefinition::new("Name", ColumnType::Text))
+                .column(ColumnDefinition::new("name", ColumnType::Text))
+                .create(),
+            Err(crate::Error::InvalidLayer(_))
+        ));
+        assert!(matches!(
+            gp.layer_builder("a")
+                .data_type(GPKGDataType::Features)
+                .create(),
+            Err(crate::Error::NoGeometryColumn(_))
+        ));
+        assert!(matches!(
+            gp.layer_builder("a")
+                .data_type(GPKGDataType::Tiles)
+                .create(),
+            Err(crate::Error::InvalidLayer(_))
+        ));
+        assert!(matches!(
+            gp.layer_builder("a").spatial_index().create(),
+            Err(crate::Error::NoGeometryColumn(_))
+        ));
+        assert!(matches!(
+            gp.layer_builder("a")
+                .geometry("geom", "Point", 3857)
+                .create(),
+            Err(crate::Error::UnknownSrs(3857))
+        ));
+        assert!(matches!(
+            gp.layer_builder("a").index(&["missing"]).create(),
+            Err(crate::Error::UnknownColumn(_))
+        ));
+        // nothing is left behind by the failures
+        assert_eq!(gp.list_layers().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn drop_rename_and_copy_layers() {
+        let dir = tempdir().unwrap();
+        let filename = dir.path().join("manage_layers.gpkg");
+        let mut gp = GeoPackage::create(&filename).unwrap();
+        let create_roads = |gp: &GeoPackage, name: &str| {
+            gp.layer_builder(name)
+                .description("Roads")
+                .geometry("geom", "LineString", 4326)
+                .column(ColumnDefinition::new("name", ColumnType::Text))
+                .column(ColumnDefinition::new("surface", ColumnType::Text))
+                .index(&["name"])
+                .spatial_index()
+                .create()
+        };
+        create_roads(&gp, "roads").unwrap();
+        let road = |name: &str, x: f64| {
+            let mut f = crate::Feature::new();
+            f.set("name", name).unwrap();
+            f.set("surface", "Paved").unwrap();
+            f.geometry = Some(line_string![(x: x, y: 0.0), (x: x + 1.0, y: 1.0)].into());
+            f
         };
+        gp.insert_features_into("roads", vec![road("a", 0.0), road("b", 5.0)])
+            .unwrap();
+        gp.add_enum_constraint::<Surface>("roads", "surface")
+            .unwrap();
 
-        gp.insert_record(&sample).unwrap();
+        let references = |gp: &GeoPackage, layer: &str| -> Vec<i64> {
+            [
+                "gpkg_contents",
+                "gpkg_geometry_columns",
+                "gpkg_extensions",
+                "gpkg_data_columns",
+            ]
+            .iter()
+            .map(|t| {
+                gp.conn
+                    .query_row(
+                        &format!("SELECT count(*) FROM {} WHERE table_name = ?1", t),
+                        params![layer],
+                        |r| r.get(0),
+                    )
+                    .unwrap()
+            })
+            .collect()
+        };
+        let schema_object = |gp: &GeoPackage, name: &str| -> bool {
+            gp.conn
+                .query_row(
+                    "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE name = ?1)",
+                    params![name],
+                    |r| r.get(0),
+                )
+                .unwrap()
+        };
+        let rtree_count = |gp: &GeoPackage, rtree: &str| -> i64 {
+            gp.conn
+                .query_row(&format!("SELECT count(*) FROM {}", rtree), [], |r| r.get(0))
+                .unwrap()
+        };
+        assert_eq!(references(&gp, "roads"), vec![1, 1, 1, 1]);
 
-        gp.close();
-    }
+        gp.rename_layer("roads", "streets").unwrap();
+        assert_eq!(references(&gp, "roads"), vec![0, 0, 0, 0]);
+        assert_eq!(references(&gp, "streets"), vec![1, 1, 1, 1]);
+        assert!(!schema_object(&gp, "rtree_roads_geom"));
+        assert!(!schema_object(&gp, "idx_roads_name"));
+        assert!(schema_object(&gp, "idx_streets_name"));
+        assert_eq!(rtree_count(&gp, "rtree_streets_geom"), 2);
+        let streets = gp.get_layer_definition("streets").unwrap();
+        assert_eq!(streets.identifier.as_deref(), Some("streets"));
+        assert_eq!(streets.description.as_deref(), Some("Roads"));
+        assert!(streets.extent.is_some());
+        // the triggers of the new spatial index maintain it
+        gp.insert_feature_into("streets", &road("c", 10.0)).unwrap();
+        assert_eq!(rtree_count(&gp, "rtree_streets_geom"), 3);
+        // the old name can be used again
+        create_roads(&gp, "roads").unwrap();
 
-    fn get_test_multipolygon() -> MultiPolygon<f64> {
-        let poly1_exterior: LineString<f64> = LineString::new(vec![
-            coord! {x: -105.0, y: 40.0},
-            coord! {x: -106.0, y: 43.5},
-            coord! {x: -107.0, y: 41.0},
-            coord! {x: -105.0, y: 40.0},
-        ]);
-        let poly1 = Polygon::new(poly1_exterior, vec![]);
+        gp.copy_layer("streets", "streets_copy").unwrap();
+        assert_eq!(references(&gp, "streets_copy"), vec![1, 1, 1, 1]);
+        assert_eq!(
+            gp.get_features_from("streets_copy").unwrap(),
+            gp.get_features_from("streets").unwrap()
+        );
+        assert_eq!(rtree_count(&gp, "rtree_streets_copy_geom"), 3);
+        assert!(schema_object(&gp, "idx_streets_copy_name"));
+        assert_eq!(
+            gp.get_layer_definition("streets_copy").unwrap().extent,
+            streets.extent.map(|mut e| {
+                e.max_x = 11.0;
+                e
+            })
+        );
 
-        let poly2_exterior: LineString<f64> = LineString::new(vec![
-            coord! {x: -15.0, y: 4.0},
-            coord! {x: 16.0, y: 4.5},
-            coord! {x: -1.0, y: 10.0},
-            coord! {x: -10.0, y: 10.0},
-            coord! {x: -15.0, y: 4.0},
-        ]);
+        // the spatial index of a layer whose primary key isn't fid
+        gp.conn
+            .execute_batch(
+                "CREATE TABLE lots (OBJECTID INTEGER PRIMARY KEY, shape POINT);
+                INSERT INTO gpkg_contents (table_name, data_type, identifier, srs_id) VALUES ('lots', 'features', 'lots', 4326);
+                INSERT INTO gpkg_geometry_columns VALUES ('lots', 'shape', 'POINT', 4326, 0, 0);",
+            )
+            .unwrap();
+        gp.create_spatial_index("lots").unwrap();
+        let insert_lot = |gp: &GeoPackage, layer: &str, id: i64| {
+            gp.conn
+                .execute(
+                    &format!("INSERT INTO {} VALUES (?1, ?2)", layer),
+                    params![id, GPKGPoint(point!(x: 1.0, y: 1.0))],
+                )
+                .unwrap();
+        };
+        let rtree_ids = |gp: &GeoPackage, rtree: &str| -> Vec<i64> {
+            gp.conn
+                .prepare(&format!("SELECT id FROM {} ORDER BY id", rtree))
+                .unwrap()
+                .query_map([], |r| r.get(0))
+                .unwrap()
+                .collect::<rusqlite::Result<_>>()
+                .unwrap()
+        };
+        insert_lot(&gp, "lots", 10);
+        gp.rename_layer("lots", "plots").unwrap();
+        insert_lot(&gp, "plots", 20);
+        assert_eq!(rtree_ids(&gp, "rtree_plots_shape"), vec![10, 20]);
+        gp.copy_layer("plots", "plots_copy").unwrap();
+        gp.conn
+            .execute_batch("UPDATE plots_copy SET OBJECTID = 30 WHERE OBJECTID = 10")
+            .unwrap();
+        assert_eq!(rtree_ids(&gp, "rtree_plots_copy_shape"), vec![20, 30]);
 
-        let poly2_interior: LineString<f64> = LineString::new(vec![
-            coord! {x: -1.53, y: 4.999},
-            coord! {x: 1.609, y: 5.67},
-            coord! {x: -2.345, y: 6.2},
-            coord! {x: -1.53, y: 4.999},
-        ]);
-        let poly2 = Polygon::new(poly2_exterior, vec![poly2_interior]);
+        // a layer in a spatial reference system the other GeoPackage doesn't have
+        gp.conn
+            .execute(
+                "INSERT INTO gpkg_spatial_ref_sys (srs_name, srs_id, organization, organization_coordsys_id, definition, description)
+                VALUES ('WGS 84 / UTM zone 33N', 32633, 'EPSG', 32633, 'PROJCS[\"WGS 84 / UTM zone 33N\"]', '')",
+                [],
+            )
+            .unwrap();
+        gp.layer_builder("parcels")
+            .identifier("Land parcels")
+            .geometry("geom", "Point", 32633)
+            .create()
+            .unwrap();
+        let mut parcel = crate::Feature::new();
+        parcel.geometry = Some(point!(x: 500000.0, y: 4649776.0).into());
+        gp.insert_feature_into("parcels", &parcel).unwrap();
+        let mut other = GeoPackage::create(dir.path().join("other.gpkg")).unwrap();
+        gp.copy_layer_to("parcels", &mut other, "parcels").unwrap();
+        gp.copy_layer_to("streets", &mut other, "roads").unwrap();
+        let copied = other.get_layer_definition("parcels").unwrap();
+        assert_eq!(copied.identifier.as_deref(), Some("Land parcels"));
+        assert_eq!(copied.srs_id, Some(32633));
+        assert_eq!(
+            other.get_features_from("parcels").unwrap()[0].geometry,
+            parcel.geometry
+        );
+        assert_eq!(other.get_features_from("roads").unwrap().len(), 3);
+        assert_eq!(references(&other, "roads"), vec![1, 1, 1, 1]);
+        let constraints: i64 = other
+            .conn
+            .query_row(
+                "SELECT count(*) FROM gpkg_data_column_constraints",
+                [],
+                |r| r.get(0),
+            )
+            .unwrap();
+        assert_eq!(constraints, 2);
+        let schema_tables: Vec<String> = other
+            .conn
+            .prepare(
+                "SELECT table_name FROM gpkg_extensions WHERE extension_name = 'gpkg_schema' ORDER BY table_name",
+            )
+            .unwrap()
+            .query_map([], |r| r.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(
+            schema_tables,
+            vec!["gpkg_data_column_constraints", "gpkg_data_columns"]
+        );
+        assert!(matches!(
+            gp.copy_layer_to("parcels", &mut other, "parcels"),
+            Err(crate::Error::LayerExists(_))
+        ));
+        // the same srs id used for another system
+        let mut mismatched = GeoPackage::create(dir.path().join("mismatched.gpkg")).unwrap();
+        mismatched
+            .conn
+            .execute(
+                "INSERT INTO gpkg_spatial_ref_sys (srs_name, srs_id, organization, organization_coordsys_id, definition, description)
+                VALUES ('WGS 84 / Pseudo-Mercator', 32633, 'EPSG', 3857, 'PROJCS[\"WGS 84 / Pseudo-Mercator\"]', '')",
+                [],
+            )
+            .unwrap();
+        assert!(matches!(
+            gp.copy_layer_to("parcels", &mut mismatched, "parcels"),
+            Err(crate::Error::SrsMismatch(32633))
+        ));
+        assert!(!schema_object(&mismatched, "parcels"));
 
-        MultiPolygon::new(vec![poly1, poly2])
+        gp.drop_layer("streets").unwrap();
+        assert_eq!(references(&gp, "streets"), vec![0, 0, 0, 0]);
+        assert!(!schema_object(&gp, "streets"));
+        assert!(!schema_object(&gp, "rtree_streets_geom"));
+        assert!(!schema_object(&gp, "idx_streets_name"));
+        assert_eq!(rtree_count(&gp, "rtree_streets_copy_geom"), 3);
+
+        assert!(matches!(
+            gp.drop_layer("streets"),
+            Err(crate::Error::UnknownLayer(_))
+        ));
+        assert!(matches!(
+            gp.rename_layer("streets", "avenues"),
+            Err(crate::Error::UnknownLayer(_))
+        ));
+        assert!(matches!(
+            gp.rename_layer("roads", "parcels"),
+            Err(crate::Error::LayerExists(_))
+        ));
+        gp.conn
+            .execute_batch("CREATE VIEW paved AS SELECT * FROM roads WHERE surface = 'Paved'")
+            .unwrap();
+        assert!(matches!(
+            gp.copy_layer("paved", "paved_copy"),
+            Err(crate::Error::InvalidLayer(_))
+        ));
+        gp.drop_layer("paved").unwrap();
+        assert!(!schema_object(&gp, "paved"));
     }
 
     #[test]
-    fn multipolygon_test() {
-        #[derive(GPKGModel)]
-        struct MPTest {
-            id: i64,
-            #[geom_field("MultiPolygon")]
-            geom: GPKGMultiPolygon,
-        }
+    fn spatial_ref_sys_management() {
+        let dir = tempdir().unwrap();
+        let filename = dir.path().join("srs.gpkg");
+        let mut gp = GeoPackage::create(&filename).unwrap();
+        let utm = SpatialRefSys::new(32633, "WGS 84 / UTM zone 33N")
+            .with_organization("EPSG", 32633)
+            .with_definition("PROJCS[\"WGS 84 / UTM zone 33N\"]");
+        gp.new_srs(&utm).unwrap();
+        assert!(matches!(
+            gp.new_srs(&utm),
+            Err(crate::Error::SQLiteError(_))
+        ));
+        let ids: Vec<i64> = gp.list_srs().unwrap().iter().map(|s| s.id).collect();
+        assert_eq!(ids, vec![-1, 0, 4326, 32633]);
+        assert_eq!(gp.get_srs(32633).unwrap(), Some(utm.clone()));
+        assert_eq!(gp.get_srs(4326).unwrap(), Some(wgs84()));
+        assert_eq!(gp.get_srs(3857).unwrap(), None);
 
-        let filename = Path::new("./test_data/multipolygon.gpkg");
-        let gp = GeoPackage::create(&filename).unwrap();
+        let updated = utm.clone().with_description("UTM zone 33N");
+        gp.update_srs(&updated).unwrap();
+        assert_eq!(gp.get_srs(32633).unwrap(), Some(updated));
+        assert!(matches!(
+            gp.update_srs(&SpatialRefSys { id: 3857, ..utm }),
+            Err(crate::Error::UnknownSrs(3857))
+        ));
 
-        gp.create_layer::<MPTest>().unwrap();
+        gp.create_layer::<Stop>().unwrap();
+        gp.insert_many(vec![
+            Stop {
+                name: "a".to_owned(),
+                geom: Some(GPKGPoint(point!(x: 500000.0, y: 4649776.0))),
+            },
+            Stop {
+                name: "b".to_owned(),
+                geom: None,
+            },
+        ])
+        .unwrap();
+        // a geometry written by another tool with a big endian header and WKB
+        let mut big_endian = vec![b'G', b'P', 0, 0];
+        big_endian.extend(4326_i32.to_be_bytes());
+        big_endian.push(0);
+        big_endian.extend(1_u32.to_be_bytes());
+        big_endian.extend(510000.0_f64.to_be_bytes());
+        big_endian.extend(4650000.0_f64.to_be_bytes());
+        gp.conn
+            .execute(
+                "INSERT INTO stops (name, geom) VALUES ('c', ?1)",
+                params![big_endian],
+            )
+            .unwrap();
+        assert_eq!(gp.get_layer_srs_id("stops").unwrap(), Some(4326));
 
-        let test_geom = GPKGMultiPolygon(get_test_multipolygon());
-        dbg!(test_geom.to_wkb().unwrap());
+        gp.update_layer_srs_id("stops", 32633).unwrap();
+        assert_eq!(gp.get_layer_srs_id("stops").unwrap(), Some(32633));
+        assert_eq!(
+            gp.get_geometry_column("stops").unwrap().unwrap().srs_id,
+            32633
+        );
+        let srs_ids: Vec<Option<i64>> = gp
+            .conn
+            .prepare("SELECT ST_SRID(geom) FROM stops ORDER BY fid")
+            .unwrap()
+            .query_map([], |r| r.get(0))
+            .unwrap()
+            .map(|r| r.unwrap())
+            .collect();
+        assert_eq!(srs_ids, vec![Some(32633), None, Some(32633)]);
+        let stops: Vec<Stop> = gp.get_all().unwrap();
+        assert_eq!(
+            stops[2].geom.as_ref().map(|g| g.0),
+            Some(point!(x: 510000.0, y: 4650000.0))
+        );
+        // the spatial index still finds the features
+        assert_eq!(
+            gp.get_in_bbox::<Stop>(505000.0, 4600000.0, 515000.0, 4700000.0)
+                .unwrap()
+                .len(),
+            1
+        );
 
-        let sample = MPTest {
-            id: 99,
-            geom: test_geom,
+        // records written afterwards are encoded with the srs of the layer too
+        let stop = |name: &str, x| Stop {
+            name: name.to_owned(),
+            geom: Some(GPKGPoint(point!(x: x, y: 4649776.0))),
         };
+        gp.insert_record(&stop("d", 501000.0)).unwrap();
+        gp.insert_many(vec![stop("e", 502000.0)]).unwrap();
+        gp.update_record(2, &stop("b", 503000.0)).unwrap();
+        gp.upsert(6, &stop("f", 504000.0)).unwrap();
+        let srs_ids: Vec<Option<i64>> = gp
+            .conn
+            .prepare("SELECT ST_SRID(geom) FROM stops ORDER BY fid")
+            .unwrap()
+            .query_map([], |r| r.get(0))
+            .unwrap()
+            .map(|r| r.unwrap())
+            .collect();
+        assert_eq!(srs_ids, vec![Some(32633); 6]);
 
-        gp.insert_record(&sample).unwrap();
+        assert!(matches!(
+            gp.update_layer_srs_id("stops", 3857),
+            Err(crate::Error::UnknownSrs(3857))
+        ));
+        assert!(matches!(
+            gp.update_layer_srs_id("nowhere", 4326),
+            Err(crate::Error::UnknownLayer(_))
+        ));
+        assert!(matches!(
+            gp.get_layer_srs_id("nowhere"),
+            Err(crate::Error::UnknownLayer(_))
+        ));
+        match gp.delete_srs(32633) {
+            Err(crate::Error::SrsInUse { srs_id, layers }) => {
+                assert_eq!(srs_id, 32633);
+                assert_eq!(layers, vec!["stops".to_owned()]);
+            }
+            r => panic!("expected the srs to be in use, got {:?}", r),
+        }
+        assert!(matches!(
+            gp.delete_srs(4326),
+            Err(crate::Error::RequiredSrs(4326))
+        ));
+        assert!(matches!(
+            gp.delete_srs(3857),
+            Err(crate::Error::UnknownSrs(3857))
+        ));
 
-        gp.close();
+        gp.update_layer_srs_id("stops", 4326).unwrap();
+        gp.delete_srs(32633).unwrap();
+        assert_eq!(gp.get_srs(32633).unwrap(), None);
+    }
+
+    #[test]
+    fn epsg_catalog() {
+        let codes = SpatialRefSys::epsg_codes();
+        assert_eq!(codes.len(), 7 + 6 + 120 + 11 + 23);
+        assert!(codes.windows(2).all(|w| w[0] < w[1]));
+        for code in codes {
+            let srs = SpatialRefSys::from_epsg(code).unwrap();
+            assert_eq!((srs.id, srs.organization_coordsys_id), (code, code));
+            assert_eq!(srs.organization, "EPSG");
+            assert!(srs
+                .definition
+                .ends_with(&format!("AUTHORITY[\"EPSG\",\"{}\"]]", code)));
+            assert_eq!(
+                srs.definition.matches('[').count(),
+                srs.definition.matches(']').count()
+            );
+        }
+        assert_eq!(SpatialRefSys::from_epsg(32661), None);
+        assert_eq!(SpatialRefSys::from_epsg(25827), None);
+
+        // the definition GeoPackages have always been created with
+        assert_eq!(
+            SpatialRefSys::from_epsg(4326).unwrap().definition,
+            "GEOGCS[\"WGS 84\",DATUM[\"WGS_1984\",SPHEROID[\"WGS 84\",6378137,298.257223563,AUTHORITY[\"EPSG\",\"7030\"]],AUTHORITY[\"EPSG\",\"6326\"]],PRIMEM[\"Greenwich\",0,AUTHORITY[\"EPSG\",\"8901\"]],UNIT[\"degree\",0.0174532925199433,AUTHORITY[\"EPSG\",\"9122\"]],AUTHORITY[\"EPSG\",\"4326\"]]"
+        );
+        let utm = SpatialRefSys::from_epsg(32733).unwrap();
+        assert_eq!(utm.name, "WGS 84 / UTM zone 33S");
+        assert_eq!(
+            utm.description,
+            "Between 12°E and 18°E, southern hemisphere"
+        );
+        assert!(utm
+            .definition
+            .contains("PARAMETER[\"central_meridian\",15],PARAMETER[\"scale_factor\",0.9996],PARAMETER[\"false_easting\",500000],PARAMETER[\"false_northing\",10000000]"));
+        assert_eq!(
+            SpatialRefSys::from_epsg(32601).unwrap().description,
+            "Between 180°W and 174°W, northern hemisphere"
+        );
+        assert_eq!(
+            SpatialRefSys::from_epsg(25832).unwrap().name,
+            "ETRS89 / UTM zone 32N"
+        );
+        assert!(SpatialRefSys::from_epsg(26918)
+            .unwrap()
+            .definition
+            .starts_with("PROJCS[\"NAD83 / UTM zone 18N\",GEOGCS[\"NAD83\""));
+
+        let dir = tempdir().unwrap();
+        let filename = dir.path().join("epsg.gpkg");
+        let gp = GeoPackage::create(&filename).unwrap();
+        gp.new_srs(27700).unwrap();
+        assert_eq!(gp.get_srs(27700).unwrap(), SpatialRefSys::from_epsg(27700));
+        assert!(matches!(
+            gp.new_srs(9999),
+            Err(crate::Error::UnknownEpsgCode(9999))
+        ));
+        gp.layer_builder("parcels")
+            .geometry("geom", "Polygon", 27700)
+            .create()
+            .unwrap();
+        assert_eq!(gp.get_layer_srs_id("parcels").unwrap(), Some(27700));
     }
 }
@@ -0,0 +1,731 @@
+use crate::extensions;
+use crate::extent::{self, Extent};
+use crate::filter::{to_value, Filter};
+use crate::result::{Error, Result};
+use crate::spatial::{self, DistanceMetric, SpatialPredicate};
+use crate::sql;
+use crate::types::GPKGGeometry;
+use crate::GPKGModel;
+use geo_types::{Geometry, Point};
+use rusqlite::types::{FromSql, Value};
+use rusqlite::{params_from_iter, Connection, Row, Rows, Statement, ToSql};
+use std::marker::PhantomData;
+
+/// A read of the records of a layer, created with [GeoPackage::query](crate::GeoPackage::query).
+///
+/// By default every record in the layer named by the [GPKGModel] is read. Conditions added with
+/// [Query::filter] and the `where_sql` methods are combined with AND. The records can be
+/// collected with [Query::all], read in batches with [Query::for_each_batch], or streamed
+/// one at a time by preparing the query with [Query::prepare].
+///
+/// Records can be limited to the ones whose geometry is in a bounding box with [Query::bbox],
+/// or that have an exact spatial relationship to another geometry with [Query::spatial].
+///
+/// The records nearest to a point are found with [Query::nearest].
+///
+/// Records can also be counted and aggregated in SQL with [Query::count], [Query::min] and similar methods.
+///
+/// Results can be paged through with [Query::limit] and [Query::offset], or more efficiently by fid
+/// with [Query::after_fid] and [Query::all_with_fids].
+/// # Examples
+/// ```
+/// # use gpkg::{GeoPackage, GPKGModel};
+/// # use tempfile::tempdir;
+/// # let dir = tempdir().unwrap();
+/// # let path = dir.path().join("paging.gpkg");
+/// # let mut gp = GeoPackage::create(path).unwrap();
+/// #[derive(GPKGModel)]
+/// struct Item {
+///     length: f64,
+/// }
+///
+/// gp.create_layer::<Item>().unwrap();
+/// gp.insert_many((0..25).map(|i| Item { length: i as f64 })).unwrap();
+///
+/// let longest = gp.query::<Item>().order_by_desc("length").limit(3).all().unwrap();
+/// assert_eq!(longest[0].length, 24.0);
+///
+/// let mut last_fid = 0;
+/// let mut pages = 0;
+/// loop {
+///     let page = gp.query::<Item>().after_fid(last_fid).limit(10).all_with_fids().unwrap();
+///     match page.last() {
+///         Some((fid, _)) => last_fid = *fid,
+///         None => break,
+///     }
+///     pages += 1;
+/// }
+/// assert_eq!(pages, 3);
+/// ```
+pub struct Query<'conn, T> {
+    conn: &'conn Connection,
+    layer_name: String,
+    conditions: Vec<String>,
+    params: Vec<Value>,
+    // bounding boxes that the geometry has to intersect
+    bboxes: Vec<Extent>,
+    // columns to sort by, and whether each is descending
+    order_by: Vec<(String, bool)>,
+    after_fid: Option<i64>,
+    limit: Option<u64>,
+    offset: Option<u64>,
+    // an invalid condition, which is returned once the query is run
+    error: Option<Error>,
+    model: PhantomData<fn() -> T>,
+}
+
+impl<'conn, 'a, T: GPKGModel<'a>> Query<'conn, T> {
+    pub(crate) fn new(conn: &'conn Connection) -> Self {
+        Query {
+            conn,
+            layer_name: T::get_gpkg_layer_name().to_owned(),
+            conditions: Vec::new(),
+            params: Vec::new(),
+            bboxes: Vec::new(),
+            order_by: Vec::new(),
+            after_fid: None,
+            limit: None,
+            offset: None,
+            error: None,
+            model: PhantomData,
+        }
+    }
+
+    /// Read from the named layer instead of the one given by the derive
+    pub fn layer(mut self, layer_name: &str) -> Self {
+        self.layer_name = layer_name.to_owned();
+        self
+    }
+
+    /// Only read the records matching a SQL predicate, which is used as is
+    pub fn where_sql(mut self, predicate: &str) -> Self {
+        self.conditions.push(format!("({})", predicate));
+        self
+    }
+
+    /// Only read the records matching a SQL predicate with placeholders for the given parameters.
+    ///
+    /// Parameters are bound in the order they're added to the query, so `?` placeholders are
+    /// the simplest to use when a query has more than one condition.
+    pub fn where_sql_with(mut self, predicate: &str, params: &[&dyn ToSql]) -> Self {
+        for param in params {
+            match to_value(*param) {
+                Ok(v) => self.params.push(v),
+                Err(e) => self.fail(e.into()),
+            }
+        }
+        self.where_sql(predicate)
+    }
+
+    /// Only read the records matching a [Filter]
+    /// # Errors
+    /// Running the query returns [Error::UnknownColumn] if the filter uses a column that isn't in the model.
+    pub fn filter(mut self, filter: Filter) -> Self {
+        for name in &filter.columns {
+            self.check_column(name);
+        }
+        if let Some(e) = filter.error {
+            self.fail(e.into());
+        }
+        self.params.extend(filter.params);
+        self.conditions.push(format!("({})", filter.sql));
+        self
+    }
+
+    /// Only read records whose geometry has a bounding box that intersects the given one.
+    ///
+    /// When the layer has an RTree spatial index, the records are found through the index. Otherwise each
+    /// geometry is checked using the envelope in its header, or its coordinates if it doesn't have one.
+    /// Records with a null or empty geometry are never matched.
+    /// # Errors
+    /// Running the query returns [Error::NoGeometryColumn] if the model doesn't have a geometry column.
+    /// # Examples
+    /// ```
+    /// # use gpkg::{GeoPackage, GPKGModel};
+    /// # use gpkg::types::GPKGPoint;
+    /// # use tempfile::tempdir;
+    /// # let dir = tempdir().unwrap();
+    /// # let path = dir.path().join("bbox.gpkg");
+    /// # let mut gp = GeoPackage::create(path).unwrap();
+    /// #[derive(GPKGModel)]
+    /// #[layer_name = "stops"]
+    /// #[gpkg(spatial_index)]
+    /// struct Stop {
+    ///     name: String,
+    ///     #[geom_field("Point")]
+    ///     geom: GPKGPoint,
+    /// }
+    ///
+    /// gp.create_layer::<Stop>().unwrap();
+    /// gp.insert_many((0..10).map(|i| Stop {
+    ///     name: format!("Stop {}", i),
+    ///     geom: GPKGPoint(geo_types::point!(x: i as f64, y: i as f64)),
+    /// })).unwrap();
+    ///
+    /// let mut stmt = gp.query::<Stop>().bbox(2.5, 0.0, 5.0, 10.0).order_by("name").prepare().unwrap();
+    /// let names: Vec<String> = stmt.iter().unwrap().map(|s| s.unwrap().name).collect();
+    /// assert_eq!(names, vec!["Stop 3", "Stop 4", "Stop 5"]);
+    /// ```
+    pub fn bbox(mut self, min_x: f64, min_y: f64, max_x: f64, max_y: f64) -> Self {
+        self.bboxes.push(Extent {
+            min_x,
+            min_y,
+            max_x,
+            max_y,
+        });
+        self
+    }
+
+    /// Only read records whose geometry has a spatial relationship to another geometry.
+    ///
+    /// Candidates are found by their bounding box as with [Query::bbox], using the spatial index if there is one,
+    /// and the relationship is then checked exactly on the decoded geometries. Since this happens in SQL,
+    /// it can be combined with ordering, limits and aggregates like any other condition.
+    /// # Errors
+    /// Running the query returns [Error::NoGeometryColumn] if the model doesn't have a geometry column.
+    /// # Examples
+    /// ```
+    /// # use gpkg::{GeoPackage, GPKGModel, SpatialPredicate};
+    /// # use gpkg::types::GPKGPolygon;
+    /// # use geo_types::{point, polygon};
+    /// # use tempfile::tempdir;
+    /// # let dir = tempdir().unwrap();
+    /// # let path = dir.path().join("spatial.gpkg");
+    /// # let mut gp = GeoPackage::create(path).unwrap();
+    /// #[derive(GPKGModel)]
+    /// #[layer_name = "parcels"]
+    /// #[gpkg(spatial_index)]
+    /// struct Parcel {
+    ///     owner: String,
+    ///     #[geom_field("Polygon")]
+    ///     geom: GPKGPolygon,
+    /// }
+    ///
+    /// gp.create_layer::<Parcel>().unwrap();
+    /// gp.insert_many(vec![
+    ///     Parcel {
+    ///         owner: String::from("Ada"),
+    ///         geom: GPKGPolygon(polygon![(x: 0.0, y: 0.0), (x: 10.0, y: 0.0), (x: 0.0, y: 10.0)]),
+    ///     },
+    ///     Parcel {
+    ///         owner: String::from("Grace"),
+    ///         geom: GPKGPolygon(polygon![(x: 10.0, y: 0.0), (x: 10.0, y: 10.0), (x: 0.0, y: 10.0)]),
+    ///     },
+    /// ]).unwrap();
+    ///
+    /// // both parcels have the point in their bounding box, but only one contains it
+    /// let parcels = gp.query::<Parcel>().spatial(SpatialPredicate::Contains, point!(x: 8.0, y: 7.0)).all().unwrap();
+    /// assert_eq!(parcels.len(), 1);
+    /// assert_eq!(parcels[0].owner, "Grace");
+    /// ```
+    pub fn spatial<G: Into<Geometry<f64>>>(
+        mut self,
+        predicate: SpatialPredicate,
+        geometry: G,
+    ) -> Self {
+        let geometry = GPKGGeometry(geometry.into());
+        match predicate.search_extent(&geometry.0) {
+            Some(e) => self.bboxes.push(e),
+            // an empty geometry has no relationship to anything
+            None => return self.where_sql("0"),
+        }
+        let column = match T::get_geometry_column() {
+            Some(g) => sql::quote_identifier(&g.name),
+            None => {
+                self.fail(Error::NoGeometryColumn(self.layer_name.clone()));
+                return self;
+            }
+        };
+        let function = predicate.sql_function();
+        match predicate {
+            SpatialPredicate::PlanarDistanceWithin(distance) => self.where_sql_with(
+                &format!("{}({}, ?, ?)", function, column),
+                &[&geometry, &distance],
+            ),
+            _ => self.where_sql_with(&format!("{}({}, ?)", function, column), &[&geometry]),
+        }
+    }
+
+    /// Sort the records by a column in ascending order, after any columns that have already been added.
+    ///
+    /// The column can be any column of the model or the fid.
+    /// # Errors
+    /// Running the query returns [Error::UnknownColumn] if the column isn't in the model.
+    pub fn order_by(mut self, column: &str) -> Self {
+        self.check_column(column);
+        self.order_by.push((column.to_owned(), false));
+        self
+    }
+
+    /// Sort the records by a column in descending order, see [Query::order_by]
+    pub fn order_by_desc(mut self, column: &str) -> Self {
+        self.check_column(column);
+        self.order_by.push((column.to_owned(), true));
+        self
+    }
+
+    /// Read at most this many records
+    pub fn limit(mut self, limit: u64) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Skip this many records before reading any
+    pub fn offset(mut self, offset: u64) -> Self {
+        self.offset = Some(offset);
+        self
+    }
+
+    /// Only read records with a fid greater than the given one, in order of fid.
+    ///
+    /// Passing the last fid of one page gets the next page without the cost of skipping
+    /// records with an offset. The fids of the records are read with [Query::all_with_fids].
+    /// # Errors
+    /// Running the query returns [Error::InvalidQuery] if it's also ordered by another column.
+    pub fn after_fid(mut self, fid: i64) -> Self {
+        self.after_fid = Some(fid);
+        self
+    }
+
+    fn check_column(&mut self, name: &str) {
+        let known = name.eq_ignore_ascii_case("fid")
+            || T::get_columns()
+                .iter()
+                .any(|c| c.name.eq_ignore_ascii_case(name));
+        if !known {
+            self.fail(Error::UnknownColumn(name.to_owned()));
+        }
+    }
+
+    // keep the first problem with the query to return when it's run
+    fn fail(&mut self, error: Error) {
+        if self.error.is_none() {
+            self.error = Some(error);
+        }
+    }
+
+    // a condition for each bounding box, which use the spatial index of the layer if it has one
+    fn bbox_conditions(&self) -> Result<Vec<String>> {
+        if self.bboxes.is_empty() {
+            return Ok(Vec::new());
+        }
+        let geom = T::get_geometry_column()
+            .ok_or_else(|| Error::NoGeometryColumn(self.layer_name.clone()))?;
+        // the parameters are bound in the order max x, min x, max y, min y
+        let condition =
+            match extensions::has_spatial_index(self.conn, &self.layer_name, &geom.name)? {
+                true => format!(
+                    "fid IN (SELECT id FROM {} WHERE minx <= ? AND maxx >= ? AND miny <= ? AND maxy >= ?)",
+                    sql::quote_identifier(&sql::rtree_name(&self.layer_name, &geom.name))
+                ),
+                false => {
+                    let g = sql::quote_identifier(&geom.name);
+                    format!(
+                        "ST_MinX({g}) <= ? AND ST_MaxX({g}) >= ? AND ST_MinY({g}) <= ? AND ST_MaxY({g}) >= ?"
+                    )
+                }
+            };
+        Ok(vec![format!("({})", condition); self.bboxes.len()])
+    }
+
+    // the parameters to bind, with the ones for the bounding boxes last so that numbered placeholders
+    // in the other conditions still refer to their own parameters
+    fn bound_params(&self) -> Vec<Value> {
+        let mut params = self.params.clone();
+        for b in &self.bboxes {
+            params.extend([b.max_x, b.min_x, b.max_y, b.min_y].map(Value::Real));
+        }
+        params
+    }
+
+    // every condition of the query, in the order their parameters are bound
+    fn all_conditions(&self) -> Result<Vec<String>> {
+        let mut conditions = self.conditions.clone();
+        conditions.extend(self.bbox_conditions()?);
+        Ok(conditions)
+    }
+
+    fn sql(&self, with_fid: bool) -> Result<String> {
+        let mut sql = sql::select_columns_sql(&self.layer_name, &T::get_columns(), with_fid);
+        sql += &self.clauses()?;
+        sql += ";";
+        Ok(sql)
+    }
+
+    // the where, order by and limit clauses of the query
+    fn clauses(&self) -> Result<String> {
+        let mut sql = String::new();
+        let mut conditions = self.all_conditions()?;
+        let mut order_by: Vec<String> = self
+            .order_by
+            .iter()
+            .map(|(column, desc)| {
+                let direction = if *desc { "DESC" } else { "ASC" };
+                format!("{} {}", sql::quote_identifier(column), direction)
+            })
+            .collect();
+        if let Some(fid) = self.after_fid {
+            let by_fid = self
+                .order_by
+                .iter()
+                .all(|(column, desc)| column.eq_ignore_ascii_case("fid") && !desc);
+            if !by_fid {
+                return Err(Error::InvalidQuery(String::from(
+                    "records after a fid can only be ordered by fid",
+                )));
+            }
+            // the fid is an integer, so there's no need to bind it
+            conditions.push(format!("fid > {}", fid));
+            order_by = vec![String::from("fid ASC")];
+        }
+        if !conditions.is_empty() {
+            sql += " WHERE ";
+            sql += &conditions.join(" AND ");
+        }
+        if !order_by.is_empty() {
+            sql += " ORDER BY ";
+            sql += &order_by.join(", ");
+        }
+        match (self.limit, self.offset) {
+            (Some(limit), Some(offset)) => sql += &format!(" LIMIT {} OFFSET {}", limit, offset),
+            (Some(limit), None) => sql += &format!(" LIMIT {}", limit),
+            // sqlite needs a limit to use an offset, and a negative one means there isn't a limit
+            (None, Some(offset)) => sql += &format!(" LIMIT -1 OFFSET {}", offset),
+            (None, None) => {}
+        }
+        Ok(sql)
+    }
+
+    // run an aggregate expression over the records matched by the query
+    fn aggregate<V: FromSql>(self, expression: &str) -> Result<V> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+        let sql = if self.limit.is_none() && self.offset.is_none() {
+            // the order doesn't matter unless it affects which records are included
+            let mut conditions = self.all_conditions()?;
+            if let Some(fid) = self.after_fid {
+                conditions.push(format!("fid > {}", fid));
+            }
+            let mut sql = format!(
+                "SELECT {} FROM {}",
+                expression,
+                sql::quote_identifier(&self.layer_name)
+            );
+            if !conditions.is_empty() {
+                sql += " WHERE ";
+                sql += &conditions.join(" AND ");
+            }
+            sql
+        } else {
+            format!(
+                "SELECT {} FROM (SELECT * FROM {}{})",
+                expression,
+                sql::quote_identifier(&self.layer_name),
+                self.clauses()?
+            )
+        };
+        let value = self
+            .conn
+            .query_row(&sql, params_from_iter(self.bound_params()), |r| r.get(0))?;
+        Ok(value)
+    }
+
+    /// The number of records matched by the query, which is counted without reading any of them
+    pub fn count(self) -> Result<i64> {
+        self.aggregate("count(*)")
+    }
+
+    /// Whether the query matches any records
+    pub fn exists(self) -> Result<bool> {
+        Ok(self.limit(1).count()? > 0)
+    }
+
+    /// The smallest value of a column in the records matched by the query, which is None if there are none
+    /// # Errors
+    /// Returns [Error::UnknownColumn] if the column isn't in the model.
+    /// # Examples
+    /// ```
+    /// # use gpkg::{GeoPackage, GPKGModel};
+    /// # use tempfile::tempdir;
+    /// # let dir = tempdir().unwrap();
+    /// # let path = dir.path().join("aggregates.gpkg");
+    /// # let mut gp = GeoPackage::create(path).unwrap();
+    /// #[derive(GPKGModel)]
+    /// struct Item {
+    ///     length: f64,
+    ///     count: i64,
+    /// }
+    ///
+    /// gp.create_layer::<Item>().unwrap();
+    /// gp.insert_many((1..=10).map(|i| Item { length: i as f64, count: i })).unwrap();
+    ///
+    /// assert_eq!(gp.query::<Item>().min::<f64>("length").unwrap(), Some(1.0));
+    /// assert_eq!(gp.query::<Item>().max::<f64>("length").unwrap(), Some(10.0));
+    /// assert_eq!(gp.query::<Item>().where_sql("count > 5").sum::<i64>("count").unwrap(), Some(40));
+    /// assert_eq!(gp.query::<Item>().where_sql("count > 10").avg("length").unwrap(), None);
+    /// ```
+    pub fn min<V: FromSql>(self, column: &str) -> Result<Option<V>> {
+        self.column_aggregate("min", column)
+    }
+
+    /// The largest value of a column in the records matched by the query, see [Query::min]
+    pub fn max<V: FromSql>(self, column: &str) -> Result<Option<V>> {
+        self.column_aggregate("max", column)
+    }
+
+    /// The sum of a column in the records matched by the query, see [Query::min]
+    pub fn sum<V: FromSql>(self, column: &str) -> Result<Option<V>> {
+        self.column_aggregate("sum", column)
+    }
+
+    /// The average of a column in the records matched by the query, see [Query::min]
+    pub fn avg(self, column: &str) -> Result<Option<f64>> {
+        self.column_aggregate("avg", column)
+    }
+
+    fn column_aggregate<V: FromSql>(mut self, function: &str, column: &str) -> Result<Option<V>> {
+        self.check_column(column);
+        let expression = format!("{}({})", function, sql::quote_identifier(column));
+        self.aggregate(&expression)
+    }
+
+    fn prepare_with<R>(
+        self,
+        with_fid: bool,
+        read: fn(&Row) -> rusqlite::Result<R>,
+    ) -> Result<PreparedQuery<'conn, R>> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+        let stmt = self.conn.prepare(&self.sql(with_fid)?)?;
+        Ok(PreparedQuery {
+            stmt,
+            params: self.bound_params(),
+            read,
+        })
+    }
+
+    /// Prepare the statement for the query, which can then be iterated over without
+    /// reading every record into memory first.
+    /// # Examples
+    /// ```
+    /// # use gpkg::{GeoPackage, GPKGModel};
+    /// # use tempfile::tempdir;
+    /// # let dir = tempdir().unwrap();
+    /// # let path = dir.path().join("prepare.gpkg");
+    /// # let mut gp = GeoPackage::create(path).unwrap();
+    /// #[derive(GPKGModel)]
+    /// struct Item {
+    ///     length: f64,
+    /// }
+    ///
+    /// gp.create_layer::<Item>().unwrap();
+    /// gp.insert_many((0..100).map(|i| Item { length: i as f64 })).unwrap();
+    ///
+    /// let mut stmt = gp.query::<Item>().where_sql("length >= 50").prepare().unwrap();
+    /// let mut total = 0.0;
+    /// for item in stmt.iter().unwrap() {
+    ///     total += item.unwrap().length;
+    /// }
+    /// assert_eq!(total, 3725.0);
+    /// ```
+    pub fn prepare(self) -> Result<PreparedQuery<'conn, T>> {
+        self.prepare_with(false, T::from_row)
+    }
+
+    /// Prepare the statement for the query, with each record read along with its fid
+    pub fn prepare_with_fids(self) -> Result<PreparedQuery<'conn, (i64, T)>> {
+        self.prepare_with(true, read_with_fid::<T>)
+    }
+
+    /// Read every matching record into a Vec
+    pub fn all(self) -> Result<Vec<T>> {
+        self.prepare()?.iter()?.collect()
+    }
+
+    /// Read every matching record into a Vec along with its fid
+    pub fn all_with_fids(self) -> Result<Vec<(i64, T)>> {
+        self.prepare_with_fids()?.iter()?.collect()
+    }
+
+    /// Call a function with batches of up to `batch_size` records, so only one batch is in memory at a time.
+    ///
+    /// Reading stops at the first error, including one returned by the function.
+    pub fn for_each_batch<F>(self, batch_size: usize, mut f: F) -> Result<()>
+    where
+        F: FnMut(Vec<T>) -> Result<()>,
+    {
+        assert!(batch_size > 0, "The batch size must be at least 1");
+        let mut stmt = self.prepare()?;
+        let mut batch = Vec::with_capacity(batch_size);
+        for record in stmt.iter()? {
+            batch.push(record?);
+            if batch.len() == batch_size {
+                f(std::mem::replace(
+                    &mut batch,
+                    Vec::with_capacity(batch_size),
+                ))?;
+            }
+        }
+        if !batch.is_empty() {
+            f(batch)?;
+        }
+        Ok(())
+    }
+
+    /// Read the `k` matching records whose geometry is nearest to a point, along with their distances, nearest first.
+    ///
+    /// Distances are in meters for layers with a geographic spatial reference system, and in the units of the
+    /// spatial reference system otherwise, see [DistanceMetric]. Candidates are read from windows around the point
+    /// that grow until the nearest records are known, which uses the spatial index if the layer has one.
+    /// Records with a null or empty geometry are never returned.
+    /// # Errors
+    /// Returns [Error::NoGeometryColumn] if the model doesn't have a geometry column, and [Error::InvalidQuery]
+    /// if the query has a limit, offset or order, since the records are ordered by distance.
+    /// # Examples
+    /// ```
+    /// # use gpkg::{GeoPackage, GPKGModel};
+    /// # use gpkg::types::GPKGPoint;
+    /// # use geo_types::point;
+    /// # use tempfile::tempdir;
+    /// # let dir = tempdir().unwrap();
+    /// # let path = dir.path().join("nearest.gpkg");
+    /// # let mut gp = GeoPackage::create(path).unwrap();
+    /// #[derive(GPKGModel)]
+    /// #[layer_name = "hydrants"]
+    /// #[gpkg(spatial_index)]
+    /// struct Hydrant {
+    ///     in_service: bool,
+    ///     #[geom_field("Point")]
+    ///     geom: GPKGPoint,
+    /// }
+    ///
+    /// gp.create_layer::<Hydrant>().unwrap();
+    /// gp.insert_many((0..100).map(|i| Hydrant {
+    ///     in_service: i % 2 == 0,
+    ///     geom: GPKGPoint(point!(x: -105.0 + i as f64 * 0.001, y: 40.0)),
+    /// })).unwrap();
+    ///
+    /// let nearest = gp
+    ///     .query::<Hydrant>()
+    ///     .where_sql("in_service")
+    ///     .nearest(point!(x: -104.9505, y: 40.0), 2)
+    ///     .unwrap();
+    /// assert_eq!(nearest.len(), 2);
+    /// // the layer is in WGS 84, so the distances are in meters
+    /// assert!(nearest[0].1 > 40.0 && nearest[1].1 < 130.0);
+    /// ```
+    pub fn nearest(mut self, point: Point<f64>, k: usize) -> Result<Vec<(T, f64)>> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        if self.limit.is_some()
+            || self.offset.is_some()
+            || self.after_fid.is_some()
+            || !self.order_by.is_empty()
+        {
+            return Err(Error::InvalidQuery(String::from(
+                "nearest records are ordered by distance, so they can't be limited or ordered by a column",
+            )));
+        }
+        if T::get_geometry_column().is_none() {
+            return Err(Error::NoGeometryColumn(self.layer_name));
+        }
+        let metric = DistanceMetric::for_layer(self.conn, &self.layer_name)?;
+        let layer_extent = match extent::current_extent(self.conn, &self.layer_name)? {
+            Some(e) if k > 0 => e,
+            _ => return Ok(Vec::new()),
+        };
+        let count: i64 = self.conn.query_row(
+            &format!(
+                "SELECT count(*) FROM {}",
+                sql::quote_identifier(&self.layer_name)
+            ),
+            [],
+            |r| r.get(0),
+        )?;
+
+        // start with a window that reaches the layer and would hold about k records if they were spread evenly
+        let width =
+            (layer_extent.max_x - layer_extent.min_x).max(layer_extent.max_y - layer_extent.min_y);
+        let to_layer = (layer_extent.min_x - point.x())
+            .max(point.x() - layer_extent.max_x)
+            .max(layer_extent.min_y - point.y())
+            .max(point.y() - layer_extent.max_y);
+        let mut half_width = (width * (k as f64 / count.max(1) as f64).sqrt() / 2.0)
+            .max(to_layer)
+            .max(1e-9);
+        let other_bboxes = self.bboxes.len();
+        loop {
+            let window = metric.window(&point, half_width);
+            self.bboxes.truncate(other_bboxes);
+            self.bboxes.push(window);
+            let mut candidates = Vec::new();
+            let mut stmt = self.conn.prepare(&self.sql(false)?)?;
+            let mut rows = stmt.query(params_from_iter(self.bound_params()))?;
+            while let Some(row) = rows.next()? {
+                let record = T::from_row(row)?;
+                if let Some(geometry) = spatial::record_geometry(&record)? {
+                    let distance = metric.distance(&point, &geometry);
+                    // an empty geometry has no distance
+                    if !distance.is_nan() {
+                        candidates.push((record, distance));
+                    }
+                }
+            }
+            candidates.sort_by(|a, b| a.1.total_cmp(&b.1));
+            // anything outside of the window is further away than this
+            let known = metric.outside_window(&point, half_width);
+            let complete = candidates.len() >= k && candidates[k - 1].1 <= known;
+            let covers_layer = window.min_x <= layer_extent.min_x
+                && window.min_y <= layer_extent.min_y
+                && window.max_x >= layer_extent.max_x
+                && window.max_y >= layer_extent.max_y;
+            if complete || covers_layer {
+                candidates.truncate(k);
+                return Ok(candidates);
+            }
+            half_width *= 2.0;
+        }
+    }
+}
+
+fn read_with_fid<'a, T: GPKGModel<'a>>(row: &Row) -> rusqlite::Result<(i64, T)> {
+    Ok((row.get(0)?, T::from_row_offset(row, 1)?))
+}
+
+/// A prepared [Query], which can be iterated over any number of times
+pub struct PreparedQuery<'conn, T> {
+    stmt: Statement<'conn>,
+    params: Vec<Value>,
+    read: fn(&Row) -> rusqlite::Result<T>,
+}
+
+impl<'conn, T> PreparedQuery<'conn, T> {
+    /// Run the query, returning an iterator that reads each record as it's reached
+    pub fn iter(&mut self) -> Result<Records<'_, T>> {
+        Ok(Records {
+            rows: self.stmt.query(params_from_iter(self.params.iter()))?,
+            read: self.read,
+        })
+    }
+}
+
+/// An iterator over the records returned by a [PreparedQuery]
+pub struct Records<'stmt, T> {
+    rows: Rows<'stmt>,
+    read: fn(&Row) -> rusqlite::Result<T>,
+}
+
+impl<T> Iterator for Records<'_, T> {
+    type Item = Result<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.rows.next() {
+            Ok(Some(row)) => Some((self.read)(row).map_err(Error::from)),
+            Ok(None) => None,
+            Err(e) => Some(Err(e.into())),
+        }
+    }
+}
@@ -17,4 +17,37 @@ pub enum Error {
     CreateExistingError,
     #[error("GeoPackage failed validation check when opening")]
     ValidationError,
+    #[error("{0} does not match any variant of the enum")]
+    InvalidEnumValue(String),
+    #[error("Layer {0} does not exist in the GeoPackage")]
+    UnknownLayer(String),
+    #[error("Layer {0} already exists in the GeoPackage")]
+    LayerExists(String),
+    #[error("Layer {0} does not have a geometry column")]
+    NoGeometryColumn(String),
+    #[error("Column {0} does not exist in the layer")]
+    UnknownColumn(String),
+    #[error("Layer {layer} does not match the model: {}", .problems.join("; "))]
+    SchemaMismatch {
+        layer: String,
+        problems: Vec<String>,
+    },
+    #[error("There is no feature with fid {0} in the layer")]
+    UnknownFeature(i64),
+    #[error("Invalid query: {0}")]
+    InvalidQuery(String),
+    #[error("Invalid layer definition: {0}")]
+    InvalidLayer(String),
+    #[error("Spatial reference system {0} does not exist in the GeoPackage")]
+    UnknownSrs(i64),
+    #[error("Spatial reference system {0} is a different system in the other GeoPackage")]
+    SrsMismatch(i64),
+    #[error("EPSG:{0} is not in the catalog of spatial reference systems")]
+    UnknownEpsgCode(i64),
+    #[error("Spatial reference system {srs_id} is used by {}", .layers.join(", "))]
+    SrsInUse { srs_id: i64, layers: Vec<String> },
+    #[error("Spatial reference system {0} is required by the GeoPackage specification")]
+    RequiredSrs(i64),
+    #[error("Field {0} is a reference and can't be read from a row, use a Cow or an owned type to read it")]
+    BorrowedField(String),
 }
@@ -0,0 +1,434 @@
+use crate::extent::Extent;
+use crate::result::{Error, Result};
+use rusqlite::{params, Connection, OptionalExtension};
+
+/// The SQL storage type of a column, as described by the GeoPackage [data types](https://www.geopackage.org/spec130/#table_column_data_types)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnType {
+    Integer,
+    Real,
+    Text,
+    Blob,
+}
+
+impl ColumnType {
+    /// The type name used when declaring a column of this type
+    pub fn as_sql(&self) -> &'static str {
+        match self {
+            ColumnType::Integer => "INTEGER",
+            ColumnType::Real => "REAL",
+            ColumnType::Text => "TEXT",
+            ColumnType::Blob => "BLOB",
+        }
+    }
+
+    /// Map a declared column type from an existing table onto the type it will be stored as.
+    ///
+    /// Geometry type names such as POINT are stored as blobs.
+    pub(crate) fn from_declared(declared: &str) -> Option<ColumnType> {
+        let upper = declared.trim().to_uppercase();
+        // TEXT and BLOB can have a maximum length, i.e. TEXT(50)
+        let base = upper.split('(').next().unwrap_or_default().trim();
+        match base {
+            "BOOLEAN" | "TINYINT" | "SMALLINT" | "MEDIUMINT" | "INT" | "INTEGER" => {
+                Some(ColumnType::Integer)
+            }
+            "FLOAT" | "DOUBLE" | "REAL" => Some(ColumnType::Real),
+            "TEXT" | "DATE" | "DATETIME" => Some(ColumnType::Text),
+            "BLOB" => Some(ColumnType::Blob),
+            _ if is_geometry_type_name(base) => Some(ColumnType::Blob),
+            _ => None,
+        }
+    }
+}
+
+/// Describes whether Z or M values are allowed for a geometry column
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MZOption {
+    Prohibited = 0,
+    Mandatory = 1,
+    Optional = 2,
+}
+
+impl MZOption {
+    pub(crate) fn from_flag(flag: i64) -> Option<MZOption> {
+        match flag {
+            0 => Some(MZOption::Prohibited),
+            1 => Some(MZOption::Mandatory),
+            2 => Some(MZOption::Optional),
+            _ => None,
+        }
+    }
+}
+
+/// A column of a layer, as declared by a [GPKGModel](crate::GPKGModel)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColumnDefinition {
+    pub name: String,
+    pub column_type: ColumnType,
+    pub not_null: bool,
+    pub unique: bool,
+    /// A SQL literal or parenthesized expression used as the default value of the column
+    pub default: Option<String>,
+    /// A SQL expression that every value in the column must satisfy
+    pub check: Option<String>,
+}
+
+impl ColumnDefinition {
+    /// A column that allows nulls and has no constraints, see [LayerBuilder](crate::LayerBuilder)
+    pub fn new(name: &str, column_type: ColumnType) -> Self {
+        ColumnDefinition {
+            name: name.to_owned(),
+            column_type,
+            not_null: false,
+            unique: false,
+            default: None,
+            check: None,
+        }
+    }
+
+    /// Don't allow nulls in the column
+    pub fn not_null(mut self) -> Self {
+        self.not_null = true;
+        self
+    }
+
+    /// Don't allow the same value in more than one row
+    pub fn unique(mut self) -> Self {
+        self.unique = true;
+        self
+    }
+
+    /// Set the default value of the column, which is a SQL literal or parenthesized expression
+    pub fn with_default(mut self, default: &str) -> Self {
+        self.default = Some(default.to_owned());
+        self
+    }
+
+    /// Require every value in the column to satisfy a SQL expression
+    pub fn with_check(mut self, check: &str) -> Self {
+        self.check = Some(check.to_owned());
+        self
+    }
+}
+
+/// A secondary index on one or more columns of a layer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexDefinition {
+    pub columns: Vec<String>,
+    pub unique: bool,
+}
+
+impl IndexDefinition {
+    /// The name of the index when it is created for the given layer
+    pub fn name_for(&self, layer_name: &str) -> String {
+        format!("idx_{}_{}", layer_name, self.columns.join("_"))
+    }
+}
+
+/// The geometry column of a feature layer, as it appears in the
+/// [gpkg_geometry_columns](https://www.geopackage.org/spec130/#_geometry_columns) table
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GeometryColumnDefinition {
+    pub name: String,
+    pub geometry_type: String,
+    pub srs_id: i64,
+    pub z: MZOption,
+    pub m: MZOption,
+}
+
+/// The kind of data a layer holds, from the data_type column of gpkg_contents
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GPKGDataType {
+    Features,
+    Attributes,
+    Tiles,
+    /// A data type defined by an extension, such as 2d-gridded-coverage
+    Other(String),
+}
+
+impl GPKGDataType {
+    /// The name of the data type as it's stored in gpkg_contents
+    pub fn as_str(&self) -> &str {
+        match self {
+            GPKGDataType::Features => "features",
+            GPKGDataType::Attributes => "attributes",
+            GPKGDataType::Tiles => "tiles",
+            GPKGDataType::Other(name) => name,
+        }
+    }
+
+    pub(crate) fn from_name(name: &str) -> GPKGDataType {
+        match name {
+            "features" => GPKGDataType::Features,
+            "attributes" => GPKGDataType::Attributes,
+            "tiles" => GPKGDataType::Tiles,
+            _ => GPKGDataType::Other(name.to_owned()),
+        }
+    }
+}
+
+/// A layer as it's registered in the [gpkg_contents](https://www.geopackage.org/spec130/#_contents) table
+#[derive(Debug, Clone, PartialEq)]
+pub struct LayerDefinition {
+    pub name: String,
+    pub data_type: GPKGDataType,
+    pub identifier: Option<String>,
+    pub description: Option<String>,
+    /// The time of the last change to the layer as an ISO 8601 timestamp, e.g. 2024-01-31T12:00:00.000Z
+    pub last_change: String,
+    /// The bounding box of the layer, which is None if it hasn't been recorded
+    pub extent: Option<Extent>,
+    pub srs_id: Option<i64>,
+}
+
+/// A column of an existing table, as reported by SQLite
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TableColumn {
+    pub name: String,
+    /// The type the column was declared with, such as INTEGER, TEXT(50) or POINT
+    pub declared_type: String,
+    pub not_null: bool,
+    pub primary_key: bool,
+}
+
+impl TableColumn {
+    /// The type that values in the column are stored as, which is None for declared types outside of the specification
+    pub fn column_type(&self) -> Option<ColumnType> {
+        ColumnType::from_declared(&self.declared_type)
+    }
+}
+
+/// The layers in gpkg_contents ordered by name, or only the one with the given name
+pub(crate) fn get_layers(
+    conn: &Connection,
+    layer_name: Option<&str>,
+) -> Result<Vec<LayerDefinition>> {
+    let mut stmt = conn.prepare(
+        "SELECT table_name, data_type, identifier, description, last_change, min_x, min_y, max_x, max_y, srs_id
+        FROM gpkg_contents WHERE ?1 IS NULL OR table_name = ?1 ORDER BY table_name",
+    )?;
+    let rows = stmt.query_map(params![layer_name], |row| {
+        let extent = match (row.get(5)?, row.get(6)?, row.get(7)?, row.get(8)?) {
+            (Some(min_x), Some(min_y), Some(max_x), Some(max_y)) => Some(Extent {
+                min_x,
+                min_y,
+                max_x,
+                max_y,
+            }),
+            _ => None,
+        };
+        Ok(LayerDefinition {
+            name: row.get(0)?,
+            data_type: GPKGDataType::from_name(&row.get::<_, String>(1)?),
+            identifier: row.get(2)?,
+            description: row.get(3)?,
+            last_change: row.get(4)?,
+            extent,
+            srs_id: row.get(9)?,
+        })
+    })?;
+    let mut out_vec = Vec::new();
+    for r in rows {
+        out_vec.push(r?);
+    }
+    Ok(out_vec)
+}
+
+pub(crate) fn get_table_columns(conn: &Connection, table_name: &str) -> Result<Vec<TableColumn>> {
+    let mut stmt = conn.prepare("SELECT name, type, \"notnull\", pk FROM pragma_table_info(?1)")?;
+    let rows = stmt.query_map(params![table_name], |row| {
+        Ok(TableColumn {
+            name: row.get(0)?,
+            declared_type: row.get(1)?,
+            not_null: row.get(2)?,
+            primary_key: row.get::<_, i64>(3)? > 0,
+        })
+    })?;
+    let mut out_vec = Vec::new();
+    for r in rows {
+        out_vec.push(r?);
+    }
+    Ok(out_vec)
+}
+
+/// The name of the integer primary key of a table, which the specification requires a layer to have.
+/// The rowid is used for a table without one.
+pub(crate) fn primary_key_column(conn: &Connection, table_name: &str) -> Result<String> {
+    Ok(get_table_columns(conn, table_name)?
+        .into_iter()
+        .find(|c| c.primary_key)
+        .map(|c| c.name)
+        .unwrap_or_else(|| String::from("rowid")))
+}
+
+pub(crate) fn get_geometry_column(
+    conn: &Connection,
+    table_name: &str,
+) -> Result<Option<GeometryColumnDefinition>> {
+    let geom = conn
+        .query_row(
+            "SELECT column_name, geometry_type_name, srs_id, z, m FROM gpkg_geometry_columns WHERE table_name = ?1",
+            params![table_name],
+            |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, i64>(2)?,
+                    row.get::<_, i64>(3)?,
+                    row.get::<_, i64>(4)?,
+                ))
+            },
+        )
+        .optional()?;
+    Ok(match geom {
+        Some((name, geometry_type, srs_id, z, m)) => Some(GeometryColumnDefinition {
+            name,
+            geometry_type: geometry_type.to_uppercase(),
+            srs_id,
+            z: MZOption::from_flag(z).ok_or(Error::ValidationError)?,
+            m: MZOption::from_flag(m).ok_or(Error::ValidationError)?,
+        }),
+        None => None,
+    })
+}
+
+/// Check that the columns of an existing table can be read into a model with the given columns.
+///
+/// Columns are matched by name, any columns in the table that aren't in the model are ignored.
+pub(crate) fn check_compatibility(
+    conn: &Connection,
+    table_name: &str,
+    columns: &[ColumnDefinition],
+    geometry_column: Option<&GeometryColumnDefinition>,
+) -> Result<()> {
+    let table_columns = get_table_columns(conn, table_name)?;
+    if table_columns.is_empty() {
+        return Err(Error::SchemaMismatch {
+            layer: table_name.to_owned(),
+            problems: vec![String::from("the layer does not exist")],
+        });
+    }
+
+    let mut problems = Vec::new();
+    for column in columns {
+        let found = table_columns
+            .iter()
+            .find(|c| c.name.eq_ignore_ascii_case(&column.name));
+        let table_column = match found {
+            Some(c) => c,
+            None => {
+                problems.push(format!("column {} is missing", column.name));
+                continue;
+            }
+        };
+        // the geometry column is checked against gpkg_geometry_columns below
+        let is_geom = geometry_column.is_some_and(|g| g.name.eq_ignore_ascii_case(&column.name));
+        if !is_geom {
+            match ColumnType::from_declared(&table_column.declared_type) {
+                Some(t) if t == column.column_type => {}
+                _ => problems.push(format!(
+                    "column {} is declared as {} but the model expects {}",
+                    column.name,
+                    table_column.declared_type,
+                    column.column_type.as_sql()
+                )),
+            }
+        }
+        if column.not_null && !(table_column.not_null || table_column.primary_key) {
+            problems.push(format!(
+                "column {} allows nulls but the model field is not optional",
+                column.name
+            ));
+        }
+    }
+
+    if let Some(model_geom) = geometry_column {
+        match get_geometry_column(conn, table_name)? {
+            Some(layer_geom) => {
+                if !layer_geom.name.eq_ignore_ascii_case(&model_geom.name) {
+                    problems.push(format!(
+                        "the geometry column is {} but the model expects {}",
+                        layer_geom.name, model_geom.name
+                    ));
+                }
+                // tools like GDAL store the base type name and record Z and M only in the flags,
+                // so the suffixes are left to the flag checks below
+                let layer_type = layer_geom.geometry_type.to_uppercase();
+                let model_type = model_geom.geometry_type.to_uppercase();
+                let model_base = base_geometry_type(&model_type);
+                // a model reading any geometry can read every geometry type
+                if model_base != "GEOMETRY" && base_geometry_type(&layer_type) != model_base {
+                    problems.push(format!(
+                        "the geometry type is {} but the model expects {}",
+                        layer_geom.geometry_type, model_geom.geometry_type
+                    ));
+                }
+                if !mz_compatible(layer_geom.z, model_geom.z) {
+                    problems.push(format!(
+                        "the layer has z flag {:?} but the model expects {:?}",
+                        layer_geom.z, model_geom.z
+                    ));
+                }
+                if !mz_compatible(layer_geom.m, model_geom.m) {
+                    problems.push(format!(
+                        "the layer has m flag {:?} but the model expects {:?}",
+                        layer_geom.m, model_geom.m
+                    ));
+                }
+            }
+            None => problems.push(String::from("the layer has no geometry column")),
+        }
+    }
+
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::SchemaMismatch {
+            layer: table_name.to_owned(),
+            problems,
+        })
+    }
+}
+
+// whether geometries from a layer with the given flag can be read into a model with the other
+fn mz_compatible(layer: MZOption, model: MZOption) -> bool {
+    match model {
+        MZOption::Optional => true,
+        _ => layer == model,
+    }
+}
+
+/// The name of a geometry type without its Z, M or ZM suffix, e.g. POINT for POINTZ
+pub(crate) fn base_geometry_type(name: &str) -> &str {
+    ["ZM", "Z", "M"]
+        .iter()
+        .find_map(|suffix| name.strip_suffix(suffix))
+        .unwrap_or(name)
+}
+
+/// The Z and M flags implied by the suffix of a geometry type name, e.g. POINTZ or LINESTRINGZM
+pub(crate) fn geometry_type_flags(name: &str) -> (MZOption, MZOption) {
+    let flag = |present| match present {
+        true => MZOption::Mandatory,
+        false => MZOption::Prohibited,
+    };
+    match name {
+        _ if name.ends_with("ZM") => (flag(true), flag(true)),
+        _ => (flag(name.ends_with('Z')), flag(name.ends_with('M'))),
+    }
+}
+
+pub(crate) fn is_geometry_type_name(name: &str) -> bool {
+    matches!(
+        base_geometry_type(name),
+        "GEOMETRY"
+            | "POINT"
+            | "LINESTRING"
+            | "POLYGON"
+            | "MULTIPOINT"
+            | "MULTILINESTRING"
+            | "MULTIPOLYGON"
+            | "GEOMETRYCOLLECTION"
+    )
+}
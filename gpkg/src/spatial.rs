@@ -0,0 +1,174 @@
+use crate::extent::{self, Extent};
+use crate::result::{Error, Result};
+use crate::schema::get_geometry_column;
+use crate::types::GPKGGeometry;
+use crate::{GPKGModel, GeoPackageWKB};
+use geo::{
+    BoundingRect, Closest, CoordsIter, EuclideanDistance, HaversineClosestPoint, HaversineDistance,
+    Intersects, Relate,
+};
+use geo_types::{Geometry, Point};
+use rusqlite::types::ValueRef;
+use rusqlite::{params, Connection, OptionalExtension};
+
+// the radius used by geo for haversine distances, in meters
+const MEAN_EARTH_RADIUS: f64 = 6371008.8;
+
+/// A relationship between the geometry of a record and another geometry, used to find records with
+/// [Query::spatial](crate::Query::spatial)
+///
+/// Relationships are evaluated exactly on the decoded geometries, in the units of the layer's spatial reference system.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SpatialPredicate {
+    /// The geometries have at least one point in common
+    Intersects,
+    /// The geometry of the record contains the other geometry, e.g. a parcel containing a point
+    Contains,
+    /// The geometry of the record is within the other geometry
+    Within,
+    /// The geometries are no further apart than the given straight line distance, in the units of the layer's
+    /// spatial reference system, which are degrees for a geographic system like WGS 84.
+    ///
+    /// Unlike [Query::nearest](crate::Query::nearest), which uses the [DistanceMetric] of the layer,
+    /// this is never a great circle distance in meters.
+    PlanarDistanceWithin(f64),
+}
+
+impl SpatialPredicate {
+    /// Whether the geometry of a record has this relationship to the other geometry
+    pub fn evaluate(&self, record: &Geometry<f64>, other: &Geometry<f64>) -> bool {
+        match self {
+            SpatialPredicate::Intersects => record.intersects(other),
+            SpatialPredicate::Contains => record.relate(other).is_contains(),
+            SpatialPredicate::Within => record.relate(other).is_within(),
+            SpatialPredicate::PlanarDistanceWithin(distance) => {
+                record.euclidean_distance(other) <= *distance
+            }
+        }
+    }
+
+    // the name of the SQL function that evaluates the predicate
+    pub(crate) fn sql_function(&self) -> &'static str {
+        match self {
+            SpatialPredicate::Intersects => "ST_Intersects",
+            SpatialPredicate::Contains => "ST_Contains",
+            SpatialPredicate::Within => "ST_Within",
+            SpatialPredicate::PlanarDistanceWithin(_) => "ST_DWithin",
+        }
+    }
+
+    /// The box that the extent of a matching record has to intersect, which is None if nothing can match
+    pub(crate) fn search_extent(&self, other: &Geometry<f64>) -> Option<Extent> {
+        let rect = other.bounding_rect()?;
+        let margin = match self {
+            SpatialPredicate::PlanarDistanceWithin(distance) => distance.max(0.0),
+            _ => 0.0,
+        };
+        Some(Extent {
+            min_x: rect.min().x - margin,
+            min_y: rect.min().y - margin,
+            max_x: rect.max().x + margin,
+            max_y: rect.max().y + margin,
+        })
+    }
+}
+
+/// How the distance from a point to the geometries of a layer is measured by nearest neighbour queries,
+/// which depends on the spatial reference system of the layer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DistanceMetric {
+    /// Straight line distance in the units of a projected spatial reference system
+    Planar,
+    /// Great circle distance in meters between longitude and latitude coordinates in degrees
+    Haversine,
+}
+
+impl DistanceMetric {
+    /// The metric for the geometry column of a layer, which is haversine for geographic spatial reference systems
+    pub(crate) fn for_layer(conn: &Connection, layer_name: &str) -> Result<DistanceMetric> {
+        let geom = get_geometry_column(conn, layer_name)?
+            .ok_or_else(|| Error::NoGeometryColumn(layer_name.to_owned()))?;
+        let definition: Option<String> = conn
+            .query_row(
+                "SELECT definition FROM gpkg_spatial_ref_sys WHERE srs_id = ?1",
+                params![geom.srs_id],
+                |r| r.get(0),
+            )
+            .optional()?;
+        let definition = definition.unwrap_or_default().trim_start().to_uppercase();
+        // srs 0 is the undefined geographic srs from the specification
+        let geographic = geom.srs_id == 0
+            || ["GEOGCS", "GEOGCRS", "GEODCRS", "GEOGRAPHICCRS"]
+                .iter()
+                .any(|prefix| definition.starts_with(prefix));
+        Ok(match geographic {
+            true => DistanceMetric::Haversine,
+            false => DistanceMetric::Planar,
+        })
+    }
+
+    /// The distance from a point to a geometry
+    pub fn distance(&self, from: &Point<f64>, to: &Geometry<f64>) -> f64 {
+        match self {
+            DistanceMetric::Planar => from.euclidean_distance(to),
+            DistanceMetric::Haversine => match to.haversine_closest_point(from) {
+                Closest::Intersection(_) => 0.0,
+                Closest::SinglePoint(p) => from.haversine_distance(&p),
+                // fall back to the nearest vertex
+                Closest::Indeterminate => to
+                    .coords_iter()
+                    .map(|c| from.haversine_distance(&Point::from(c)))
+                    .fold(f64::INFINITY, f64::min),
+            },
+        }
+    }
+
+    /// The square window with sides `2 * half_width` long around a point, which is where records are looked for
+    /// before it's known that nothing outside of it is closer.
+    ///
+    /// A geographic window that crosses the antimeridian covers every longitude instead, since the bounding boxes
+    /// of geometries don't wrap around, but the distances do.
+    pub(crate) fn window(&self, center: &Point<f64>, half_width: f64) -> Extent {
+        let (mut min_x, mut max_x) = (center.x() - half_width, center.x() + half_width);
+        if *self == DistanceMetric::Haversine && (min_x < -180.0 || max_x > 180.0) {
+            min_x = min_x.min(-180.0);
+            max_x = max_x.max(180.0);
+        }
+        Extent {
+            min_x,
+            min_y: center.y() - half_width,
+            max_x,
+            max_y: center.y() + half_width,
+        }
+    }
+
+    /// A lower bound on the distance from the center of a square window, with sides `2 * half_width` long,
+    /// to anything outside of it
+    pub(crate) fn outside_window(&self, center: &Point<f64>, half_width: f64) -> f64 {
+        match self {
+            DistanceMetric::Planar => half_width,
+            DistanceMetric::Haversine => {
+                let r = half_width.to_radians().min(std::f64::consts::PI);
+                // anything outside has either a latitude at least r away, or a longitude at least r away
+                // at a latitude no further from the equator than the window reaches
+                let max_latitude = (center.y().abs() + half_width).min(90.0).to_radians();
+                let by_longitude = 2.0 * (max_latitude.cos() * (r / 2.0).sin()).asin();
+                MEAN_EARTH_RADIUS * r.min(by_longitude)
+            }
+        }
+    }
+}
+
+/// The decoded geometry of a record, which is None if the model has no geometry or the geometry is null
+pub(crate) fn record_geometry<'a, T: GPKGModel<'a>>(record: &T) -> Result<Option<Geometry<f64>>> {
+    extent::with_geometry_blob(record, |b| decode_geometry(ValueRef::Blob(b)))
+}
+
+/// Decode a GeoPackage geometry blob, which is None for a null value
+pub(crate) fn decode_geometry(value: ValueRef) -> Result<Option<Geometry<f64>>> {
+    match value {
+        ValueRef::Null => Ok(None),
+        ValueRef::Blob(b) => Ok(Some(GPKGGeometry::from_wkb(&mut b.to_vec())?.0)),
+        _ => Err(Error::GeomDecodeError),
+    }
+}
@@ -1,3 +1,306 @@
+use crate::extensions::{RTREE_EXTENSION_DEFINITION, RTREE_EXTENSION_NAME};
+use crate::schema::{ColumnDefinition, GeometryColumnDefinition, IndexDefinition};
+
+/// Quote an identifier such as a table or column name for use in a SQL statement
+pub(crate) fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// Quote a string literal for use in a SQL statement
+pub(crate) fn quote_literal(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+/// Build the statements that create a layer's table and register it in the GeoPackage metadata tables
+pub(crate) fn create_layer_sql(
+    layer_name: &str,
+    columns: &[ColumnDefinition],
+    geometry_column: Option<&GeometryColumnDefinition>,
+    indexes: &[IndexDefinition],
+    identifier: &str,
+    description: &str,
+    spatial_index: bool,
+) -> String {
+    let mut column_defs = vec![String::from("fid INTEGER PRIMARY KEY")];
+    for column in columns {
+        let mut column_def = format!(
+            "{} {}",
+            quote_identifier(&column.name),
+            column.column_type.as_sql()
+        );
+        if column.not_null {
+            column_def += " NOT NULL";
+        }
+        if column.unique {
+            column_def += " UNIQUE";
+        }
+        if let Some(default) = &column.default {
+            column_def += &format!(" DEFAULT {}", default);
+        }
+        if let Some(check) = &column.check {
+            column_def += &format!(" CHECK ({})", check);
+        }
+        column_defs.push(column_def);
+    }
+    let mut sql = format!(
+        "BEGIN;\nCREATE TABLE {} (\n{}\n);\n",
+        quote_identifier(layer_name),
+        column_defs.join(",\n")
+    );
+    for index in indexes {
+        sql += &create_index_sql(layer_name, index);
+    }
+    let (data_type, srs_id) = match geometry_column {
+        Some(geom) => {
+            sql += &format!(
+                "INSERT INTO gpkg_geometry_columns VALUES ({}, {}, {}, {}, {}, {});\n",
+                quote_literal(layer_name),
+                quote_literal(&geom.name),
+                quote_literal(&geom.geometry_type.to_uppercase()),
+                geom.srs_id,
+                geom.z as i32,
+                geom.m as i32
+            );
+            ("features", geom.srs_id.to_string())
+        }
+        None => ("attributes", String::from("NULL")),
+    };
+    sql += &format!(
+        "INSERT INTO gpkg_contents (table_name, data_type, identifier, description, srs_id) VALUES ({}, '{}', {}, {}, {});\n",
+        quote_literal(layer_name),
+        data_type,
+        quote_literal(identifier),
+        quote_literal(description),
+        srs_id
+    );
+    if let (true, Some(geom)) = (spatial_index, geometry_column) {
+        // the layer is created with fid as its primary key
+        sql += &spatial_index_sql(layer_name, &geom.name, "fid");
+    }
+    sql += "COMMIT;";
+    sql
+}
+
+/// Build the statement that creates an index on a layer, named by [IndexDefinition::name_for]
+pub(crate) fn create_index_sql(layer_name: &str, index: &IndexDefinition) -> String {
+    let index_columns: Vec<String> = index.columns.iter().map(|c| quote_identifier(c)).collect();
+    format!(
+        "CREATE {}INDEX {} ON {} ({});\n",
+        if index.unique { "UNIQUE " } else { "" },
+        quote_identifier(&index.name_for(layer_name)),
+        quote_identifier(layer_name),
+        index_columns.join(", ")
+    )
+}
+
+/// The name of the RTree virtual table that indexes a geometry column
+pub(crate) fn rtree_name(layer_name: &str, column_name: &str) -> String {
+    format!("rtree_{}_{}", layer_name, column_name)
+}
+
+/// Build the statement that adds a row to gpkg_extensions, see [register_extension](crate::extensions::register_extension)
+pub(crate) fn register_extension_sql(
+    table_name: Option<&str>,
+    column_name: Option<&str>,
+    extension_name: &str,
+    definition: &str,
+    scope: &str,
+) -> String {
+    let literal = |value: Option<&str>| value.map_or_else(|| String::from("NULL"), quote_literal);
+    let (table_name, column_name) = (literal(table_name), literal(column_name));
+    let extension_name = quote_literal(extension_name);
+    format!(
+        "INSERT INTO gpkg_extensions (table_name, column_name, extension_name, definition, scope)
+        SELECT {table_name}, {column_name}, {extension_name}, {}, {}
+        WHERE NOT EXISTS (
+            SELECT 1 FROM gpkg_extensions
+            WHERE table_name IS {table_name} AND column_name IS {column_name} AND extension_name = {extension_name}
+        );\n",
+        quote_literal(definition),
+        quote_literal(scope)
+    )
+}
+
+/// Build the statements that create an RTree index on a geometry column, fill it from the existing rows,
+/// add the triggers from the specification that keep it up to date and register the extension.
+///
+/// The RTree ids are the values of the integer primary key of the layer, which is usually but not always fid.
+pub(crate) fn spatial_index_sql(layer_name: &str, column_name: &str, primary_key: &str) -> String {
+    let rtree = rtree_name(layer_name, column_name);
+    let r = quote_identifier(&rtree);
+    let t = quote_identifier(layer_name);
+    let c = quote_identifier(column_name);
+    let id = quote_identifier(primary_key);
+    let trigger = |suffix: &str| quote_identifier(&format!("{}_{}", rtree, suffix));
+    let not_empty = |row: &str| format!("{row}.{c} NOT NULL AND NOT ST_IsEmpty({row}.{c})");
+    let is_empty = |row: &str| format!("{row}.{c} IS NULL OR ST_IsEmpty({row}.{c})");
+    let insert_new = format!(
+        "INSERT OR REPLACE INTO {r} VALUES (NEW.{id}, ST_MinX(NEW.{c}), ST_MaxX(NEW.{c}), ST_MinY(NEW.{c}), ST_MaxY(NEW.{c}));"
+    );
+    let mut sql = format!("CREATE VIRTUAL TABLE {r} USING rtree(id, minx, maxx, miny, maxy);\n");
+    sql += &format!(
+        "INSERT OR REPLACE INTO {r} SELECT {id}, ST_MinX({c}), ST_MaxX({c}), ST_MinY({c}), ST_MaxY({c}) FROM {t} WHERE {c} NOT NULL AND NOT ST_IsEmpty({c});\n"
+    );
+    sql += &format!(
+        "CREATE TRIGGER {} AFTER INSERT ON {t} WHEN ({}) BEGIN {insert_new} END;\n",
+        trigger("insert"),
+        not_empty("NEW")
+    );
+    sql += &format!(
+        "CREATE TRIGGER {} AFTER UPDATE OF {c} ON {t} WHEN OLD.{id} = NEW.{id} AND ({}) BEGIN {insert_new} END;\n",
+        trigger("update1"),
+        not_empty("NEW")
+    );
+    sql += &format!(
+        "CREATE TRIGGER {} AFTER UPDATE OF {c} ON {t} WHEN OLD.{id} = NEW.{id} AND ({}) BEGIN DELETE FROM {r} WHERE id = OLD.{id}; END;\n",
+        trigger("update2"),
+        is_empty("NEW")
+    );
+    sql += &format!(
+        "CREATE TRIGGER {} AFTER UPDATE ON {t} WHEN OLD.{id} != NEW.{id} AND ({}) BEGIN DELETE FROM {r} WHERE id = OLD.{id}; {insert_new} END;\n",
+        trigger("update3"),
+        not_empty("NEW")
+    );
+    sql += &format!(
+        "CREATE TRIGGER {} AFTER UPDATE ON {t} WHEN OLD.{id} != NEW.{id} AND ({}) BEGIN DELETE FROM {r} WHERE id IN (OLD.{id}, NEW.{id}); END;\n",
+        trigger("update4"),
+        is_empty("NEW")
+    );
+    sql += &format!(
+        "CREATE TRIGGER {} AFTER DELETE ON {t} WHEN OLD.{c} NOT NULL BEGIN DELETE FROM {r} WHERE id = OLD.{id}; END;\n",
+        trigger("delete")
+    );
+    sql += &register_extension_sql(
+        Some(layer_name),
+        Some(column_name),
+        RTREE_EXTENSION_NAME,
+        RTREE_EXTENSION_DEFINITION,
+        "write-only",
+    );
+    sql
+}
+
+/// Build the statements that remove the RTree index on a geometry column if there is one, along with its triggers,
+/// including the ones added by later versions of the specification, and its row in gpkg_extensions
+pub(crate) fn drop_spatial_index_sql(layer_name: &str, column_name: &str) -> String {
+    let rtree = rtree_name(layer_name, column_name);
+    let mut sql = String::new();
+    for suffix in [
+        "insert", "update1", "update2", "update3", "update4", "update5", "update6", "update7",
+        "delete",
+    ] {
+        sql += &format!(
+            "DROP TRIGGER IF EXISTS {};\n",
+            quote_identifier(&format!("{}_{}", rtree, suffix))
+        );
+    }
+    sql += &format!("DROP TABLE IF EXISTS {};\n", quote_identifier(&rtree));
+    sql += &format!(
+        "DELETE FROM gpkg_extensions WHERE table_name = {} AND column_name = {} AND extension_name = {};\n",
+        quote_literal(layer_name),
+        quote_literal(column_name),
+        quote_literal(RTREE_EXTENSION_NAME)
+    );
+    sql
+}
+
+/// Build a statement inserting a row with a parameter for each column,
+/// preceded by a parameter for the fid if one is given
+pub(crate) fn insert_sql(layer_name: &str, columns: &[ColumnDefinition], with_fid: bool) -> String {
+    let mut names: Vec<String> = columns.iter().map(|c| quote_identifier(&c.name)).collect();
+    if with_fid {
+        names.insert(0, String::from("fid"));
+    }
+    let params = vec!["?"; names.len()];
+    format!(
+        "INSERT INTO {} ({}) VALUES ({})",
+        quote_identifier(layer_name),
+        names.join(", "),
+        params.join(", ")
+    )
+}
+
+/// Build a statement updating every column of the row with a given fid, which is the last parameter
+pub(crate) fn update_sql(layer_name: &str, columns: &[ColumnDefinition]) -> String {
+    let assignments: Vec<String> = columns
+        .iter()
+        .map(|c| format!("{} = ?", quote_identifier(&c.name)))
+        .collect();
+    format!(
+        "UPDATE {} SET {} WHERE fid = ?",
+        quote_identifier(layer_name),
+        assignments.join(", ")
+    )
+}
+
+/// Build a statement inserting a row with the fid as the first parameter, or updating the row if the fid is taken
+pub(crate) fn upsert_sql(layer_name: &str, columns: &[ColumnDefinition]) -> String {
+    let names: Vec<String> = columns.iter().map(|c| quote_identifier(&c.name)).collect();
+    let params = vec!["?"; columns.len() + 1];
+    let assignments: Vec<String> = names
+        .iter()
+        .map(|n| format!("{} = excluded.{}", n, n))
+        .collect();
+    let mut sql = format!(
+        "INSERT INTO {} (fid, {}) VALUES ({}) ON CONFLICT(fid) DO ",
+        quote_identifier(layer_name),
+        names.join(", "),
+        params.join(", ")
+    );
+    if assignments.is_empty() {
+        sql += "NOTHING";
+    } else {
+        sql += "UPDATE SET ";
+        sql += &assignments.join(", ");
+    }
+    sql
+}
+
+/// Build a statement deleting the row with the given fid
+pub(crate) fn delete_sql(layer_name: &str) -> String {
+    format!("DELETE FROM {} WHERE fid = ?", quote_identifier(layer_name))
+}
+
+/// Build a query selecting each column in order, optionally filtered by a predicate
+pub(crate) fn select_sql(
+    layer_name: &str,
+    columns: &[ColumnDefinition],
+    predicate: Option<&str>,
+) -> String {
+    let mut sql = select_columns_sql(layer_name, columns, false);
+    if let Some(p) = predicate {
+        sql += " WHERE ";
+        sql += p;
+    }
+    sql += ";";
+    sql
+}
+
+/// Build the start of a query selecting each column in order, optionally preceded by the fid,
+/// which can then be followed by any other clauses
+pub(crate) fn select_columns_sql(
+    layer_name: &str,
+    columns: &[ColumnDefinition],
+    with_fid: bool,
+) -> String {
+    let mut names: Vec<String> = columns.iter().map(|c| quote_identifier(&c.name)).collect();
+    if with_fid {
+        names.insert(0, String::from("fid"));
+    }
+    format!(
+        "SELECT {} FROM {}",
+        names.join(", "),
+        quote_identifier(layer_name)
+    )
+}
+
+/// Set the last change time of a layer in gpkg_contents to the current time
+pub(crate) const UPDATE_LAST_CHANGE: &str = "UPDATE gpkg_contents SET last_change = strftime('%Y-%m-%dT%H:%M:%fZ', 'now') WHERE table_name = ?1";
+
+pub(crate) const BEGIN_WRITE: &str = "SAVEPOINT gpkg_write";
+pub(crate) const COMMIT_WRITE: &str = "RELEASE gpkg_write";
+pub(crate) const ROLLBACK_WRITE: &str = "ROLLBACK TO gpkg_write; RELEASE gpkg_write";
+
 pub mod table_definitions {
     pub const CREATE_EXTENSTIONS_TABLE: &str = "CREATE TABLE gpkg_extensions (
             table_name TEXT,
@@ -67,4 +370,30 @@ pub mod table_definitions {
             CONSTRAINT fk_gtms_table_name FOREIGN KEY (table_name) REFERENCES gpkg_contents(table_name),
             CONSTRAINT fk_gtms_srs FOREIGN KEY (srs_id) REFERENCES gpkg_spatial_ref_sys (srs_id)
         );";
+
+    // tables for the schema extension https://www.geopackage.org/spec130/#extension_schema
+    pub const CREATE_DATA_COLUMNS_TABLE: &str = "CREATE TABLE IF NOT EXISTS gpkg_data_columns (
+            table_name TEXT NOT NULL,
+            column_name TEXT NOT NULL,
+            name TEXT,
+            title TEXT,
+            description TEXT,
+            mime_type TEXT,
+            constraint_name TEXT,
+            CONSTRAINT pk_gdc PRIMARY KEY (table_name, column_name),
+            CONSTRAINT gdc_tn UNIQUE (table_name, name)
+        );";
+
+    pub const CREATE_DATA_COLUMN_CONSTRAINTS_TABLE: &str =
+        "CREATE TABLE IF NOT EXISTS gpkg_data_column_constraints (
+            constraint_name TEXT NOT NULL,
+            constraint_type TEXT NOT NULL,
+            value TEXT,
+            min NUMERIC,
+            min_is_inclusive BOOLEAN,
+            max NUMERIC,
+            max_is_inclusive BOOLEAN,
+            description TEXT,
+            CONSTRAINT gdcc_ntv UNIQUE (constraint_name, constraint_type, value)
+        );";
 }
@@ -1,37 +1,335 @@
+use crate::epsg;
+use crate::extent::with_geometry_blob;
+use crate::gpkg_wkb::set_blob_srs_id;
+use crate::layer::object_type;
+use crate::result::{Error, Result};
+use crate::schema::get_geometry_column;
+use crate::sql::{self, quote_identifier};
+use crate::GPKGModel;
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use std::borrow::Cow;
+
 /// Represents a spatial reference system as it appears in the GeoPackage [specification](https://www.geopackage.org/spec130/#gpkg_spatial_ref_sys_cols)
-pub struct SpatialRefSys<'a> {
-    pub name: &'a str,
+/// # Examples
+/// ```
+/// # use gpkg::SpatialRefSys;
+/// let local = SpatialRefSys::new(100000, "Site grid")
+///     .with_organization("ACME", 7)
+///     .with_description("local engineering grid");
+/// assert_eq!(local.definition, "undefined");
+///
+/// let utm = SpatialRefSys::from_epsg(32633).unwrap();
+/// assert_eq!(utm.name, "WGS 84 / UTM zone 33N");
+/// assert!(utm.definition.contains("PARAMETER[\"central_meridian\",15]"));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpatialRefSys {
+    pub name: String,
     pub id: i64,
-    pub organization: &'a str,
+    pub organization: String,
     pub organization_coordsys_id: i64,
-    pub definition: &'a str,
-    pub description: &'a str,
+    pub definition: String,
+    pub description: String,
+}
+
+impl SpatialRefSys {
+    /// A system with the given srs_id and name, which is undefined until it's given a definition.
+    ///
+    /// The organization is NONE, with the srs_id as its code, until it's set with [SpatialRefSys::with_organization].
+    pub fn new(id: i64, name: impl Into<String>) -> Self {
+        SpatialRefSys {
+            name: name.into(),
+            id,
+            organization: String::from("NONE"),
+            organization_coordsys_id: id,
+            definition: String::from("undefined"),
+            description: String::new(),
+        }
+    }
+
+    /// Set the organization that defines the system and its code for it, e.g. EPSG and 4326
+    pub fn with_organization(mut self, organization: impl Into<String>, coordsys_id: i64) -> Self {
+        self.organization = organization.into();
+        self.organization_coordsys_id = coordsys_id;
+        self
+    }
+
+    /// Set the WKT definition of the system
+    pub fn with_definition(mut self, definition: impl Into<String>) -> Self {
+        self.definition = definition.into();
+        self
+    }
+
+    pub fn with_description(mut self, description: impl Into<String>) -> Self {
+        self.description = description.into();
+        self
+    }
+
+    /// The definition of an EPSG system from the catalog bundled with the crate, using the EPSG code as the srs_id.
+    ///
+    /// The catalog has the common geographic systems, Web Mercator, the UTM zones on WGS 84, ETRS89 and NAD83,
+    /// LAEA Europe and the national grids of Great Britain, France, the Netherlands and New Zealand,
+    /// see [SpatialRefSys::epsg_codes]. Returns None for any other code.
+    pub fn from_epsg(code: i64) -> Option<Self> {
+        epsg::lookup(code)
+    }
+
+    /// The EPSG codes in the bundled catalog, in ascending order
+    pub fn epsg_codes() -> Vec<i64> {
+        epsg::codes()
+    }
+
+    /// Read a system from a row of gpkg_spatial_ref_sys, using the names of the columns
+    /// # Examples
+    /// ```
+    /// # use gpkg::{GeoPackage, SpatialRefSys};
+    /// # use tempfile::tempdir;
+    /// # let dir = tempdir().unwrap();
+    /// # let path = dir.path().join("srs_from_row.gpkg");
+    /// let gp = GeoPackage::create(path).unwrap();
+    /// let wgs84 = gp
+    ///     .conn
+    ///     .query_row(
+    ///         "SELECT * FROM gpkg_spatial_ref_sys WHERE srs_id = 4326",
+    ///         [],
+    ///         SpatialRefSys::from_row,
+    ///     )
+    ///     .unwrap();
+    /// assert_eq!(wgs84.organization, "EPSG");
+    /// ```
+    pub fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        Ok(SpatialRefSys {
+            name: row.get("srs_name")?,
+            id: row.get("srs_id")?,
+            organization: row.get("organization")?,
+            organization_coordsys_id: row.get("organization_coordsys_id")?,
+            definition: row.get("definition")?,
+            // the description is optional in files written by other tools
+            description: row
+                .get::<_, Option<String>>("description")?
+                .unwrap_or_default(),
+        })
+    }
+}
+
+/// A spatial reference system to add to a GeoPackage with [GeoPackage::new_srs](crate::GeoPackage::new_srs),
+/// which is either a [SpatialRefSys] or the EPSG code of one in the bundled catalog
+pub trait SrsSource {
+    /// The definition of the system
+    /// # Errors
+    /// Returns [Error::UnknownEpsgCode] for an EPSG code that isn't in the catalog.
+    fn to_srs(&self) -> Result<Cow<'_, SpatialRefSys>>;
+}
+
+impl SrsSource for SpatialRefSys {
+    fn to_srs(&self) -> Result<Cow<'_, SpatialRefSys>> {
+        Ok(Cow::Borrowed(self))
+    }
+}
+
+impl<T: SrsSource + ?Sized> SrsSource for &T {
+    fn to_srs(&self) -> Result<Cow<'_, SpatialRefSys>> {
+        (**self).to_srs()
+    }
+}
+
+impl SrsSource for i64 {
+    fn to_srs(&self) -> Result<Cow<'_, SpatialRefSys>> {
+        SpatialRefSys::from_epsg(*self)
+            .map(Cow::Owned)
+            .ok_or(Error::UnknownEpsgCode(*self))
+    }
 }
 
 pub(crate) mod defaults {
     use super::SpatialRefSys;
-    pub const WGS84: SpatialRefSys = SpatialRefSys {
-        name: "WGS 84 geodetic",
-        id: 4326,
-        organization: "EPSG",
-        organization_coordsys_id: 4326,
-        definition: "GEOGCS[\"WGS 84\",DATUM[\"WGS_1984\",SPHEROID[\"WGS 84\",6378137,298.257223563,AUTHORITY[\"EPSG\",\"7030\"]],AUTHORITY[\"EPSG\",\"6326\"]],PRIMEM[\"Greenwich\",0,AUTHORITY[\"EPSG\",\"8901\"]],UNIT[\"degree\",0.0174532925199433,AUTHORITY[\"EPSG\",\"9122\"]],AUTHORITY[\"EPSG\",\"4326\"]]",
-        description: "longitude/latitude coordinates in decimal degrees on the WGS 84 spheroid",
-    };
-    pub const GEOGRAPHIC: SpatialRefSys = SpatialRefSys {
-        name: "undefined geographic SRS",
-        id: 0,
-        organization: "NONE",
-        organization_coordsys_id: 0,
-        definition: "undefined",
-        description: "undefined geographic coordinate reference system",
+
+    pub(crate) fn wgs84() -> SpatialRefSys {
+        SpatialRefSys {
+            name: String::from("WGS 84 geodetic"),
+            ..super::epsg::lookup(4326).expect("the catalog has WGS 84")
+        }
+    }
+
+    pub(crate) fn undefined_geographic() -> SpatialRefSys {
+        SpatialRefSys::new(0, "undefined geographic SRS")
+            .with_description("undefined geographic coordinate reference system")
+    }
+
+    pub(crate) fn undefined_cartesian() -> SpatialRefSys {
+        SpatialRefSys::new(-1, "undefined cartesian SRS")
+            .with_description("undefined cartesian coordinate reference system")
+    }
+
+    /// The systems every GeoPackage has to contain
+    pub(crate) const REQUIRED: [i64; 3] = [4326, 0, -1];
+}
+
+const SELECT_SRS: &str = "SELECT srs_name, srs_id, organization, organization_coordsys_id, definition, description FROM gpkg_spatial_ref_sys";
+
+pub(crate) fn list_srs(conn: &Connection) -> Result<Vec<SpatialRefSys>> {
+    let mut stmt = conn.prepare(&format!("{} ORDER BY srs_id", SELECT_SRS))?;
+    let srs = stmt
+        .query_map([], SpatialRefSys::from_row)?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(srs)
+}
+
+pub(crate) fn get_srs(conn: &Connection, srs_id: i64) -> Result<Option<SpatialRefSys>> {
+    Ok(conn
+        .query_row(
+            &format!("{} WHERE srs_id = ?1", SELECT_SRS),
+            params![srs_id],
+            SpatialRefSys::from_row,
+        )
+        .optional()?)
+}
+
+/// Check that a spatial reference system exists, returning [Error::UnknownSrs] if it doesn't
+pub(crate) fn check_srs_exists(conn: &Connection, srs_id: i64) -> Result<()> {
+    let exists: bool = conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM gpkg_spatial_ref_sys WHERE srs_id = ?1)",
+        params![srs_id],
+        |r| r.get(0),
+    )?;
+    match exists {
+        true => Ok(()),
+        false => Err(Error::UnknownSrs(srs_id)),
+    }
+}
+
+pub(crate) fn insert_srs(conn: &Connection, srs: &SpatialRefSys) -> Result<()> {
+    conn.execute(
+        "INSERT INTO gpkg_spatial_ref_sys (srs_name, srs_id, organization, organization_coordsys_id, definition, description)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![
+            srs.name,
+            srs.id,
+            srs.organization,
+            srs.organization_coordsys_id,
+            srs.definition,
+            srs.description,
+        ],
+    )?;
+    Ok(())
+}
+
+pub(crate) fn update_srs(conn: &Connection, srs: &SpatialRefSys) -> Result<()> {
+    let updated = conn.execute(
+        "UPDATE gpkg_spatial_ref_sys SET srs_name = ?1, organization = ?3, organization_coordsys_id = ?4, definition = ?5, description = ?6
+        WHERE srs_id = ?2",
+        params![
+            srs.name,
+            srs.id,
+            srs.organization,
+            srs.organization_coordsys_id,
+            srs.definition,
+            srs.description,
+        ],
+    )?;
+    match updated {
+        0 => Err(Error::UnknownSrs(srs.id)),
+        _ => Ok(()),
+    }
+}
+
+/// The layers and tile matrix sets that use a spatial reference system
+pub(crate) fn srs_users(conn: &Connection, srs_id: i64) -> Result<Vec<String>> {
+    let mut sql = String::from(
+        "SELECT table_name FROM gpkg_contents WHERE srs_id = ?1
+        UNION SELECT table_name FROM gpkg_geometry_columns WHERE srs_id = ?1",
+    );
+    if object_type(conn, "gpkg_tile_matrix_set")?.is_some() {
+        sql += " UNION SELECT table_name FROM gpkg_tile_matrix_set WHERE srs_id = ?1";
+    }
+    let mut stmt = conn.prepare(&sql)?;
+    let users = stmt
+        .query_map(params![srs_id], |r| r.get(0))?
+        .collect::<rusqlite::Result<Vec<String>>>()?;
+    Ok(users)
+}
+
+pub(crate) fn delete_srs(conn: &Connection, srs_id: i64) -> Result<()> {
+    if defaults::REQUIRED.contains(&srs_id) {
+        return Err(Error::RequiredSrs(srs_id));
+    }
+    check_srs_exists(conn, srs_id)?;
+    let layers = srs_users(conn, srs_id)?;
+    if !layers.is_empty() {
+        return Err(Error::SrsInUse { srs_id, layers });
+    }
+    conn.execute(
+        "DELETE FROM gpkg_spatial_ref_sys WHERE srs_id = ?1",
+        params![srs_id],
+    )?;
+    Ok(())
+}
+
+pub(crate) fn get_layer_srs_id(conn: &Connection, layer_name: &str) -> Result<Option<i64>> {
+    conn.query_row(
+        "SELECT srs_id FROM gpkg_contents WHERE table_name = ?1",
+        params![layer_name],
+        |r| r.get(0),
+    )
+    .optional()?
+    .ok_or_else(|| Error::UnknownLayer(layer_name.to_owned()))
+}
+
+/// The geometry of a record encoded with the srs_id of the layer's geometry column, since a geometry
+/// is always encoded with 4326 on its own.
+///
+/// This is None if the layer or the record has no geometry, in which case the record's parameters are used as they are.
+pub(crate) fn layer_geometry_blob<'a, T: GPKGModel<'a>>(
+    record: &T,
+    srs_id: Option<i64>,
+) -> Result<Option<Vec<u8>>> {
+    let srs_id = match srs_id {
+        Some(s) => s,
+        None => return Ok(None),
     };
-    pub const CARTESIAN: SpatialRefSys = SpatialRefSys {
-        name: "undefined cartesian SRS",
-        id: -1,
-        organization: "NONE",
-        organization_coordsys_id: -1,
-        definition: "undefined",
-        description: "undefined cartesian coordinate reference system",
+    with_geometry_blob(record, |blob| {
+        let mut blob = blob.to_vec();
+        set_blob_srs_id(&mut blob, srs_id as i32)?;
+        Ok(Some(blob))
+    })
+}
+
+/// Change the spatial reference system of a layer, along with the srs_id in the header of every geometry it contains.
+///
+/// The coordinates aren't transformed, so this is for correcting the system of a layer rather than reprojecting it.
+pub(crate) fn set_layer_srs_id(conn: &Connection, layer_name: &str, srs_id: i64) -> Result<()> {
+    get_layer_srs_id(conn, layer_name)?;
+    check_srs_exists(conn, srs_id)?;
+    conn.execute(
+        "UPDATE gpkg_contents SET srs_id = ?2 WHERE table_name = ?1",
+        params![layer_name, srs_id],
+    )?;
+    let geom = match get_geometry_column(conn, layer_name)? {
+        Some(g) => g,
+        None => return Ok(()),
     };
+    conn.execute(
+        "UPDATE gpkg_geometry_columns SET srs_id = ?2 WHERE table_name = ?1",
+        params![layer_name, srs_id],
+    )?;
+
+    let table = quote_identifier(layer_name);
+    let column = quote_identifier(&geom.name);
+    let mut select = conn.prepare(&format!(
+        "SELECT rowid, {column} FROM {table} WHERE {column} IS NOT NULL"
+    ))?;
+    let mut update = conn.prepare(&format!(
+        "UPDATE {table} SET {column} = ?2 WHERE rowid = ?1"
+    ))?;
+    // the rows are read before any of them are written, so the query doesn't see its own updates
+    let rows = select
+        .query_map([], |r| Ok((r.get::<_, i64>(0)?, r.get::<_, Vec<u8>>(1)?)))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    for (rowid, mut blob) in rows {
+        if set_blob_srs_id(&mut blob, srs_id as i32)? {
+            update.execute(params![rowid, blob])?;
+        }
+    }
+    conn.execute(sql::UPDATE_LAST_CHANGE, params![layer_name])?;
+    Ok(())
 }
@@ -0,0 +1,138 @@
+use crate::extent::{read_f64, read_u32};
+use crate::result::{Error, Result};
+use byteorder::ReadBytesExt;
+use std::io::Cursor;
+
+// the names of the WKB geometry types, indexed by their code
+const TYPE_NAMES: [&str; 15] = [
+    "GEOMETRY",
+    "POINT",
+    "LINESTRING",
+    "POLYGON",
+    "MULTIPOINT",
+    "MULTILINESTRING",
+    "MULTIPOLYGON",
+    "GEOMETRYCOLLECTION",
+    "CIRCULARSTRING",
+    "COMPOUNDCURVE",
+    "CURVEPOLYGON",
+    "MULTICURVE",
+    "MULTISURFACE",
+    "CURVE",
+    "SURFACE",
+];
+
+// the name of a geometry type and the number of values in each coordinate, from an ISO WKB type code
+fn geometry_type(code: u32) -> Result<(&'static str, &'static str, usize)> {
+    let name = TYPE_NAMES
+        .get((code % 1000) as usize)
+        .ok_or(Error::UnsupportedGeometryType)?;
+    let (suffix, dimensions) = match code / 1000 {
+        0 => ("", 2),
+        1 => ("Z", 3),
+        2 => ("M", 3),
+        3 => ("ZM", 4),
+        _ => return Err(Error::UnsupportedGeometryType),
+    };
+    Ok((name, suffix, dimensions))
+}
+
+fn read_byte_order(r: &mut Cursor<&[u8]>) -> Result<bool> {
+    match r.read_u8()? {
+        0 => Ok(false),
+        1 => Ok(true),
+        _ => Err(Error::GeomDecodeError),
+    }
+}
+
+/// The name of the type of a WKB geometry in the form used by gpkg_geometry_columns, e.g. POINTZ
+pub(crate) fn wkb_geometry_type(wkb: &[u8]) -> Result<String> {
+    let mut r = Cursor::new(wkb);
+    let little_endian = read_byte_order(&mut r)?;
+    let (name, suffix, _) = geometry_type(read_u32(&mut r, little_endian)?)?;
+    Ok(format!("{}{}", name, suffix))
+}
+
+/// Write a WKB geometry as WKT, keeping any Z and M values
+pub(crate) fn wkb_to_wkt(wkb: &[u8]) -> Result<String> {
+    let mut out = String::new();
+    write_geometry(&mut Cursor::new(wkb), &mut out, true)?;
+    Ok(out)
+}
+
+// write a geometry, with the type name if it's tagged, i.e. not a part of a multi geometry
+fn write_geometry(r: &mut Cursor<&[u8]>, out: &mut String, tagged: bool) -> Result<()> {
+    let little_endian = read_byte_order(r)?;
+    let code = read_u32(r, little_endian)?;
+    let (name, suffix, dimensions) = geometry_type(code)?;
+    if tagged {
+        out.push_str(name);
+        if !suffix.is_empty() {
+            out.push(' ');
+            out.push_str(suffix);
+        }
+        out.push(' ');
+    }
+    let read_count = |r: &mut Cursor<&[u8]>| read_u32(r, little_endian);
+    let write_points = |r: &mut Cursor<&[u8]>, out: &mut String, count: u32| -> Result<()> {
+        out.push('(');
+        for i in 0..count {
+            if i > 0 {
+                out.push_str(", ");
+            }
+            for d in 0..dimensions {
+                if d > 0 {
+                    out.push(' ');
+                }
+                out.push_str(&read_f64(r, little_endian)?.to_string());
+            }
+        }
+        out.push(')');
+        Ok(())
+    };
+    match code % 1000 {
+        1 => {
+            let start = out.len();
+            write_points(r, out, 1)?;
+            // an empty point is written with NaN coordinates
+            if out[start..].contains("NaN") {
+                out.truncate(start);
+                out.push_str("EMPTY");
+            }
+        }
+        2 => match read_count(r)? {
+            0 => out.push_str("EMPTY"),
+            count => write_points(r, out, count)?,
+        },
+        3 => match read_count(r)? {
+            0 => out.push_str("EMPTY"),
+            rings => {
+                out.push('(');
+                for i in 0..rings {
+                    if i > 0 {
+                        out.push_str(", ");
+                    }
+                    let count = read_count(r)?;
+                    write_points(r, out, count)?;
+                }
+                out.push(')');
+            }
+        },
+        4..=7 => match read_count(r)? {
+            0 => out.push_str("EMPTY"),
+            parts => {
+                out.push('(');
+                for i in 0..parts {
+                    if i > 0 {
+                        out.push_str(", ");
+                    }
+                    // only the parts of a geometry collection have their own types
+                    write_geometry(r, out, code % 1000 == 7)?;
+                }
+                out.push(')');
+            }
+        },
+        _ => return Err(Error::UnsupportedGeometryType),
+    }
+    Ok(())
+}